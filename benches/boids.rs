@@ -0,0 +1,300 @@
+// the simulation lives in the binary, so the benchmarks mount its ecs module directly
+#[allow(dead_code)]
+// its unit tests are compiled along with it under clippy --all-targets, but without a test
+// harness nothing uses what they import
+#[cfg_attr(test, allow(unused_imports))]
+#[path = "../src/ecs/mod.rs"]
+mod ecs;
+
+use bevy::{
+    ecs::schedule::{Schedule, SystemStage},
+    prelude::*,
+    sprite::Rect,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+use bevy_boids::{util, QuadtreeValue, QueryScratch};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use ecs::components::{Boid, Collider, Kinematics, Leader, QuadtreeRect};
+use ecs::resources::{
+    BehaviorMask, BoidParams, EntityQuadtree, EntityWrapper, NeighborStrategy, ObstacleQuadtree,
+    PerfBudget, PhysicsConfig, SettlePhase, SpawnConfig, WorldBounds,
+};
+use ecs::setup::{BOID_SCALE, BOID_SPEED};
+use ecs::systems::{
+    apply_kinematics, avoid_obstacles, avoid_screen_edges, flocking, follow_leaders,
+    normalize_flock_speed, sanitize_kinematics, update_quadtree, wander_leaders,
+};
+
+#[derive(StageLabel)]
+struct PhysicsStage;
+
+const FLOCK_SIZES: [usize; 3] = [500, 2500, 10000];
+const WORLD: Rect = Rect {
+    min: Vec2::new(-960., -540.),
+    max: Vec2::new(960., 540.),
+};
+// half-width of the square used by the query benchmarks
+const QUERY_RADIUS: f32 = 50.;
+const LEADER_COUNT: usize = 5;
+// a few ticks of movement at BOID_SPEED
+const REINDEX_THRESHOLD: f32 = 5.;
+// fixed seed so every run benchmarks the same flock
+const SEED: u64 = 0xB01D5;
+
+/* Benchmarks */
+
+fn bench_quadtree_add(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree_add");
+    for size in FLOCK_SIZES {
+        let values = random_values(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &values, |b, values| {
+            b.iter(|| {
+                let mut quadtree = EntityQuadtree::empty(WORLD);
+                quadtree.extend(values.iter().cloned());
+                black_box(quadtree)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_quadtree_query_rect(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree_query_rect");
+    for size in FLOCK_SIZES {
+        let values = random_values(size);
+        let quadtree = build_quadtree(&values);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &values, |b, values| {
+            b.iter(|| {
+                for value in values {
+                    let center = value.get_center();
+                    let rect = Rect {
+                        min: center - QUERY_RADIUS,
+                        max: center + QUERY_RADIUS,
+                    };
+                    black_box(quadtree.query_rect(&rect));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_quadtree_query_radius(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree_query_radius");
+    for size in FLOCK_SIZES {
+        let values = random_values(size);
+        let quadtree = build_quadtree(&values);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &values, |b, values| {
+            b.iter(|| {
+                for value in values {
+                    black_box(quadtree.query_radius(value.get_center(), QUERY_RADIUS));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// the same intersecting-values query per boid, collected into a fresh Vec each time versus
+// into one buffer reused from a QueryScratch
+fn bench_quadtree_query_scratch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree_query_scratch");
+    let size = FLOCK_SIZES[FLOCK_SIZES.len() - 1];
+    let values = random_values(size);
+    let quadtree = build_quadtree(&values);
+    let query_rects: Vec<Rect> = values
+        .iter()
+        .map(|value| Rect {
+            min: value.get_center() - QUERY_RADIUS,
+            max: value.get_center() + QUERY_RADIUS,
+        })
+        .collect();
+    group.bench_function("allocating", |b| {
+        b.iter(|| {
+            for rect in &query_rects {
+                black_box(quadtree.values_intersecting_where(rect, |_| true));
+            }
+        })
+    });
+    group.bench_function("scratch", |b| {
+        let mut scratch = QueryScratch::new();
+        b.iter(|| {
+            let mut results = scratch.take();
+            for rect in &query_rects {
+                quadtree.values_intersecting_into(rect, |_| true, &mut results);
+                black_box(&results);
+            }
+            scratch.restore(results);
+        })
+    });
+    group.finish();
+}
+
+// one fixed-timestep tick of every physics system that doesn't need a window
+fn bench_physics_tick(c: &mut Criterion) {
+    ComputeTaskPool::init(TaskPool::default);
+    let mut group = c.benchmark_group("physics_tick");
+    group.sample_size(20);
+    for size in FLOCK_SIZES {
+        let mut world = build_world(size);
+        let mut schedule = physics_schedule();
+        // let the quadtree settle so every sample starts from an up-to-date tree
+        schedule.run(&mut world);
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| schedule.run(&mut world))
+        });
+    }
+    group.finish();
+}
+
+// the largest flock only, where boids share quadtree nodes the most
+fn bench_physics_tick_node_cache(c: &mut Criterion) {
+    ComputeTaskPool::init(TaskPool::default);
+    let mut group = c.benchmark_group("physics_tick_node_cache");
+    group.sample_size(20);
+    let size = FLOCK_SIZES[FLOCK_SIZES.len() - 1];
+    let mut world = build_world(size);
+    world.insert_resource(NeighborStrategy::NodeCache);
+    let mut schedule = physics_schedule();
+    schedule.run(&mut world);
+    group.bench_function(BenchmarkId::from_parameter(size), |b| {
+        b.iter(|| schedule.run(&mut world))
+    });
+    group.finish();
+}
+
+// boids move about BOID_SPEED / 60 per tick, so most of them skip reindexing on any given tick
+fn bench_physics_tick_reindex_threshold(c: &mut Criterion) {
+    ComputeTaskPool::init(TaskPool::default);
+    let mut group = c.benchmark_group("physics_tick_reindex_threshold");
+    group.sample_size(20);
+    let size = FLOCK_SIZES[FLOCK_SIZES.len() - 1];
+    let mut world = build_world(size);
+    world.insert_resource(PhysicsConfig {
+        reindex_threshold: REINDEX_THRESHOLD,
+        ..default()
+    });
+    let mut schedule = physics_schedule();
+    schedule.run(&mut world);
+    group.bench_function(BenchmarkId::from_parameter(size), |b| {
+        b.iter(|| schedule.run(&mut world))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_quadtree_add,
+    bench_quadtree_query_rect,
+    bench_quadtree_query_radius,
+    bench_quadtree_query_scratch,
+    bench_physics_tick,
+    bench_physics_tick_node_cache,
+    bench_physics_tick_reindex_threshold
+);
+criterion_main!(benches);
+
+/* Internal-only Functions */
+
+fn random_values(count: usize) -> Vec<EntityWrapper> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    (0..count)
+        .map(|i| {
+            let translation = random_translation(&mut rng);
+            EntityWrapper {
+                entity: Entity::from_raw(i as u32),
+                rect: Rect {
+                    min: translation,
+                    max: translation + BOID_SCALE,
+                },
+                velocity: random_velocity(&mut rng),
+            }
+        })
+        .collect()
+}
+
+fn build_quadtree(values: &[EntityWrapper]) -> EntityQuadtree {
+    let mut quadtree = EntityQuadtree::empty(WORLD);
+    quadtree.extend(values.iter().cloned());
+    quadtree
+}
+
+fn build_world(count: usize) -> World {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut world = World::new();
+    let spawn_config = SpawnConfig::default();
+    let mut quadtree = EntityQuadtree::empty(WORLD);
+    for i in 0..count {
+        let transform = Transform {
+            translation: random_translation(&mut rng).extend(0.),
+            scale: BOID_SCALE.extend(1.),
+            ..default()
+        };
+        let velocity = random_velocity(&mut rng);
+        let mut boid = world.spawn();
+        boid.insert(Boid)
+            .insert(Collider)
+            .insert(Kinematics {
+                velocity,
+                acceleration: Vec3::ZERO,
+                preferred_speed: BOID_SPEED,
+            })
+            .insert(transform);
+        if i < LEADER_COUNT {
+            boid.insert(Leader);
+        }
+        let value = EntityWrapper::new(boid.id(), &velocity, &transform, spawn_config.alignment);
+        boid.insert(QuadtreeRect(value.rect));
+        quadtree.add(value);
+    }
+    world.insert_resource(quadtree);
+    world.insert_resource(ObstacleQuadtree::empty(WORLD));
+    world.insert_resource(WorldBounds::new(WORLD));
+    world.insert_resource(BoidParams::default());
+    world.insert_resource(PhysicsConfig::default());
+    world.insert_resource(NeighborStrategy::default());
+    world.insert_resource(BehaviorMask::default());
+    world.insert_resource(SettlePhase::default());
+    // fixed radii so every sample does the same work
+    world.insert_resource(PerfBudget {
+        budget_ms: f32::INFINITY,
+        ..default()
+    });
+    world.insert_resource(spawn_config);
+    world
+}
+
+// same order as physics_system_set, minus the systems that read Windows or send events
+fn physics_schedule() -> Schedule {
+    let mut schedule = Schedule::default();
+    schedule.add_stage(
+        PhysicsStage,
+        SystemStage::parallel()
+            .with_system(flocking)
+            .with_system(avoid_obstacles.after(flocking))
+            .with_system(follow_leaders.after(avoid_obstacles))
+            .with_system(wander_leaders.after(follow_leaders))
+            .with_system(avoid_screen_edges.after(wander_leaders))
+            .with_system(normalize_flock_speed.after(avoid_screen_edges))
+            .with_system(sanitize_kinematics.after(normalize_flock_speed))
+            .with_system(apply_kinematics.after(sanitize_kinematics))
+            .with_system(update_quadtree.after(apply_kinematics)),
+    );
+    schedule
+}
+
+fn random_translation(rng: &mut StdRng) -> Vec2 {
+    Vec2::new(
+        rng.gen_range(WORLD.min.x..WORLD.max.x - BOID_SCALE.x),
+        rng.gen_range(WORLD.min.y..WORLD.max.y - BOID_SCALE.y),
+    )
+}
+
+fn random_velocity(rng: &mut StdRng) -> Vec3 {
+    Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))
+        .normalize_or_zero()
+        .extend(0.)
+        * BOID_SPEED
+}
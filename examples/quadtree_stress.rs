@@ -0,0 +1,177 @@
+// exercises the quadtree on its own, without any ecs components, and reports how long each
+// operation takes; run with `cargo run --release --example quadtree_stress -- [count]`
+use std::{
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use bevy::{prelude::Vec2, sprite::Rect};
+use bevy_boids::{Quadtree, QuadtreeStats, QuadtreeValue};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const DEFAULT_COUNT: usize = 1_000_000;
+const WORLD: Rect = Rect {
+    min: Vec2::new(-10_000., -10_000.),
+    max: Vec2::new(10_000., 10_000.),
+};
+const MIN_VALUE_SIZE: f32 = 1.;
+const MAX_VALUE_SIZE: f32 = 8.;
+const QUERY_COUNT: usize = 100_000;
+// half-width of the rects and radius of the circles queried
+const QUERY_RADIUS: f32 = 50.;
+// deletes search the tree node by node, so only a sample is removed and re-added each cycle
+const DELETE_COUNT: usize = 10_000;
+const CYCLES: usize = 3;
+// fixed seed so every run builds the same tree
+const SEED: u64 = 0x0DD5;
+
+#[derive(Clone)]
+struct SimpleValue {
+    id: usize,
+    rect: Rect,
+}
+
+// Rect is neither Eq nor Hash, so identity comes from the id
+impl PartialEq for SimpleValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for SimpleValue {}
+
+impl Hash for SimpleValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl QuadtreeValue for SimpleValue {
+    fn get_rect(&self) -> &Rect {
+        &self.rect
+    }
+}
+
+fn main() {
+    let count = std::env::args()
+        .nth(1)
+        .map(|arg| arg.parse().expect("count must be a positive integer"))
+        .unwrap_or(DEFAULT_COUNT);
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let values: Vec<SimpleValue> = (0..count)
+        .map(|id| SimpleValue {
+            id,
+            rect: random_rect(&mut rng),
+        })
+        .collect();
+
+    let mut quadtree = Quadtree::empty(WORLD);
+    let elapsed = time(|| quadtree.extend(values.iter().cloned()));
+    report("add", count, elapsed);
+    assert_eq!(quadtree.len(), count);
+    assert_all_queryable(&quadtree, &values);
+
+    for cycle in 0..CYCLES {
+        println!("cycle {}", cycle + 1);
+        let rects: Vec<Rect> = (0..QUERY_COUNT)
+            .map(|_| query_rect_around(random_point(&mut rng)))
+            .collect();
+        let mut found = 0;
+        let elapsed = time(|| {
+            for rect in &rects {
+                found += quadtree.values_intersecting(rect).len();
+            }
+        });
+        report("query_rect", QUERY_COUNT, elapsed);
+
+        let centers: Vec<Vec2> = (0..QUERY_COUNT).map(|_| random_point(&mut rng)).collect();
+        let elapsed = time(|| {
+            for center in &centers {
+                found += quadtree.query_radius(*center, QUERY_RADIUS).len();
+            }
+        });
+        report("query_radius", QUERY_COUNT, elapsed);
+        println!("  {} values found by queries", found);
+
+        let sample: Vec<SimpleValue> = (0..DELETE_COUNT.min(count))
+            .map(|_| values[rng.gen_range(0..count)].clone())
+            .collect();
+        let mut deleted = vec![];
+        let elapsed = time(|| {
+            for value in &sample {
+                deleted.extend(quadtree.delete(value));
+            }
+        });
+        report("delete", sample.len(), elapsed);
+        let elapsed = time(|| quadtree.extend(deleted));
+        report("re-add", sample.len(), elapsed);
+        assert_eq!(quadtree.len(), count);
+    }
+
+    assert_all_queryable(&quadtree, &values);
+    let stats = QuadtreeStats::calculate(&quadtree);
+    stats.print();
+    stats.print_histograms();
+}
+
+/* Internal-only Functions */
+
+// every value must be found by a query over its own rect
+fn assert_all_queryable(quadtree: &Quadtree<SimpleValue>, values: &[SimpleValue]) {
+    for value in values {
+        assert!(
+            quadtree
+                .values_intersecting_where(&value.rect, |other| other == value)
+                .len()
+                == 1,
+            "value {} is missing from the quadtree",
+            value.id
+        );
+    }
+}
+
+fn time(f: impl FnOnce()) -> Duration {
+    let started = Instant::now();
+    f();
+    started.elapsed()
+}
+
+fn report(operation: &str, count: usize, elapsed: Duration) {
+    println!(
+        "  {:<12} {:>9} ops in {:>10.2?} ({:.0} ns/op)",
+        operation,
+        count,
+        elapsed,
+        elapsed.as_nanos() as f64 / count.max(1) as f64
+    );
+}
+
+fn random_rect(rng: &mut StdRng) -> Rect {
+    let size = Vec2::new(
+        rng.gen_range(MIN_VALUE_SIZE..MAX_VALUE_SIZE),
+        rng.gen_range(MIN_VALUE_SIZE..MAX_VALUE_SIZE),
+    );
+    // keep the whole rect strictly inside the world so add never skips it
+    let min = Vec2::new(
+        rng.gen_range(WORLD.min.x + 1.0..WORLD.max.x - size.x - 1.),
+        rng.gen_range(WORLD.min.y + 1.0..WORLD.max.y - size.y - 1.),
+    );
+    Rect {
+        min,
+        max: min + size,
+    }
+}
+
+fn random_point(rng: &mut StdRng) -> Vec2 {
+    Vec2::new(
+        rng.gen_range(WORLD.min.x..WORLD.max.x),
+        rng.gen_range(WORLD.min.y..WORLD.max.y),
+    )
+}
+
+fn query_rect_around(center: Vec2) -> Rect {
+    Rect {
+        min: center - Vec2::splat(QUERY_RADIUS),
+        max: center + Vec2::splat(QUERY_RADIUS),
+    }
+}
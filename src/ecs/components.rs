@@ -1,31 +1,259 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, sprite::Rect};
+
+use super::resources::Integrator;
 
 #[derive(Component)]
 pub struct Boid;
 
+// followers within range steer toward the nearest leader, leaders wander
+#[derive(Component)]
+pub struct Leader;
+
+// index of the flock a boid was spawned into, picks its color from SpawnConfig::species_colors
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Species(pub usize);
+
+// cluster a boid was last found in by assign_cluster_ids, the index of the cluster's lowest entity
+// so the id survives regrouping for as long as that boid stays in it
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClusterId(pub u32);
+
+// altitude band of a boid; boids only flock with others in the same band, and each band is drawn
+// at its own z
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Layer(pub u8);
+
+// pinned in place: skipped by kinematics and steering, but still a neighbor to other boids
+#[derive(Component)]
+pub struct Frozen;
+
+// set on boids fleeing the cursor and spread to their neighbors, an alarmed boid flocks tighter
+// and faster until ttl (seconds) runs out
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Alarmed {
+    pub ttl: f32,
+}
+
 #[derive(Component, Clone)]
 pub struct Kinematics {
     pub velocity: Vec3,
     pub acceleration: Vec3,
+    // cruising speed ease_to_preferred_speed pulls the boid back to after bounces and scares
+    pub preferred_speed: f32,
 }
 
 impl Kinematics {
+    pub fn is_finite(&self) -> bool {
+        self.velocity.is_finite() && self.acceleration.is_finite()
+    }
+
     pub fn integrate(&self, t: f32) -> Vec3 {
         self.velocity * t + self.acceleration * t * t / 2.
     }
 
+    // displacement over one physics step, anything predicting movement should use this
+    pub fn step(&self, delta_time: f32, integrator: Integrator) -> Vec3 {
+        match integrator {
+            Integrator::Euler => self.integrate_euler(delta_time),
+            Integrator::RK4 => self.integrate_rk4(delta_time),
+            Integrator::Verlet => self.integrate_verlet(delta_time),
+        }
+    }
+
+    // one of substeps equal slices of step, so substepping changes where collisions are checked
+    // but not where the boid ends up
+    pub fn substep(&self, delta_time: f32, substeps: usize, integrator: Integrator) -> Vec3 {
+        self.step(delta_time, integrator) / substeps.max(1) as f32
+    }
+
+    // ignores acceleration within the step
+    pub fn integrate_euler(&self, h: f32) -> Vec3 {
+        self.velocity * h
+    }
+
+    // slopes are the velocity at the start, middle and end of the step, which is exact for a
+    // constant acceleration
     pub fn integrate_rk4(&self, h: f32) -> Vec3 {
-        let v0 = self.velocity;
-        let k1 = self.integrate(0.) + v0;
-        let k2 = self.integrate(h / 2.) + (v0 + k1 / 2.);
-        let k3 = self.integrate(h / 2.) + (v0 + k2 / 2.);
-        let k4 = self.integrate(h) + (v0 + k3);
+        let velocity_at = |t: f32| self.velocity + self.acceleration * t;
+        let k1 = velocity_at(0.);
+        let k2 = velocity_at(h / 2.);
+        let k3 = velocity_at(h / 2.);
+        let k4 = velocity_at(h);
         h * (k1 + (2. * k2) + (2. * k3) + k4) / 6.
     }
+
+    // position half of velocity Verlet, the velocity half is verlet_velocity
+    pub fn integrate_verlet(&self, h: f32) -> Vec3 {
+        self.integrate(h)
+    }
+
+    // velocity after a Verlet step, averaging the acceleration the step started and ended with
+    pub fn verlet_velocity(&self, previous_acceleration: Vec3, h: f32) -> Vec3 {
+        self.velocity + (previous_acceleration + self.acceleration) * h / 2.
+    }
 }
 
+// acceleration at the end of the last physics step, only read by Integrator::Verlet
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct PreviousAcceleration(pub Vec3);
+
+// velocity at the start of the physics tick, smooth_velocity blends toward the steered velocity
+// from here
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PreviousVelocity(pub Vec3);
+
 #[derive(Component)]
 pub struct Collider;
 
+// where a boid was when it last moved more than BoidParams::stall_distance, and the physics ticks
+// it has stayed within that distance of it since; two numbers instead of a position history
+#[derive(Component, Clone, Copy, Debug)]
+pub struct StallWatch {
+    pub anchor: Vec2,
+    pub ticks: u32,
+}
+
+impl StallWatch {
+    pub fn new(position: Vec2) -> Self {
+        StallWatch {
+            anchor: position,
+            ticks: 0,
+        }
+    }
+
+    // record one tick at position, true once the boid has stayed within distance of the anchor
+    // for window ticks; the count restarts after reporting so a stuck boid is reported once per
+    // window
+    pub fn observe(&mut self, position: Vec2, distance: f32, window: u32) -> bool {
+        if position.distance_squared(self.anchor) > distance * distance {
+            *self = StallWatch::new(position);
+            return false;
+        }
+        self.ticks += 1;
+        if self.ticks < window {
+            return false;
+        }
+        self.ticks = 0;
+        true
+    }
+}
+
+// drives the scale-in/scale-out transition of a boid, t goes from 0 to 1
+#[derive(Component)]
+pub struct SpawnAnim {
+    pub t: f32,
+    pub despawning: bool,
+}
+
+impl SpawnAnim {
+    pub fn spawning() -> Self {
+        SpawnAnim {
+            t: 0.,
+            despawning: false,
+        }
+    }
+
+    // insert on a boid to shrink it away and then despawn it
+    pub fn despawning() -> Self {
+        SpawnAnim {
+            t: 0.,
+            despawning: true,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct CollisionEvent;
+
+// sent when a boid's rect is no longer inside WorldBounds
+pub struct BoidEscaped {
+    pub entity: Entity,
+    pub position: Vec2,
+}
+
+// sent by detect_stalls when a boid has barely moved for BoidParams::stall_ticks
+pub struct BoidStalled {
+    pub entity: Entity,
+}
+
+// one-shot push added to every boid's velocity by apply_impulse
+pub struct Impulse {
+    pub velocity: Vec2,
+}
+
+// sent when a boid isn't spawned at position because the flock is already at MaxBoids
+pub struct BoidSpawnRejected {
+    pub position: Vec2,
+}
+
+// debug sprite covering the quadtree node under the cursor
+#[derive(Component)]
+pub struct NodeHighlight;
+
+// debug sprite drawn along a boid's velocity
+#[derive(Component)]
+pub struct VelocityLine {
+    pub boid: Entity,
+}
+
+// copy of a boid drawn across the seam while it is near a wrapping edge, direction is the sign of
+// the offset on each axis
+#[derive(Component)]
+pub struct WrapGhost {
+    pub boid: Entity,
+    pub direction: IVec2,
+}
+
+// debug sprite drawn around the selected boid
+#[derive(Component)]
+pub struct SelectionMarker;
+
+// text of the F3 debug HUD
+#[derive(Component)]
+pub struct HudText;
+
+// text of the selected boid's inspection panel
+#[derive(Component)]
+pub struct InspectionText;
+
+// rect a boid was last stored in the quadtree with, lets update_quadtree find it without a full search
+#[derive(Component)]
+pub struct QuadtreeRect(pub Rect);
+
+// pulls boids within radius toward position with an inverse-square acceleration
+#[derive(Component, Clone, Debug)]
+pub struct Attractor {
+    pub position: Vec2,
+    pub strength: f32,
+    pub radius: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rk4_is_exact_for_a_constant_acceleration() {
+        let kinematics = Kinematics {
+            velocity: Vec3::new(30., -20., 0.),
+            acceleration: Vec3::new(-400., 250., 0.),
+            preferred_speed: 0.,
+        };
+        for h in [1. / 60., 0.5, 2.] {
+            let analytic = kinematics.velocity * h + kinematics.acceleration * h * h / 2.;
+            let step = kinematics.step(h, Integrator::RK4);
+            assert!(step.abs_diff_eq(analytic, 1e-4 * analytic.length()));
+            assert!(kinematics
+                .step(h, Integrator::Verlet)
+                .abs_diff_eq(analytic, 1e-4 * analytic.length()));
+            // Euler leaves the acceleration out
+            assert_eq!(
+                kinematics.step(h, Integrator::Euler),
+                kinematics.velocity * h
+            );
+            // with the acceleration unchanged over the step, Verlet's velocity is exact too
+            let velocity = kinematics.verlet_velocity(kinematics.acceleration, h);
+            assert!(velocity.abs_diff_eq(kinematics.velocity + kinematics.acceleration * h, 1e-4));
+        }
+    }
+}
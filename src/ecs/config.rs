@@ -0,0 +1,349 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use super::resources::{
+    AttractorConfigs, BoidParams, PhysicsConfig, QuadtreeFit, SpawnConfig, WindowConfig,
+};
+use super::setup::MAX_LAYERS;
+
+pub const CONFIG_PATH: &str = "boids.toml";
+
+/*
+    Layout of boids.toml, every section and field is optional:
+
+    [boids]
+    min_speed = 10.0
+    separation_falloff = "Exponential"
+
+    [spawn]
+    initial_speed = 100.0
+    species_count = 2
+    settle_ticks = 60
+    species_colors = ["7fffd4", "ff4500"]
+
+    [physics]
+    frame_rate = 60.0
+    substeps = 4
+    integrator = "Verlet"
+
+    [window]
+    resolution = [1280.0, 720.0]
+    mode = "BorderlessFullscreen"
+    vsync = false
+
+    [quadtree_fit]
+    enabled = true
+    interval = 1.0
+    padding = 200.0
+
+    [[attractors]]
+    position = [0.0, 300.0]
+    strength = 100000.0
+    radius = 200.0
+*/
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct BoidsConfig {
+    pub boids: BoidParams,
+    pub spawn: SpawnConfig,
+    pub physics: PhysicsConfig,
+    pub window: WindowConfig,
+    pub quadtree_fit: QuadtreeFit,
+    pub attractors: AttractorConfigs,
+}
+
+// read config from disk, falling back to defaults if the file is missing or malformed
+pub fn load_config(path: &str) -> BoidsConfig {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return BoidsConfig::default(),
+    };
+    match parse_config(&contents) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Ignoring malformed {}: {}", path, err);
+            BoidsConfig::default()
+        }
+    }
+}
+
+// parse and validate, out-of-range fields are reset to their defaults and reported
+pub fn parse_config(contents: &str) -> Result<BoidsConfig, toml::de::Error> {
+    let mut config: BoidsConfig = toml::from_str(contents)?;
+    for field in validate_config(&mut config) {
+        eprintln!("Rejected config field {}, using default", field);
+    }
+    Ok(config)
+}
+
+// returns the names of rejected fields
+pub fn validate_config(config: &mut BoidsConfig) -> Vec<&'static str> {
+    let mut rejected = vec![];
+    let default_boids = BoidParams::default();
+    let default_spawn = SpawnConfig::default();
+    let default_physics = PhysicsConfig::default();
+    if !is_non_negative(config.boids.min_speed) {
+        config.boids.min_speed = default_boids.min_speed;
+        rejected.push("boids.min_speed");
+    }
+    if !is_positive(config.boids.max_acceleration) {
+        config.boids.max_acceleration = default_boids.max_acceleration;
+        rejected.push("boids.max_acceleration");
+    }
+    if !is_unit_interval(config.boids.speed_return_rate) {
+        config.boids.speed_return_rate = default_boids.speed_return_rate;
+        rejected.push("boids.speed_return_rate");
+    }
+    if !is_unit_interval(config.boids.separation_strength) {
+        config.boids.separation_strength = default_boids.separation_strength;
+        rejected.push("boids.separation_strength");
+    }
+    if !is_unit_interval(config.boids.alignment_strength) {
+        config.boids.alignment_strength = default_boids.alignment_strength;
+        rejected.push("boids.alignment_strength");
+    }
+    if !is_non_negative(config.boids.separation_closing_gain) {
+        config.boids.separation_closing_gain = default_boids.separation_closing_gain;
+        rejected.push("boids.separation_closing_gain");
+    }
+    // 1 would keep the previous velocity forever
+    if !(0. ..1.).contains(&config.boids.smoothing) {
+        config.boids.smoothing = default_boids.smoothing;
+        rejected.push("boids.smoothing");
+    }
+    if !is_positive(config.boids.scare_radius) {
+        config.boids.scare_radius = default_boids.scare_radius;
+        rejected.push("boids.scare_radius");
+    }
+    if !is_unit_interval(config.boids.scare_strength) {
+        config.boids.scare_strength = default_boids.scare_strength;
+        rejected.push("boids.scare_strength");
+    }
+    if !is_non_negative(config.boids.alarm_ttl) {
+        config.boids.alarm_ttl = default_boids.alarm_ttl;
+        rejected.push("boids.alarm_ttl");
+    }
+    if !is_positive(config.boids.alarm_radius) {
+        config.boids.alarm_radius = default_boids.alarm_radius;
+        rejected.push("boids.alarm_radius");
+    }
+    if !is_unit_interval(config.boids.alarm_falloff) {
+        config.boids.alarm_falloff = default_boids.alarm_falloff;
+        rejected.push("boids.alarm_falloff");
+    }
+    if !is_non_negative(config.boids.alarm_cohesion_boost) {
+        config.boids.alarm_cohesion_boost = default_boids.alarm_cohesion_boost;
+        rejected.push("boids.alarm_cohesion_boost");
+    }
+    if !is_non_negative(config.boids.alarm_speed) {
+        config.boids.alarm_speed = default_boids.alarm_speed;
+        rejected.push("boids.alarm_speed");
+    }
+    if !is_positive(config.boids.leader_radius) {
+        config.boids.leader_radius = default_boids.leader_radius;
+        rejected.push("boids.leader_radius");
+    }
+    if !is_unit_interval(config.boids.leader_weight) {
+        config.boids.leader_weight = default_boids.leader_weight;
+        rejected.push("boids.leader_weight");
+    }
+    if !is_positive(config.boids.separation_radius) {
+        config.boids.separation_radius = default_boids.separation_radius;
+        rejected.push("boids.separation_radius");
+    }
+    if !is_positive(config.boids.alignment_radius) {
+        config.boids.alignment_radius = default_boids.alignment_radius;
+        rejected.push("boids.alignment_radius");
+    }
+    if !is_positive(config.boids.cohesion_radius) {
+        config.boids.cohesion_radius = default_boids.cohesion_radius;
+        rejected.push("boids.cohesion_radius");
+    }
+    if !is_non_negative(config.boids.cohesion_floor) {
+        config.boids.cohesion_floor = default_boids.cohesion_floor;
+        rejected.push("boids.cohesion_floor");
+    }
+    if !(-1. ..=1.).contains(&config.boids.heading_similarity_cutoff) {
+        config.boids.heading_similarity_cutoff = default_boids.heading_similarity_cutoff;
+        rejected.push("boids.heading_similarity_cutoff");
+    }
+    if !is_non_negative(config.boids.bounce_restitution) {
+        config.boids.bounce_restitution = default_boids.bounce_restitution;
+        rejected.push("boids.bounce_restitution");
+    }
+    if !is_non_negative(config.boids.wrap_ghost_margin) {
+        config.boids.wrap_ghost_margin = default_boids.wrap_ghost_margin;
+        rejected.push("boids.wrap_ghost_margin");
+    }
+    if !is_unit_interval(config.boids.open_space_weight) {
+        config.boids.open_space_weight = default_boids.open_space_weight;
+        rejected.push("boids.open_space_weight");
+    }
+    if !is_positive(config.boids.open_space_distance) {
+        config.boids.open_space_distance = default_boids.open_space_distance;
+        rejected.push("boids.open_space_distance");
+    }
+    if !is_positive(config.boids.flock_split_radius) {
+        config.boids.flock_split_radius = default_boids.flock_split_radius;
+        rejected.push("boids.flock_split_radius");
+    }
+    if !is_unit_interval(config.boids.flock_split_weight) {
+        config.boids.flock_split_weight = default_boids.flock_split_weight;
+        rejected.push("boids.flock_split_weight");
+    }
+    if !is_non_negative(config.boids.stall_distance) {
+        config.boids.stall_distance = default_boids.stall_distance;
+        rejected.push("boids.stall_distance");
+    }
+    if !is_non_negative(config.boids.scatter_ttl) {
+        config.boids.scatter_ttl = default_boids.scatter_ttl;
+        rejected.push("boids.scatter_ttl");
+    }
+    if !is_unit_interval(config.boids.scatter_strength) {
+        config.boids.scatter_strength = default_boids.scatter_strength;
+        rejected.push("boids.scatter_strength");
+    }
+    if !is_non_negative(config.spawn.initial_speed) {
+        config.spawn.initial_speed = default_spawn.initial_speed;
+        rejected.push("spawn.initial_speed");
+    }
+    if config.spawn.species_count == 0 {
+        config.spawn.species_count = default_spawn.species_count;
+        rejected.push("spawn.species_count");
+    }
+    if !is_non_negative(config.spawn.spawn_jitter) || !config.spawn.spawn_jitter.is_finite() {
+        config.spawn.spawn_jitter = default_spawn.spawn_jitter;
+        rejected.push("spawn.spawn_jitter");
+    }
+    if config.spawn.max_boids == 0 {
+        config.spawn.max_boids = default_spawn.max_boids;
+        rejected.push("spawn.max_boids");
+    }
+    if !(1..=MAX_LAYERS).contains(&config.spawn.layer_count) {
+        config.spawn.layer_count = default_spawn.layer_count;
+        rejected.push("spawn.layer_count");
+    }
+    if !config.spawn.z.is_finite() {
+        config.spawn.z = default_spawn.z;
+        rejected.push("spawn.z");
+    }
+    if !is_positive(config.physics.frame_rate as f32) {
+        config.physics.frame_rate = default_physics.frame_rate;
+        rejected.push("physics.frame_rate");
+    }
+    if config.physics.substeps == 0 {
+        config.physics.substeps = default_physics.substeps;
+        rejected.push("physics.substeps");
+    }
+    if !is_non_negative(config.physics.reindex_threshold)
+        || !config.physics.reindex_threshold.is_finite()
+    {
+        config.physics.reindex_threshold = default_physics.reindex_threshold;
+        rejected.push("physics.reindex_threshold");
+    }
+    if !config
+        .window
+        .resolution
+        .iter()
+        .all(|&length| is_positive(length) && length.is_finite())
+    {
+        config.window.resolution = WindowConfig::default().resolution;
+        rejected.push("window.resolution");
+    }
+    let default_fit = QuadtreeFit::default();
+    if !is_positive(config.quadtree_fit.interval) {
+        config.quadtree_fit.interval = default_fit.interval;
+        rejected.push("quadtree_fit.interval");
+    }
+    if !is_non_negative(config.quadtree_fit.padding) {
+        config.quadtree_fit.padding = default_fit.padding;
+        rejected.push("quadtree_fit.padding");
+    }
+    let attractor_count = config.attractors.0.len();
+    config.attractors.0.retain(|attractor| {
+        attractor.position.iter().all(|c| c.is_finite())
+            && attractor.strength.is_finite()
+            && is_positive(attractor.radius)
+    });
+    if config.attractors.0.len() < attractor_count {
+        rejected.push("attractors");
+    }
+    rejected
+}
+
+/* Internal-only Functions */
+
+// NaN fails all of these checks
+
+fn is_positive(value: f32) -> bool {
+    value > 0.
+}
+
+fn is_non_negative(value: f32) -> bool {
+    value >= 0.
+}
+
+fn is_unit_interval(value: f32) -> bool {
+    (0. ..=1.).contains(&value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::resources::{Integrator, SeparationFalloff};
+
+    const SAMPLE: &str = r#"
+        [boids]
+        min_speed = 12.5
+        separation_radius = -4.0
+        separation_falloff = "Linear"
+
+        [spawn]
+        initial_speed = 80.0
+        species_count = 3
+
+        [physics]
+        frame_rate = 30.0
+        substeps = 2
+        integrator = "Verlet"
+    "#;
+
+    #[test]
+    fn sample_config_parses_into_the_resources() {
+        let config = parse_config(SAMPLE).unwrap();
+        assert_eq!(config.boids.min_speed, 12.5);
+        assert_eq!(config.boids.separation_falloff, SeparationFalloff::Linear);
+        assert_eq!(config.spawn.initial_speed, 80.);
+        assert_eq!(config.spawn.species_count, 3);
+        assert_eq!(config.physics.frame_rate, 30.);
+        assert_eq!(config.physics.substeps, 2);
+        assert_eq!(config.physics.integrator, Integrator::Verlet);
+        // fields the file leaves out keep their defaults
+        let defaults = BoidParams::default();
+        assert_eq!(config.boids.alignment_radius, defaults.alignment_radius);
+    }
+
+    #[test]
+    fn out_of_range_fields_fall_back_to_defaults() {
+        let mut config = toml::from_str::<BoidsConfig>(SAMPLE).unwrap();
+        assert_eq!(
+            validate_config(&mut config),
+            vec!["boids.separation_radius"]
+        );
+        assert_eq!(
+            config.boids.separation_radius,
+            BoidParams::default().separation_radius
+        );
+    }
+
+    #[test]
+    fn missing_or_malformed_files_load_the_defaults() {
+        let config = load_config("no/such/boids.toml");
+        assert_eq!(
+            config.spawn.initial_speed,
+            SpawnConfig::default().initial_speed
+        );
+        assert!(parse_config("[boids\nmin_speed = ").is_err());
+    }
+}
@@ -0,0 +1,732 @@
+use std::mem;
+
+use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+    utils::HashSet,
+};
+
+use crate::util::{coords::cursor_world_position, quadtree::quadtree_stats::QuadtreeStats};
+
+use super::{
+    components::{
+        Boid, BoidEscaped, BoidSpawnRejected, BoidStalled, Frozen, HudText, InspectionText,
+        Kinematics, Leader, NodeHighlight, SelectionMarker, SpawnAnim, Species, VelocityLine,
+    },
+    flock_stats::FlockStats,
+    resources::{
+        BehaviorMask, BoidParams, BoidSnapshot, EntityQuadtree, HudOverlay, NeighborStrategy,
+        PhysicsPause, QuadtreeOverlay, ReplayBuffer, SelectedBoid, VelocityOverlay,
+    },
+    setup::BOID_SCALE,
+};
+
+const NODE_HIGHLIGHT_COLOR: Color = Color::rgba(1., 1., 1., 0.1);
+const NODE_HIGHLIGHT_Z: f32 = -1.;
+const VELOCITY_LINE_COLOR: Color = Color::YELLOW;
+const VELOCITY_LINE_WIDTH: f32 = 0.5;
+const VELOCITY_LINE_Z: f32 = 1.;
+const EPS: f32 = 0.00001;
+// clicks further than this from every boid clear the selection
+const SELECT_RADIUS: f32 = 10.;
+const SELECTION_COLOR: Color = Color::rgba(1., 1., 1., 0.5);
+const SELECTION_SCALE: f32 = 3.;
+const SELECTION_Z: f32 = -0.5;
+// relative to the assets folder
+const HUD_FONT: &str = "fonts/DejaVuSansMono.ttf";
+const HUD_FONT_SIZE: f32 = 16.;
+const HUD_COLOR: Color = Color::WHITE;
+const HUD_MARGIN: f32 = 8.;
+const BEHAVIOR_BINDINGS: [(KeyCode, BehaviorMask, &str); 5] = [
+    (KeyCode::Key1, BehaviorMask::SEPARATION, "separation"),
+    (KeyCode::Key2, BehaviorMask::ALIGNMENT, "alignment"),
+    (KeyCode::Key3, BehaviorMask::COHESION, "cohesion"),
+    (KeyCode::Key4, BehaviorMask::EDGE, "edge avoidance"),
+    (KeyCode::Key5, BehaviorMask::GOAL, "leaders and attractors"),
+];
+
+/*
+    Debugging aids, none of these affect the simulation.
+*/
+
+pub fn toggle_velocity_overlay(keys: Res<Input<KeyCode>>, mut overlay: ResMut<VelocityOverlay>) {
+    if keys.just_pressed(KeyCode::V) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+// draw a line from each boid along its velocity, lines are thin sprites kept in sync with boids
+pub fn draw_velocity_vectors(
+    mut commands: Commands,
+    overlay: Res<VelocityOverlay>,
+    boid_query: Query<(Entity, &Kinematics, &Transform), With<Boid>>,
+    mut line_query: Query<(Entity, &VelocityLine, &mut Transform, &mut Visibility), Without<Boid>>,
+) {
+    if !overlay.enabled {
+        for (line, _, _, _) in line_query.iter() {
+            commands.entity(line).despawn();
+        }
+        return;
+    }
+    let mut boids_with_lines = HashSet::new();
+    for (line, velocity_line, mut transform, mut visibility) in line_query.iter_mut() {
+        match boid_query.get(velocity_line.boid) {
+            Ok((boid, kinematics, boid_transform)) => {
+                boids_with_lines.insert(boid);
+                let line_vec = kinematics.velocity.truncate() * overlay.scale;
+                // zero-velocity boids would produce a degenerate line
+                visibility.is_visible = line_vec.length_squared() > EPS;
+                if visibility.is_visible {
+                    *transform =
+                        velocity_line_transform(boid_transform.translation.truncate(), line_vec);
+                }
+            }
+            // boid was despawned
+            Err(_) => commands.entity(line).despawn(),
+        }
+    }
+    for (boid, _, _) in boid_query.iter() {
+        if !boids_with_lines.contains(&boid) {
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: VELOCITY_LINE_COLOR,
+                        ..default()
+                    },
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(VelocityLine { boid });
+        }
+    }
+}
+
+// number keys switch individual steering behaviors on and off
+pub fn toggle_behaviors(keys: Res<Input<KeyCode>>, mut mask: ResMut<BehaviorMask>) {
+    for (key, behavior, name) in BEHAVIOR_BINDINGS {
+        if keys.just_pressed(key) {
+            mask.toggle(behavior);
+            let state = if mask.contains(behavior) { "on" } else { "off" };
+            info!("Steering behavior {} {}", name, state);
+        }
+    }
+}
+
+// boid events are rare enough to log each one
+pub fn log_boid_events(
+    mut escaped_events: EventReader<BoidEscaped>,
+    mut rejected_events: EventReader<BoidSpawnRejected>,
+    mut stalled_events: EventReader<BoidStalled>,
+) {
+    for event in escaped_events.iter() {
+        warn!(
+            "Boid {:?} escaped the world bounds at {}",
+            event.entity, event.position
+        );
+    }
+    for event in rejected_events.iter() {
+        info!(
+            "No boid spawned at {}, the flock is at MaxBoids",
+            event.position
+        );
+    }
+    for event in stalled_events.iter() {
+        info!("Boid {:?} stalled", event.entity);
+    }
+}
+
+// N switches how flocking finds neighbors, brute force is the reference the others should match
+pub fn cycle_neighbor_strategy(keys: Res<Input<KeyCode>>, mut strategy: ResMut<NeighborStrategy>) {
+    if keys.just_pressed(KeyCode::N) {
+        *strategy = strategy.next();
+        info!("Neighbor strategy {:?}", *strategy);
+    }
+}
+
+pub fn toggle_quadtree_overlay(keys: Res<Input<KeyCode>>, mut overlay: ResMut<QuadtreeOverlay>) {
+    if keys.just_pressed(KeyCode::Q) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+pub fn spawn_node_highlight(mut commands: Commands) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: NODE_HIGHLIGHT_COLOR,
+                ..default()
+            },
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(NodeHighlight);
+}
+
+// shade the deepest quadtree node under the cursor and report it whenever it changes
+pub fn highlight_node_under_cursor(
+    mut highlight_query: Query<(&mut Transform, &mut Visibility), With<NodeHighlight>>,
+    mut last_node: Local<Option<(usize, Vec2)>>,
+    overlay: Res<QuadtreeOverlay>,
+    quadtree: Res<EntityQuadtree>,
+    windows: Res<Windows>,
+    camera_query: Query<&GlobalTransform, With<Camera>>,
+) {
+    let cursor = windows
+        .get_primary()
+        .zip(camera_query.get_single().ok())
+        .and_then(|(window, camera)| cursor_world_position(camera, window));
+    let node = cursor
+        .filter(|_| overlay.enabled)
+        .and_then(|cursor| quadtree.query_point(&cursor));
+    for (mut transform, mut visibility) in highlight_query.iter_mut() {
+        visibility.is_visible = node.is_some();
+        if let Some(node) = node {
+            transform.translation = ((node.rect.min + node.rect.max) / 2.).extend(NODE_HIGHLIGHT_Z);
+            transform.scale = (node.rect.max - node.rect.min).extend(1.);
+        }
+    }
+    let node_key = node.map(|node| (node.depth, node.rect.min));
+    if node_key != *last_node {
+        if let Some(node) = node {
+            info!(
+                "Quadtree node at depth {} with {} values",
+                node.depth,
+                node.values.len()
+            );
+        }
+        *last_node = node_key;
+    }
+}
+
+// snapshot of a selected boid, shown in the inspection panel by inspect_selected_boid
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoidInspection {
+    pub entity: Entity,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub speed: f32,
+    pub neighbor_count: usize,
+    pub is_leader: bool,
+    pub species: Option<usize>,
+}
+
+// pick the boid nearest to a left click, clicking empty space clears the selection
+pub fn select_boid(
+    mouse: Res<Input<MouseButton>>,
+    mut selected: ResMut<SelectedBoid>,
+    quadtree: Res<EntityQuadtree>,
+    windows: Res<Windows>,
+    camera_query: Query<&GlobalTransform, With<Camera>>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let cursor = windows
+        .get_primary()
+        .zip(camera_query.get_single().ok())
+        .and_then(|(window, camera)| cursor_world_position(camera, window));
+    selected.0 = cursor.and_then(|cursor| {
+        quadtree
+            .neighbors_sorted(cursor, SELECT_RADIUS)
+            .first()
+            .map(|(value, _)| value.entity)
+    });
+}
+
+pub fn spawn_selection_marker(mut commands: Commands) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: SELECTION_COLOR,
+                ..default()
+            },
+            transform: Transform::from_scale((BOID_SCALE * SELECTION_SCALE).extend(1.)),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(SelectionMarker);
+}
+
+pub fn spawn_inspection_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(HUD_FONT),
+                    font_size: HUD_FONT_SIZE,
+                    color: HUD_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(HUD_MARGIN),
+                    right: Val::Px(HUD_MARGIN),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(Visibility { is_visible: false })
+        .insert(InspectionText);
+}
+
+// follow the selected boid with the marker and show its state in the inspection panel every
+// frame, the selection is dropped once the boid is despawned
+#[allow(clippy::type_complexity)]
+pub fn inspect_selected_boid(
+    mut selected: ResMut<SelectedBoid>,
+    mut marker_query: Query<(&mut Transform, &mut Visibility), With<SelectionMarker>>,
+    mut panel_query: Query<
+        (&mut Text, &mut Visibility),
+        (With<InspectionText>, Without<SelectionMarker>),
+    >,
+    boid_query: Query<
+        (&Kinematics, &Transform, Option<&Leader>, Option<&Species>),
+        (With<Boid>, Without<SelectionMarker>),
+    >,
+    quadtree: Res<EntityQuadtree>,
+    params: Res<BoidParams>,
+) {
+    let inspection = selected.0.and_then(|entity| {
+        let (kinematics, transform, leader, species) = boid_query.get(entity).ok()?;
+        Some(inspect_boid(
+            entity,
+            kinematics,
+            transform,
+            leader.is_some(),
+            species.copied(),
+            &quadtree,
+            &params,
+        ))
+    });
+    if inspection.is_none() {
+        selected.0 = None;
+    }
+    for (mut transform, mut visibility) in marker_query.iter_mut() {
+        visibility.is_visible = inspection.is_some();
+        if let Some(inspection) = &inspection {
+            transform.translation = inspection.position.extend(SELECTION_Z);
+        }
+    }
+    for (mut text, mut visibility) in panel_query.iter_mut() {
+        visibility.is_visible = inspection.is_some();
+        if let (Some(inspection), Some(section)) = (&inspection, text.sections.first_mut()) {
+            section.value = inspection_text(inspection);
+        }
+    }
+}
+
+// Delete shrinks the selected boid away, animate_spawn_transitions despawns it once it's gone
+pub fn despawn_selected_boid(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut selected: ResMut<SelectedBoid>,
+    boid_query: Query<(), With<Boid>>,
+) {
+    if !keys.just_pressed(KeyCode::Delete) {
+        return;
+    }
+    if let Some(entity) = selected.0.take() {
+        if boid_query.contains(entity) {
+            commands.entity(entity).insert(SpawnAnim::despawning());
+        }
+    }
+}
+
+// gathers what the inspection panel shows, neighbors are counted within the widest flocking radius
+pub fn inspect_boid(
+    entity: Entity,
+    kinematics: &Kinematics,
+    transform: &Transform,
+    is_leader: bool,
+    species: Option<Species>,
+    quadtree: &EntityQuadtree,
+    params: &BoidParams,
+) -> BoidInspection {
+    let position = transform.translation.truncate();
+    let velocity = kinematics.velocity.truncate();
+    let radius = params
+        .separation_radius
+        .max(params.alignment_radius)
+        .max(params.cohesion_radius);
+    let neighbor_count = quadtree
+        .query_radius(position, radius)
+        .iter()
+        .filter(|value| value.entity != entity)
+        .count();
+    BoidInspection {
+        entity,
+        position,
+        velocity,
+        speed: velocity.length(),
+        neighbor_count,
+        is_leader,
+        species: species.map(|species| species.0),
+    }
+}
+
+// one field per line, like the HUD
+pub fn inspection_text(inspection: &BoidInspection) -> String {
+    let species = match inspection.species {
+        Some(species) => species.to_string(),
+        None => "--".to_string(),
+    };
+    format!(
+        "Boid {:?}{}\nPosition: ({:.0}, {:.0})\nVelocity: ({:.1}, {:.1})\nSpeed: {:.1}\nNeighbors: {}\nSpecies: {}",
+        inspection.entity,
+        if inspection.is_leader { " (leader)" } else { "" },
+        inspection.position.x,
+        inspection.position.y,
+        inspection.velocity.x,
+        inspection.velocity.y,
+        inspection.speed,
+        inspection.neighbor_count,
+        species
+    )
+}
+
+pub fn toggle_physics_pause(keys: Res<Input<KeyCode>>, mut pause: ResMut<PhysicsPause>) {
+    if keys.just_pressed(KeyCode::Space) {
+        pause.paused = !pause.paused;
+        info!(
+            "Physics {}",
+            if pause.paused { "paused" } else { "resumed" }
+        );
+    }
+}
+
+pub fn toggle_hud(
+    keys: Res<Input<KeyCode>>,
+    mut hud: ResMut<HudOverlay>,
+    mut text_query: Query<&mut Visibility, With<HudText>>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        hud.enabled = !hud.enabled;
+        for mut visibility in text_query.iter_mut() {
+            visibility.is_visible = hud.enabled;
+        }
+    }
+}
+
+pub fn spawn_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(HUD_FONT),
+                    font_size: HUD_FONT_SIZE,
+                    color: HUD_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(HUD_MARGIN),
+                    left: Val::Px(HUD_MARGIN),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(Visibility { is_visible: false })
+        .insert(HudText);
+}
+
+// P pauses into the recorded history, left and right arrows step through it a tick at a time;
+// boids are frozen meanwhile and pick back up from the newest frame when P is pressed again
+pub fn control_replay(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut replay: ResMut<ReplayBuffer>,
+    mut boid_query: Query<(Entity, &mut Transform, &mut Kinematics), With<Boid>>,
+    unfrozen_query: Query<Entity, (With<Boid>, Without<Frozen>)>,
+) {
+    if keys.just_pressed(KeyCode::P) {
+        if replay.is_replaying() {
+            if let Some(frame) = replay.stop() {
+                apply_snapshots(frame, &mut boid_query);
+            }
+            // boids frozen before the replay started stay frozen
+            for entity in mem::take(&mut replay.frozen) {
+                if boid_query.contains(entity) {
+                    commands.entity(entity).remove::<Frozen>();
+                }
+            }
+            info!("Replay stopped, resuming simulation");
+        } else if replay.start() {
+            replay.frozen = unfrozen_query.iter().collect();
+            for &entity in &replay.frozen {
+                commands.entity(entity).insert(Frozen);
+            }
+            info!("Replaying {} recorded ticks", replay.frames.len());
+        } else {
+            info!("Nothing recorded to replay");
+        }
+        return;
+    }
+    let offset = if keys.just_pressed(KeyCode::Left) {
+        -1
+    } else if keys.just_pressed(KeyCode::Right) {
+        1
+    } else {
+        return;
+    };
+    replay.step(offset);
+    if let (Some(cursor), Some(frame)) = (replay.cursor, replay.current_frame()) {
+        apply_snapshots(frame, &mut boid_query);
+        info!("Replay tick {}/{}", cursor + 1, replay.frames.len());
+    }
+}
+
+// rewritten every frame while shown, the quadtree stats need a full traversal
+pub fn update_hud(
+    hud: Res<HudOverlay>,
+    diagnostics: Res<Diagnostics>,
+    quadtree: Res<EntityQuadtree>,
+    mask: Res<BehaviorMask>,
+    boid_query: Query<(Entity, &Transform, &Kinematics), With<Boid>>,
+    mut text_query: Query<&mut Text, With<HudText>>,
+) {
+    if !hud.enabled {
+        return;
+    }
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.average());
+    let mut samples: Vec<_> = boid_query
+        .iter()
+        .map(|(entity, transform, kinematics)| {
+            (
+                entity,
+                transform.translation.truncate(),
+                kinematics.velocity.truncate(),
+            )
+        })
+        .collect();
+    let contents = hud_text(
+        fps,
+        &FlockStats::calculate(&mut samples),
+        &QuadtreeStats::calculate(&quadtree),
+        *mask,
+    );
+    for mut text in text_query.iter_mut() {
+        if let Some(section) = text.sections.first_mut() {
+            section.value = contents.clone();
+        }
+    }
+}
+
+// one stat per line, fps is None until enough frames have been measured
+pub fn hud_text(
+    fps: Option<f64>,
+    flock: &FlockStats,
+    stats: &QuadtreeStats,
+    mask: BehaviorMask,
+) -> String {
+    let fps = match fps {
+        Some(fps) => format!("{:.0}", fps),
+        None => "--".to_string(),
+    };
+    let behaviors: Vec<&str> = BEHAVIOR_BINDINGS
+        .iter()
+        .filter(|(_, behavior, _)| mask.contains(*behavior))
+        .map(|(_, _, name)| *name)
+        .collect();
+    let behaviors = if behaviors.is_empty() {
+        "none".to_string()
+    } else {
+        behaviors.join(", ")
+    };
+    format!(
+        "FPS: {}\nBoids: {}\nFlock: speed {:.1}, polarization {:.2}, centroid ({:.0}, {:.0})\nQuadtree: {} nodes, {} values\nBehaviors: {}",
+        fps,
+        flock.num_boids,
+        flock.mean_speed,
+        flock.polarization,
+        flock.centroid.x,
+        flock.centroid.y,
+        stats.num_nodes,
+        stats.num_values,
+        behaviors
+    )
+}
+
+/* Internal-only Functions */
+
+// boids despawned since the frame was recorded are skipped
+fn apply_snapshots(
+    frame: &[BoidSnapshot],
+    boid_query: &mut Query<(Entity, &mut Transform, &mut Kinematics), With<Boid>>,
+) {
+    for snapshot in frame {
+        if let Ok((_, mut transform, mut kinematics)) = boid_query.get_mut(snapshot.entity) {
+            *transform = snapshot.transform;
+            kinematics.velocity = snapshot.velocity;
+        }
+    }
+}
+
+// unit sprite stretched along line_vec, starting at origin
+fn velocity_line_transform(origin: Vec2, line_vec: Vec2) -> Transform {
+    Transform {
+        translation: (origin + line_vec / 2.).extend(VELOCITY_LINE_Z),
+        rotation: Quat::from_rotation_z(line_vec.y.atan2(line_vec.x)),
+        scale: Vec3::new(line_vec.length(), VELOCITY_LINE_WIDTH, 1.),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::{
+        systems::record_replay_frame,
+        test_support::{run_system, spawn_test_boid, test_world},
+    };
+
+    #[test]
+    fn velocity_lines_follow_moving_boids_and_hide_for_still_ones() {
+        let mut world = test_world();
+        world.insert_resource(VelocityOverlay {
+            enabled: true,
+            scale: 0.5,
+        });
+        let moving = spawn_test_boid(&mut world, Vec2::new(10., 0.), Vec2::new(0., 40.));
+        let still = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        // the first pass spawns the lines, the second lays them out
+        run_system(&mut world, draw_velocity_vectors);
+        run_system(&mut world, draw_velocity_vectors);
+        let mut lines = world.query::<(&VelocityLine, &Transform, &Visibility)>();
+        assert_eq!(lines.iter(&world).count(), 2);
+        for (line, transform, visibility) in lines.iter(&world) {
+            if line.boid == still {
+                assert!(!visibility.is_visible);
+            } else {
+                assert_eq!(line.boid, moving);
+                assert!(visibility.is_visible);
+                // 20 long, centered halfway along the velocity
+                assert!((transform.scale.x - 20.).abs() < EPS);
+                assert!(transform
+                    .translation
+                    .truncate()
+                    .abs_diff_eq(Vec2::new(10., 10.), EPS));
+            }
+        }
+        world.resource_mut::<VelocityOverlay>().enabled = false;
+        run_system(&mut world, draw_velocity_vectors);
+        assert_eq!(lines.iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn inspection_counts_neighbors_within_the_widest_radius() {
+        let mut world = test_world();
+        let params = BoidParams::default();
+        let radius = params.cohesion_radius.max(params.alignment_radius);
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(3., 4.));
+        spawn_test_boid(&mut world, Vec2::new(radius / 2., 0.), Vec2::ZERO);
+        spawn_test_boid(&mut world, Vec2::new(radius * 3., 0.), Vec2::ZERO);
+        let inspection = inspect_boid(
+            boid,
+            world.get::<Kinematics>(boid).unwrap(),
+            world.get::<Transform>(boid).unwrap(),
+            false,
+            Some(Species(2)),
+            world.resource::<EntityQuadtree>(),
+            &params,
+        );
+        assert_eq!(inspection.neighbor_count, 1);
+        assert_eq!(inspection.speed, 5.);
+        assert_eq!(inspection.species, Some(2));
+        let text = inspection_text(&inspection);
+        assert!(text.contains("Speed: 5.0"));
+        assert!(text.contains("Neighbors: 1"));
+        assert!(text.contains("Species: 2"));
+    }
+
+    #[test]
+    fn inspection_panel_shows_the_selected_boid_and_hides_once_it_is_despawned() {
+        let mut world = test_world();
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., 12.));
+        world.insert_resource(SelectedBoid(Some(boid)));
+        let panel = world
+            .spawn()
+            .insert(Text::from_section("", TextStyle::default()))
+            .insert(Visibility { is_visible: false })
+            .insert(InspectionText)
+            .id();
+        run_system(&mut world, inspect_selected_boid);
+        assert!(world.get::<Visibility>(panel).unwrap().is_visible);
+        let text = &world.get::<Text>(panel).unwrap().sections[0].value;
+        assert!(text.contains("Speed: 12.0"), "{}", text);
+
+        world.despawn(boid);
+        run_system(&mut world, inspect_selected_boid);
+        assert!(!world.get::<Visibility>(panel).unwrap().is_visible);
+        assert_eq!(world.resource::<SelectedBoid>().0, None);
+    }
+
+    #[test]
+    fn hud_text_lists_each_stat_and_the_enabled_behaviors() {
+        let flock = FlockStats {
+            num_boids: 120,
+            mean_speed: 98.76,
+            centroid: Vec2::new(-12.4, 30.6),
+            polarization: 0.5,
+        };
+        let stats = QuadtreeStats {
+            num_nodes: 8,
+            num_values: 120,
+            average_depth: 1.,
+            average_num_values: 15.,
+            values_per_node: vec![0, 3, 4, 1, 0],
+            leaf_depths: vec![0, 3, 4, 0, 0, 0, 0, 0],
+        };
+        let mut mask = BehaviorMask::ALL;
+        mask.toggle(BehaviorMask::COHESION);
+        mask.toggle(BehaviorMask::GOAL);
+        let text = hud_text(Some(59.6), &flock, &stats, mask);
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            vec![
+                "FPS: 60",
+                "Boids: 120",
+                "Flock: speed 98.8, polarization 0.50, centroid (-12, 31)",
+                "Quadtree: 8 nodes, 120 values",
+                "Behaviors: separation, alignment, edge avoidance",
+            ]
+        );
+        // fps stays blank until it has been measured, and nothing enabled reads as none
+        let text = hud_text(None, &flock, &stats, BehaviorMask(0));
+        assert!(text.starts_with("FPS: --\n"));
+        assert!(text.ends_with("Behaviors: none"));
+    }
+
+    #[test]
+    fn replay_only_unfreezes_the_boids_it_froze() {
+        let mut world = test_world();
+        world.insert_resource(ReplayBuffer::new(4));
+        let pinned = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        world.entity_mut(pinned).insert(Frozen);
+        let free = spawn_test_boid(&mut world, Vec2::new(20., 0.), Vec2::new(10., 0.));
+        run_system(&mut world, record_replay_frame);
+        let press_p = |world: &mut World| {
+            let mut keys = Input::<KeyCode>::default();
+            keys.press(KeyCode::P);
+            world.insert_resource(keys);
+            run_system(world, control_replay);
+        };
+
+        press_p(&mut world);
+        assert!(world.resource::<ReplayBuffer>().is_replaying());
+        assert!(world.get::<Frozen>(free).is_some());
+        assert_eq!(world.resource::<ReplayBuffer>().frozen, vec![free]);
+
+        press_p(&mut world);
+        assert!(!world.resource::<ReplayBuffer>().is_replaying());
+        assert!(world.get::<Frozen>(free).is_none());
+        assert!(world.get::<Frozen>(pinned).is_some());
+        assert!(world.resource::<ReplayBuffer>().frozen.is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use bevy::{math::DVec2, prelude::*, utils::HashMap};
+
+// flock-wide averages. Samples are sorted by entity and summed in f64, so the same flock always
+// gives bit-identical stats no matter which order the query visited it in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlockStats {
+    pub num_boids: usize,
+    pub mean_speed: f32,
+    pub centroid: Vec2,
+    // length of the mean heading, 1 when every boid flies the same way and near 0 when their
+    // headings cancel out
+    pub polarization: f32,
+}
+
+impl FlockStats {
+    // samples are (entity, position, velocity) and are sorted in place
+    pub fn calculate(samples: &mut [(Entity, Vec2, Vec2)]) -> FlockStats {
+        if samples.is_empty() {
+            return FlockStats::default();
+        }
+        samples.sort_unstable_by_key(|(entity, _, _)| *entity);
+        let (speed_sum, position_sum, heading_sum) = samples.iter().fold(
+            (0f64, DVec2::ZERO, DVec2::ZERO),
+            |(speed_sum, position_sum, heading_sum), (_, position, velocity)| {
+                (
+                    speed_sum + velocity.length() as f64,
+                    position_sum + position.as_dvec2(),
+                    heading_sum + velocity.normalize_or_zero().as_dvec2(),
+                )
+            },
+        );
+        let count = samples.len() as f64;
+        FlockStats {
+            num_boids: samples.len(),
+            mean_speed: (speed_sum / count) as f32,
+            centroid: (position_sum / count).as_vec2(),
+            polarization: (heading_sum / count).length() as f32,
+        }
+    }
+}
+
+// groups boids chained together by links, each link an (entity, neighbor) pair. Links to
+// entities missing from entities are ignored. Every cluster is sorted by entity and clusters are
+// ordered by their first entity, so the same links always give the same clusters
+pub fn detect_clusters(
+    entities: &[Entity],
+    links: impl IntoIterator<Item = (Entity, Entity)>,
+) -> Vec<Vec<Entity>> {
+    let mut sorted = entities.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let index: HashMap<Entity, usize> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, entity)| (*entity, i))
+        .collect();
+    // union-find over indices into sorted, the root of a set is always its lowest index
+    let mut parents: Vec<usize> = (0..sorted.len()).collect();
+    for (a, b) in links {
+        if let (Some(&a), Some(&b)) = (index.get(&a), index.get(&b)) {
+            let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+            parents[root_a.max(root_b)] = root_a.min(root_b);
+        }
+    }
+    let mut clusters: Vec<Vec<Entity>> = vec![];
+    let mut cluster_of_root: HashMap<usize, usize> = HashMap::default();
+    for (i, entity) in sorted.iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        let cluster = *cluster_of_root.entry(root).or_insert_with(|| {
+            clusters.push(vec![]);
+            clusters.len() - 1
+        });
+        clusters[cluster].push(*entity);
+    }
+    clusters
+}
+
+/* Internal-only Functions */
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        // path halving keeps later lookups short
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn stats_are_identical_whatever_order_the_boids_come_in() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let samples: Vec<(Entity, Vec2, Vec2)> = (0..5000)
+            .map(|i| {
+                let position = Vec2::new(rng.gen_range(-1e4..1e4), rng.gen_range(-1e4..1e4));
+                let velocity = Vec2::new(rng.gen_range(-300.0..300.), rng.gen_range(-300.0..300.));
+                (Entity::from_raw(i), position, velocity)
+            })
+            .collect();
+        let expected = FlockStats::calculate(&mut samples.clone());
+        assert_eq!(expected.num_boids, 5000);
+        for _ in 0..3 {
+            assert_eq!(FlockStats::calculate(&mut samples.clone()), expected);
+        }
+        let mut reversed: Vec<_> = samples.iter().rev().copied().collect();
+        assert_eq!(FlockStats::calculate(&mut reversed), expected);
+        let mut shuffled = samples.clone();
+        shuffled.rotate_left(1234);
+        shuffled.swap(0, 4000);
+        assert_eq!(FlockStats::calculate(&mut shuffled), expected);
+    }
+
+    #[test]
+    fn clusters_follow_chains_of_links() {
+        let [a, b, c, d, e] = [4, 0, 3, 1, 2].map(Entity::from_raw);
+        // a-b-c chained, d alone, e linked to an entity outside the flock
+        let links = [(a, b), (c, b), (e, Entity::from_raw(9))];
+        let clusters = detect_clusters(&[a, b, c, d, e], links);
+        assert_eq!(clusters, vec![vec![b, c, a], vec![d], vec![e]]);
+    }
+}
@@ -1,25 +1,46 @@
 pub mod components;
+pub mod config;
+pub mod debug;
+pub mod flock_stats;
 pub mod resources;
 pub mod setup;
 pub mod systems;
+#[cfg(test)]
+mod test_support;
 
-use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
-use bevy::window::WindowMode;
-use bevy::{prelude::*, sprite::Rect, time::FixedTimestep};
+use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::{prelude::*, time::FixedTimestep};
 
-use self::components::CollisionEvent;
-use self::resources::EntityQuadtree;
-use self::setup::{setup_camera, spawn_boids};
+use self::components::{BoidEscaped, BoidSpawnRejected, BoidStalled, CollisionEvent, Impulse};
+use self::config::{load_config, BoidsConfig, CONFIG_PATH};
+use self::debug::{
+    control_replay, cycle_neighbor_strategy, despawn_selected_boid, draw_velocity_vectors,
+    highlight_node_under_cursor, inspect_selected_boid, log_boid_events, select_boid, spawn_hud,
+    spawn_inspection_panel, spawn_node_highlight, spawn_selection_marker, toggle_behaviors,
+    toggle_hud, toggle_physics_pause, toggle_quadtree_overlay, toggle_velocity_overlay, update_hud,
+};
+use self::resources::{
+    BehaviorMask, EntityQuadtree, HudOverlay, MaxBoids, NeighborStrategy, ObstacleQuadtree,
+    PerfBudget, PhysicsPause, QuadtreeOverlay, ReplayBuffer, Scatter, SelectedBoid, SettlePhase,
+    VelocityOverlay, WorldBounds,
+};
+use self::setup::{
+    build_obstacle_quadtree, reset_simulation, setup_boid_meshes, setup_camera, spawn_attractors,
+    spawn_boid_at_cursor, spawn_boids, spawn_obstacles, validate_integrator,
+};
 use self::systems::{
-    apply_kinematics, approach_nearby_boid_groups, avoid_nearby_boids, avoid_screen_edges,
-    update_quadtree, wrap_screen_edges,
+    advance_settle_phase, animate_spawn_transitions, apply_attractors, apply_impulse,
+    apply_kinematics, assign_cluster_ids, avoid_obstacles, avoid_screen_edges, color_by_cluster,
+    decay_alarm, detect_escaped_boids, detect_stalls, draw_wrap_ghosts, ease_to_preferred_speed,
+    flocking, follow_leaders, impulse_from_arrow_keys, normalize_flock_speed, orient_to_velocity,
+    propagate_alarm, record_previous_velocity, record_replay_frame, refit_quadtree,
+    remove_despawned_boids, sanitize_kinematics, scare_from_cursor, scatter_flock,
+    scatter_from_key, seek_open_space, smooth_velocity, split_large_flocks, start_physics_timer,
+    stop_physics_timer, unless_physics_paused, update_quadtree, wander_leaders, wrap_screen_edges,
 };
 
-const SCREEN_SIZE: Vec2 = Vec2::new(1920., 1080.);
-const QUADTREE_SIZE: Rect = Rect {
-    min: Vec2::new(-SCREEN_SIZE.x / 2., -SCREEN_SIZE.y / 2.),
-    max: Vec2::new(SCREEN_SIZE.x / 2., SCREEN_SIZE.y / 2.),
-};
+// default window resolution, see WindowConfig
+pub const SCREEN_SIZE: Vec2 = Vec2::new(1920., 1080.);
 const BACKGROUND_COLOR: Color = Color::rgb(0.1, 0.1, 0.1);
 pub const PHYSICS_FRAME_RATE: f64 = 60.;
 
@@ -27,40 +48,219 @@ pub const PHYSICS_FRAME_RATE: f64 = 60.;
     These systems represent game logic.
 */
 pub fn run_ecs_application() {
-    App::new()
+    let config = load_config(CONFIG_PATH);
+    let physics_frame_rate = config.physics.frame_rate;
+    insert_world_resources(&mut App::new(), &config)
         .insert_resource(WindowDescriptor {
             title: "Bevy Boids".to_string(),
-            width: SCREEN_SIZE.x,
-            height: SCREEN_SIZE.y,
-            mode: WindowMode::Windowed,
+            width: config.window.resolution[0],
+            height: config.window.resolution[1],
+            mode: config.window.mode.into(),
+            present_mode: config.window.present_mode(),
             ..default()
         })
         .add_plugins(DefaultPlugins)
         // .add_plugin(LogDiagnosticsPlugin::default())
-        // .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        // feeds the fps shown by the HUD
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         .insert_resource(ClearColor(BACKGROUND_COLOR))
-        .insert_resource(EntityQuadtree::empty(QUADTREE_SIZE))
+        .insert_resource(SettlePhase::new(config.spawn.settle_ticks))
+        .insert_resource(config.boids)
+        .insert_resource(MaxBoids(config.spawn.max_boids))
+        .insert_resource(config.spawn)
+        .insert_resource(ReplayBuffer::new(config.physics.replay_ticks))
+        .insert_resource(config.physics)
+        .insert_resource(config.quadtree_fit)
+        .insert_resource(config.attractors)
+        .init_resource::<NeighborStrategy>()
+        .init_resource::<VelocityOverlay>()
+        .init_resource::<QuadtreeOverlay>()
+        .init_resource::<SelectedBoid>()
+        .init_resource::<PerfBudget>()
+        .init_resource::<BehaviorMask>()
+        .init_resource::<HudOverlay>()
+        .init_resource::<PhysicsPause>()
+        .init_resource::<Scatter>()
+        .add_startup_system_to_stage(StartupStage::PreStartup, setup_boid_meshes)
         .add_startup_system(setup_camera)
         .add_startup_system(spawn_boids)
+        .add_startup_system(spawn_obstacles)
+        .add_startup_system(spawn_attractors)
+        .add_startup_system(spawn_node_highlight)
+        .add_startup_system(spawn_selection_marker)
+        .add_startup_system(spawn_hud)
+        .add_startup_system(spawn_inspection_panel)
+        .add_startup_system_to_stage(StartupStage::PostStartup, build_obstacle_quadtree)
+        .add_startup_system_to_stage(StartupStage::PostStartup, validate_integrator)
         .add_event::<CollisionEvent>()
-        .add_system_set(physics_system_set(PHYSICS_FRAME_RATE))
+        .add_event::<BoidEscaped>()
+        .add_event::<BoidSpawnRejected>()
+        .add_event::<BoidStalled>()
+        .add_event::<Impulse>()
+        // before Update so the old flock is gone by the time the physics systems run
+        .add_system_to_stage(CoreStage::PreUpdate, reset_simulation)
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            spawn_boid_at_cursor.after(reset_simulation),
+        )
+        .add_system_set(physics_system_set(physics_frame_rate))
+        // after the physics step so a despawning boid can't be re-added to the quadtree
+        .add_system_to_stage(CoreStage::PostUpdate, animate_spawn_transitions)
+        // last so despawns from every earlier stage have been applied and are visible
+        .add_system_to_stage(CoreStage::Last, remove_despawned_boids)
+        .add_system(impulse_from_arrow_keys)
+        .add_system(apply_impulse.after(impulse_from_arrow_keys))
+        .add_system(scatter_from_key)
+        .add_system(orient_to_velocity)
+        .add_system(draw_wrap_ghosts.after(orient_to_velocity))
+        .add_system(assign_cluster_ids)
+        .add_system(color_by_cluster.after(assign_cluster_ids))
+        .add_system(toggle_velocity_overlay)
+        .add_system(draw_velocity_vectors.after(toggle_velocity_overlay))
+        .add_system(toggle_quadtree_overlay)
+        .add_system(highlight_node_under_cursor.after(toggle_quadtree_overlay))
+        .add_system(toggle_behaviors)
+        .add_system(cycle_neighbor_strategy)
+        .add_system(log_boid_events)
+        .add_system(select_boid)
+        .add_system(inspect_selected_boid.after(select_boid))
+        .add_system(despawn_selected_boid.after(inspect_selected_boid))
+        .add_system(control_replay)
+        .add_system(toggle_physics_pause)
+        .add_system(toggle_hud)
+        .add_system(update_hud.after(toggle_hud))
         .add_system(bevy::window::close_on_esc)
         .run();
 }
 
+// the quadtrees and bounds span the configured window, so the world grows with the resolution
+fn insert_world_resources<'a>(app: &'a mut App, config: &BoidsConfig) -> &'a mut App {
+    let world_rect = config.window.world_rect();
+    app.insert_resource(EntityQuadtree::empty(world_rect))
+        .insert_resource(ObstacleQuadtree::empty(world_rect))
+        .insert_resource(WorldBounds::new(world_rect))
+}
+
 /*
-    All of these systems represent the physics engine, which runs at a fixed 60 fps.
+    All of these systems represent the physics engine, which runs at a fixed 60 fps unless
+    PhysicsPause holds it.
 */
 fn physics_system_set(physics_frame_rate: f64) -> SystemSet {
+    physics_systems().with_run_criteria(
+        FixedTimestep::steps_per_second(physics_frame_rate).chain(unless_physics_paused),
+    )
+}
+
+// one physics tick, without the timestep or pause that decide when it runs
+fn physics_systems() -> SystemSet {
     SystemSet::new()
-        .with_run_criteria(FixedTimestep::steps_per_second(physics_frame_rate))
-        .with_system(approach_nearby_boid_groups)
-        .with_system(avoid_nearby_boids)
-        .with_system(
-            avoid_screen_edges
-                .after(approach_nearby_boid_groups)
-                .after(avoid_nearby_boids),
-        )
-        .with_system(apply_kinematics.after(avoid_screen_edges))
-        .with_system(update_quadtree.after(apply_kinematics))
+        // steering systems all write velocity, so give them a fixed order for determinism
+        .with_system(start_physics_timer)
+        .with_system(record_previous_velocity.after(start_physics_timer))
+        .with_system(flocking.after(record_previous_velocity))
+        .with_system(advance_settle_phase.after(flocking))
+        .with_system(avoid_obstacles.after(flocking))
+        .with_system(seek_open_space.after(avoid_obstacles))
+        .with_system(split_large_flocks.after(seek_open_space))
+        .with_system(scare_from_cursor.after(split_large_flocks))
+        // a boid scared this tick starts spreading its alarm on the next one
+        .with_system(scatter_flock.after(scare_from_cursor))
+        .with_system(propagate_alarm.after(scatter_flock))
+        .with_system(decay_alarm.after(propagate_alarm))
+        .with_system(follow_leaders.after(decay_alarm))
+        .with_system(wander_leaders.after(follow_leaders))
+        .with_system(apply_attractors.after(wander_leaders))
+        .with_system(avoid_screen_edges.after(apply_attractors))
+        .with_system(ease_to_preferred_speed.after(avoid_screen_edges))
+        .with_system(normalize_flock_speed.after(ease_to_preferred_speed))
+        .with_system(sanitize_kinematics.after(normalize_flock_speed))
+        .with_system(smooth_velocity.after(sanitize_kinematics))
+        .with_system(apply_kinematics.after(smooth_velocity))
+        .with_system(wrap_screen_edges.after(apply_kinematics))
+        .with_system(detect_escaped_boids.after(wrap_screen_edges))
+        .with_system(detect_stalls.after(detect_escaped_boids))
+        .with_system(update_quadtree.after(detect_stalls))
+        .with_system(refit_quadtree.after(update_quadtree))
+        .with_system(stop_physics_timer.after(refit_quadtree))
+        // outside the timed span so recording doesn't count against PerfBudget
+        .with_system(record_replay_frame.after(stop_physics_timer))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::schedule::{Stage, SystemStage},
+        sprite::Rect,
+        time::FixedTimesteps,
+    };
+
+    use super::*;
+    use crate::{
+        ecs::{
+            flock_stats::FlockStats,
+            setup::BOID_SPEED,
+            test_support::{advance_time, spawn_test_boid, test_world},
+        },
+        util::quadtree::quadtree_stats::QuadtreeStats,
+    };
+
+    #[test]
+    fn physics_tick_with_no_boids_stays_finite() {
+        let mut world = test_world();
+        world.insert_resource(Windows::default());
+        advance_time(&mut world, 1. / PHYSICS_FRAME_RATE as f32);
+        let mut stage = SystemStage::single_threaded().with_system_set(physics_systems());
+        stage.run(&mut world);
+
+        let quadtree = world.resource::<EntityQuadtree>();
+        assert_eq!(quadtree.len(), 0);
+        assert!(quadtree.centroid().is_none());
+        let stats = QuadtreeStats::calculate(quadtree);
+        assert_eq!((stats.average_depth, stats.average_num_values), (0., 0.));
+        assert_eq!(FlockStats::calculate(&mut []), FlockStats::default());
+        let budget = world.resource::<PerfBudget>();
+        assert!(budget.radius_scale.is_finite());
+    }
+
+    #[test]
+    fn pausing_physics_leaves_input_systems_running() {
+        let mut world = test_world();
+        world.insert_resource(Windows::default());
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(FixedTimesteps::default());
+        world.resource_mut::<PhysicsPause>().paused = true;
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(BOID_SPEED, 0.));
+        let mut stage = SystemStage::single_threaded()
+            .with_system_set(physics_system_set(PHYSICS_FRAME_RATE))
+            .with_system(impulse_from_arrow_keys);
+        let position = |world: &World| world.get::<Transform>(boid).unwrap().translation;
+        let start = position(&world);
+
+        world.resource_mut::<Input<KeyCode>>().press(KeyCode::Right);
+        advance_time(&mut world, 2. / PHYSICS_FRAME_RATE as f32);
+        stage.run(&mut world);
+        assert_eq!(position(&world), start);
+        assert_eq!(world.resource::<Events<Impulse>>().len(), 1);
+
+        world.resource_mut::<PhysicsPause>().paused = false;
+        advance_time(&mut world, 2. / PHYSICS_FRAME_RATE as f32);
+        stage.run(&mut world);
+        assert!(position(&world).x > start.x);
+    }
+
+    #[test]
+    fn world_resources_span_the_configured_resolution() {
+        let mut config = BoidsConfig::default();
+        config.window.resolution = [1280., 720.];
+        let mut app = App::new();
+        insert_world_resources(&mut app, &config);
+        let expected = (Vec2::new(-640., -360.), Vec2::new(640., 360.));
+        let span = |rect: Rect| (rect.min, rect.max);
+        assert_eq!(span(app.world.resource::<EntityQuadtree>().rect), expected);
+        assert_eq!(
+            span(app.world.resource::<ObstacleQuadtree>().rect),
+            expected
+        );
+        assert_eq!(span(app.world.resource::<WorldBounds>().rect), expected);
+    }
 }
@@ -1,28 +1,273 @@
-use std::hash::Hash;
+use std::{collections::VecDeque, hash::Hash, ops::Deref, time::Instant};
+
+use serde::{de, Deserialize, Deserializer};
 
 use bevy::{
-    prelude::{Entity, Transform, Vec3},
-    sprite::Rect,
+    prelude::{Color, ColorMaterial, Entity, Handle, Transform, Vec2, Vec3},
+    sprite::{Mesh2dHandle, Rect},
+    window::{PresentMode, WindowMode},
 };
 
 use crate::util::{
     quadtree::{quadtree::Quadtree, quadtree_value::QuadtreeValue},
-    rect::transform_to_rect,
+    rect::{transform_to_centered_rect, transform_to_rect},
+};
+
+use super::{
+    setup::{BOID_COLOR, BOID_DIAG_LENGTH, BOID_SPEED, BOID_Z},
+    PHYSICS_FRAME_RATE, SCREEN_SIZE,
 };
 
+// tunable parameters shared by the steering systems
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BoidParams {
+    // boids slower than this are kicked toward the steering force at this speed
+    pub min_speed: f32,
+    // rescale every boid to the flock's mean speed each tick
+    pub uniform_speed: bool,
+    // longest Kinematics::acceleration apply_kinematics integrates, longer ones are shortened to
+    // it keeping their direction; infinite by default, so nothing is clamped
+    pub max_acceleration: f32,
+    // fraction of the gap to Kinematics::preferred_speed closed each tick, 0 keeps any speed
+    pub speed_return_rate: f32,
+    // how separation strength falls off with distance to a neighbor
+    pub separation_falloff: SeparationFalloff,
+    // what alignment averages over its neighbors
+    pub alignment_mode: AlignmentMode,
+    // shape separation treats boids as, the quadtree search stays rect based either way
+    pub collision_shape: CollisionShape,
+    // ignore separation neighbors on the far side of an obstacle
+    pub separation_line_of_sight: bool,
+    // lerp weights of the separation and alignment rules, 0 turns the rule off
+    pub separation_strength: f32,
+    pub alignment_strength: f32,
+    // extra separation weight per BOID_SPEED of closing speed, 0 keeps it constant
+    pub separation_closing_gain: f32,
+    // fraction of the previous tick's velocity kept after steering, 0 applies steering as is
+    pub smoothing: f32,
+    // boids within scare_radius of the cursor flee it, scare_strength is the lerp weight
+    pub scare_radius: f32,
+    pub scare_strength: f32,
+    // seconds a scared boid stays alarmed, 0 turns alarms off. Neighbors within alarm_radius of an
+    // alarmed boid are alarmed for alarm_falloff of its remaining ttl, so the alarm fades as it
+    // spreads
+    pub alarm_ttl: f32,
+    pub alarm_radius: f32,
+    pub alarm_falloff: f32,
+    // alarmed boids multiply their cohesion weight by this and never drop below alarm_speed
+    pub alarm_cohesion_boost: f32,
+    pub alarm_speed: f32,
+    // followers within leader_radius of a leader steer toward it with leader_weight
+    pub leader_radius: f32,
+    pub leader_weight: f32,
+    // half-widths of the square neighborhoods each flocking rule looks at
+    pub separation_radius: f32,
+    pub alignment_radius: f32,
+    pub cohesion_radius: f32,
+    // cohesion ignores neighbors whose centers are closer than this and is scaled by the share of
+    // neighbors farther out, so it can't pull a boid into a crowd separation is pushing it out
+    // of; 0 lets every neighbor count
+    pub cohesion_floor: f32,
+    // alignment and cohesion skip neighbors whose heading has a dot product with the boid's own
+    // below this, so crossing traffic doesn't drag a flock around; -1 counts every neighbor
+    pub heading_similarity_cutoff: f32,
+    // what boids do at the world edge, see EdgeBehavior
+    pub edge_behavior: EdgeBehavior,
+    // fraction of the perpendicular speed kept when bouncing off the world edge, 1 is lossless
+    pub bounce_restitution: f32,
+    // boids within this distance of a wrapping edge are also drawn across the seam, 0 draws no
+    // ghosts
+    pub wrap_ghost_margin: f32,
+    // steer toward the least crowded of a few headings, open_space_distance ahead of the boid
+    pub open_space: bool,
+    pub open_space_weight: f32,
+    pub open_space_distance: f32,
+    // clusters of more than flock_split_size boids, chained together by boids within
+    // flock_split_radius of each other, nudge their members away from the cluster centroid with
+    // flock_split_weight so mega-flocks break up; 0 turns splitting off
+    pub flock_split_size: usize,
+    pub flock_split_radius: f32,
+    pub flock_split_weight: f32,
+    // a boid that stays within stall_distance of one spot for stall_ticks physics ticks is
+    // reported as stalled, 0 ticks turns detection off. With stall_nudge it's also kicked off in
+    // a random direction at its preferred speed
+    pub stall_distance: f32,
+    pub stall_ticks: u32,
+    pub stall_nudge: bool,
+    // pressing S makes every boid flee the flock centroid with scatter_strength for scatter_ttl
+    // seconds, after which flocking pulls them back together
+    pub scatter_ttl: f32,
+    pub scatter_strength: f32,
+}
+
+impl Default for BoidParams {
+    fn default() -> Self {
+        BoidParams {
+            min_speed: BOID_SPEED / 10.,
+            uniform_speed: false,
+            max_acceleration: f32::INFINITY,
+            speed_return_rate: 0.,
+            separation_falloff: SeparationFalloff::Exponential,
+            alignment_mode: AlignmentMode::Velocity,
+            collision_shape: CollisionShape::Rect,
+            separation_line_of_sight: false,
+            separation_strength: 0.03,
+            alignment_strength: 0.015,
+            separation_closing_gain: 1.,
+            smoothing: 0.,
+            scare_radius: 100.,
+            scare_strength: 0.2,
+            alarm_ttl: 1.5,
+            alarm_radius: 25.,
+            alarm_falloff: 0.8,
+            alarm_cohesion_boost: 3.,
+            alarm_speed: 1.5 * BOID_SPEED,
+            leader_radius: 100.,
+            leader_weight: 0.05,
+            separation_radius: 2.,
+            alignment_radius: 12.5,
+            cohesion_radius: 12.5,
+            cohesion_floor: 0.,
+            heading_similarity_cutoff: -1.,
+            edge_behavior: EdgeBehavior::BOUNCE,
+            bounce_restitution: 1.,
+            wrap_ghost_margin: 0.,
+            open_space: false,
+            open_space_weight: 0.02,
+            open_space_distance: 25.,
+            flock_split_size: 0,
+            flock_split_radius: 12.5,
+            flock_split_weight: 0.01,
+            stall_distance: BOID_DIAG_LENGTH,
+            stall_ticks: 2 * PHYSICS_FRAME_RATE as u32,
+            stall_nudge: false,
+            scatter_ttl: 1.,
+            scatter_strength: 0.3,
+        }
+    }
+}
+
+// what boids do at the world edges, chosen per axis so e.g. the left and right edges can wrap
+// while the top and bottom bounce. Read from boids.toml either as one mode for both axes,
+// edge_behavior = "Wrap", or per axis, edge_behavior = { x = "Wrap", y = "Bounce" }
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "EdgeBehaviorConfig")]
+pub struct EdgeBehavior {
+    pub x: EdgeMode,
+    pub y: EdgeMode,
+}
+
+impl EdgeBehavior {
+    pub const BOUNCE: EdgeBehavior = EdgeBehavior::uniform(EdgeMode::Bounce);
+
+    pub const fn uniform(mode: EdgeMode) -> Self {
+        EdgeBehavior { x: mode, y: mode }
+    }
+
+    // whether either axis uses mode
+    pub fn any(&self, mode: EdgeMode) -> bool {
+        self.x == mode || self.y == mode
+    }
+}
+
+// Bounce reflects boids off the edge (and is what edge avoidance toggles), Wrap moves a boid
+// touching one edge to the opposite one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum EdgeMode {
+    #[default]
+    Bounce,
+    Wrap,
+}
+
+// the two forms EdgeBehavior is written in
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EdgeBehaviorConfig {
+    Uniform(EdgeMode),
+    PerAxis { x: EdgeMode, y: EdgeMode },
+}
+
+impl From<EdgeBehaviorConfig> for EdgeBehavior {
+    fn from(config: EdgeBehaviorConfig) -> Self {
+        match config {
+            EdgeBehaviorConfig::Uniform(mode) => EdgeBehavior::uniform(mode),
+            EdgeBehaviorConfig::PerAxis { x, y } => EdgeBehavior { x, y },
+        }
+    }
+}
+
+// Velocity averages neighbors' full velocities, so faster neighbors pull harder. Heading averages
+// their directions only, every moving neighbor counting the same
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum AlignmentMode {
+    Velocity,
+    Heading,
+}
+
+// Rect keeps the square separation neighborhood and pushes apart along rect corners. Circle
+// treats each boid as the circle inscribed in its rect, counts a neighbor once its circle
+// overlaps the separation radius and pushes apart along the line between centers, so diagonal
+// neighbors aren't favored
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum CollisionShape {
+    Rect,
+    Circle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum SeparationFalloff {
+    InverseSquare,
+    Linear,
+    Exponential,
+}
+
+impl SeparationFalloff {
+    // weight of a neighbor's repulsion given its distance, decreasing as distance grows; distances
+    // are measured against diagonal, the live size of the boid
+    pub fn weight(&self, distance: f32, diagonal: f32) -> f32 {
+        match self {
+            SeparationFalloff::InverseSquare => inverse_square_falloff(distance, diagonal),
+            SeparationFalloff::Linear => linear_falloff(distance, diagonal),
+            SeparationFalloff::Exponential => exponential_falloff(distance, diagonal),
+        }
+    }
+}
+
+pub fn inverse_square_falloff(distance: f32, diagonal: f32) -> f32 {
+    let scaled = distance / diagonal;
+    1. / (1. + scaled * scaled)
+}
+
+// reaches zero at two boid diagonals
+pub fn linear_falloff(distance: f32, diagonal: f32) -> f32 {
+    (1. - distance / (2. * diagonal)).max(0.)
+}
+
+// logistic-style curve, close to 1 until about one boid diagonal and then drops off sharply
+pub fn exponential_falloff(distance: f32, diagonal: f32) -> f32 {
+    1. / (1. + (distance * distance - diagonal).exp() / diagonal)
+}
+
 #[derive(Clone)]
 pub struct EntityWrapper {
     pub entity: Entity,
     pub rect: Rect,
+    // copied from Kinematics by update_quadtree each physics tick, flocking reads the live value
     pub velocity: Vec3,
 }
 
 impl EntityWrapper {
-    pub fn new(entity: Entity, velocity: &Vec3, transform: &Transform) -> Self {
+    pub fn new(
+        entity: Entity,
+        velocity: &Vec3,
+        transform: &Transform,
+        alignment: SpawnAlignment,
+    ) -> Self {
         EntityWrapper {
             entity,
-            velocity: velocity.clone(),
-            rect: transform_to_rect(transform),
+            velocity: *velocity,
+            rect: alignment.transform_to_rect(transform),
         }
     }
 }
@@ -48,3 +293,670 @@ impl Hash for EntityWrapper {
 impl Eq for EntityWrapper {}
 
 pub type EntityQuadtree = Quadtree<EntityWrapper>;
+
+// parameters read by spawn_boids when populating the flock
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SpawnConfig {
+    // speed of every spawned boid, in a random direction
+    pub initial_speed: f32,
+    // number of spawned boids tagged as Leader
+    pub leader_count: usize,
+    // whether a boid's translation is the corner or the center of its rect
+    pub alignment: SpawnAlignment,
+    // draw depth of every boid, see BOID_Z
+    pub z: f32,
+    // boids are dealt round-robin into this many Layers, 1 puts every boid in the same one
+    pub layer_count: u8,
+    // boids added with spawn_single_boid take the mean velocity of the boids around them
+    pub inherit_velocity: bool,
+    // physics ticks of boosted separation after (re)spawning, see SettlePhase; 0 disables it
+    pub settle_ticks: u32,
+    // how boids are drawn, see RenderMode
+    pub render_mode: RenderMode,
+    // the spawn grid is split into this many bands of columns, one per species
+    pub species_count: usize,
+    // hex colors like "7fffd4", species without one get a color from species_color
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub species_colors: Vec<Color>,
+    // copied into MaxBoids at startup
+    pub max_boids: usize,
+    // grid boids are nudged up to this far off the lattice, 0 keeps the grid exact
+    pub spawn_jitter: f32,
+    // the jitter has its own rng, so the same seed gives the same layout on every run and reset
+    pub jitter_seed: u64,
+    // tint Sprite boids by the cluster they fly in instead of by species, clusters are linked
+    // the same way as for BoidParams::flock_split_radius
+    pub color_by_cluster: bool,
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        SpawnConfig {
+            initial_speed: BOID_SPEED,
+            leader_count: 5,
+            alignment: SpawnAlignment::Corner,
+            z: BOID_Z,
+            layer_count: 1,
+            inherit_velocity: true,
+            settle_ticks: 0,
+            render_mode: RenderMode::Sprite,
+            species_count: 1,
+            species_colors: vec![BOID_COLOR],
+            max_boids: 10_000,
+            spawn_jitter: 0.,
+            jitter_seed: 0,
+            color_by_cluster: false,
+        }
+    }
+}
+
+fn deserialize_colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|hex| Color::hex(hex).map_err(|err| de::Error::custom(format!("{}: {:?}", hex, err))))
+        .collect()
+}
+
+// Sprite gives each boid its own sprite. Instanced draws every boid with one shared mesh and
+// one shared material per species plus one for leaders, which skips per-sprite batching work
+// for large flocks but can't tint boids individually. Triangle shares handles the same way but
+// draws a triangle that orient_to_velocity turns to point along the boid's heading
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum RenderMode {
+    #[default]
+    Sprite,
+    Instanced,
+    Triangle,
+}
+
+impl RenderMode {
+    pub fn uses_meshes(&self) -> bool {
+        matches!(self, RenderMode::Instanced | RenderMode::Triangle)
+    }
+}
+
+// shared handles used by RenderMode::Instanced and RenderMode::Triangle
+pub struct BoidMeshes {
+    pub mesh: Mesh2dHandle,
+    // indexed by Species
+    pub species_materials: Vec<Handle<ColorMaterial>>,
+    pub leader_material: Handle<ColorMaterial>,
+}
+
+// how steering systems find neighbors, brute force is a reference for validating the quadtree
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NeighborStrategy {
+    #[default]
+    Quadtree,
+    BruteForce,
+    // one quadtree query per occupied node, shared by every boid whose center is in it. Still
+    // exact: each boid filters the shared list by its own rect, so only the tree descent is
+    // saved. The shared list covers the node plus the flocking radius on every side, which pays
+    // off once several boids share a node
+    NodeCache,
+}
+
+impl NeighborStrategy {
+    // order the N key cycles through
+    pub fn next(self) -> Self {
+        match self {
+            NeighborStrategy::Quadtree => NeighborStrategy::BruteForce,
+            NeighborStrategy::BruteForce => NeighborStrategy::NodeCache,
+            NeighborStrategy::NodeCache => NeighborStrategy::Quadtree,
+        }
+    }
+}
+
+// debug overlay showing each boid's velocity, velocity is multiplied by scale for display
+pub struct VelocityOverlay {
+    pub enabled: bool,
+    pub scale: f32,
+}
+
+impl Default for VelocityOverlay {
+    fn default() -> Self {
+        VelocityOverlay {
+            enabled: false,
+            scale: 0.2,
+        }
+    }
+}
+
+// debug overlay highlighting the quadtree node under the cursor
+#[derive(Default)]
+pub struct QuadtreeOverlay {
+    pub enabled: bool,
+}
+
+// on-screen stats toggled with F3
+#[derive(Default)]
+pub struct HudOverlay {
+    pub enabled: bool,
+}
+
+// freezes only the physics system set, so rendering, input and the debug overlays stay live
+// and a stopped flock can still be inspected; toggled with Space
+#[derive(Default)]
+pub struct PhysicsPause {
+    pub paused: bool,
+}
+
+// seconds left of a scatter, 0 while the flock isn't scattering
+#[derive(Default)]
+pub struct Scatter {
+    pub ttl: f32,
+}
+
+impl Scatter {
+    pub fn is_active(&self) -> bool {
+        self.ttl > 0.
+    }
+}
+
+// flock size the spawn systems won't grow past, so click-spam can't grow the quadtree forever
+pub struct MaxBoids(pub usize);
+
+// boid picked by clicking on it, inspected by the debug systems
+#[derive(Default)]
+pub struct SelectedBoid(pub Option<Entity>);
+
+// which steering behaviors run, toggled at runtime for debugging
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BehaviorMask(pub u8);
+
+impl BehaviorMask {
+    pub const SEPARATION: BehaviorMask = BehaviorMask(1 << 0);
+    pub const ALIGNMENT: BehaviorMask = BehaviorMask(1 << 1);
+    pub const COHESION: BehaviorMask = BehaviorMask(1 << 2);
+    // avoid_screen_edges
+    pub const EDGE: BehaviorMask = BehaviorMask(1 << 3);
+    // follow_leaders and apply_attractors
+    pub const GOAL: BehaviorMask = BehaviorMask(1 << 4);
+    pub const ALL: BehaviorMask = BehaviorMask(0b11111);
+
+    pub fn contains(&self, behavior: BehaviorMask) -> bool {
+        self.0 & behavior.0 == behavior.0
+    }
+
+    pub fn toggle(&mut self, behavior: BehaviorMask) {
+        self.0 ^= behavior.0;
+    }
+}
+
+impl Default for BehaviorMask {
+    fn default() -> Self {
+        BehaviorMask::ALL
+    }
+}
+
+// fixed-timestep settings for the physics system set
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PhysicsConfig {
+    // physics steps per second
+    pub frame_rate: f64,
+    // slices each step is moved in, edges are checked between slices so fast boids can't skip
+    // past them
+    pub substeps: usize,
+    // physics ticks kept in the ReplayBuffer, 0 turns recording off
+    pub replay_ticks: usize,
+    // update_quadtree leaves a boid where it was last indexed until its rect has moved this far,
+    // so neighbor queries may be off by up to this much. 0 relocates every boid every tick
+    pub reindex_threshold: f32,
+    // how apply_kinematics advances boids, see Integrator
+    pub integrator: Integrator,
+}
+
+impl PhysicsConfig {
+    pub fn delta_time(&self) -> f32 {
+        1. / self.frame_rate as f32
+    }
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            frame_rate: PHYSICS_FRAME_RATE,
+            substeps: 1,
+            replay_ticks: 600,
+            reindex_threshold: 0.,
+            integrator: Integrator::RK4,
+        }
+    }
+}
+
+// Euler moves by the velocity alone, RK4 also accounts for acceleration within the step, and
+// Verlet additionally feeds the acceleration back into the velocity using the acceleration
+// stored at the end of the previous step. All three agree when acceleration is zero
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Integrator {
+    Euler,
+    #[default]
+    RK4,
+    Verlet,
+}
+
+impl Integrator {
+    // whether boids need a PreviousAcceleration to be integrated this way
+    pub fn needs_previous_acceleration(&self) -> bool {
+        matches!(self, Integrator::Verlet)
+    }
+}
+
+// grid-spawned boids start packed together, so separation is boosted for the first few ticks
+// to let them spread out before the other rules take over
+#[derive(Default)]
+pub struct SettlePhase {
+    pub ticks_remaining: u32,
+}
+
+impl SettlePhase {
+    pub const SEPARATION_SCALE: f32 = 4.;
+
+    pub fn new(ticks: u32) -> Self {
+        SettlePhase {
+            ticks_remaining: ticks,
+        }
+    }
+
+    pub fn is_settling(&self) -> bool {
+        self.ticks_remaining > 0
+    }
+
+    // multiplier on the separation weight for the current tick
+    pub fn separation_scale(&self) -> f32 {
+        if self.is_settling() {
+            SettlePhase::SEPARATION_SCALE
+        } else {
+            1.
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.ticks_remaining = self.ticks_remaining.saturating_sub(1);
+    }
+}
+
+// state of one boid at the end of a physics tick
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoidSnapshot {
+    pub entity: Entity,
+    pub transform: Transform,
+    pub velocity: Vec3,
+}
+
+// the last few physics ticks, oldest dropped first, stepped through while replaying
+pub struct ReplayBuffer {
+    pub capacity: usize,
+    pub frames: VecDeque<Vec<BoidSnapshot>>,
+    // index of the frame on screen while replaying, None while recording
+    pub cursor: Option<usize>,
+    // boids the replay froze when it started, the only ones it unfreezes when it stops
+    pub frozen: Vec<Entity>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        ReplayBuffer {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+            cursor: None,
+            frozen: vec![],
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    // ignored while replaying, so stepping back can't overwrite the history being viewed
+    pub fn record(&mut self, frame: Vec<BoidSnapshot>) {
+        if self.capacity == 0 || self.is_replaying() {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    // starts on the newest frame, returns false if nothing has been recorded
+    pub fn start(&mut self) -> bool {
+        self.cursor = self.frames.len().checked_sub(1);
+        self.is_replaying()
+    }
+
+    // the newest frame, where the simulation picks back up
+    pub fn stop(&mut self) -> Option<&Vec<BoidSnapshot>> {
+        self.cursor = None;
+        self.frames.back()
+    }
+
+    // move the cursor by offset frames, clamped to the recorded range
+    pub fn step(&mut self, offset: isize) {
+        if let Some(cursor) = self.cursor {
+            let last = self.frames.len().saturating_sub(1) as isize;
+            self.cursor = Some((cursor as isize + offset).clamp(0, last) as usize);
+        }
+    }
+
+    pub fn current_frame(&self) -> Option<&Vec<BoidSnapshot>> {
+        self.frames.get(self.cursor?)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.cursor = None;
+        self.frozen.clear();
+    }
+}
+
+// shrinks the flocking neighborhoods while physics ticks run over budget, restoring them after
+pub struct PerfBudget {
+    // target wall time of one physics tick, in milliseconds
+    pub budget_ms: f32,
+    // multiplier applied to the alignment and cohesion radii
+    pub radius_scale: f32,
+    // when the current physics tick started
+    pub tick_started: Option<Instant>,
+}
+
+// the neighborhoods never shrink below this fraction of their configured size
+pub const MIN_RADIUS_SCALE: f32 = 0.25;
+const RADIUS_SHRINK_RATE: f32 = 0.9;
+const RADIUS_GROW_RATE: f32 = 1.05;
+// only grow back once comfortably under budget, so the scale doesn't oscillate at the edge
+const BUDGET_HEADROOM: f32 = 0.8;
+
+impl PerfBudget {
+    // feed the duration of the last physics tick to the controller
+    pub fn record_tick(&mut self, elapsed_ms: f32) {
+        self.radius_scale = adjust_radius_scale(self.radius_scale, elapsed_ms, self.budget_ms);
+    }
+}
+
+impl Default for PerfBudget {
+    fn default() -> Self {
+        PerfBudget {
+            // half of a 60 fps frame
+            budget_ms: 8.,
+            radius_scale: 1.,
+            tick_started: None,
+        }
+    }
+}
+
+// shrink multiplicatively when over budget, grow back slowly when under it
+pub fn adjust_radius_scale(radius_scale: f32, elapsed_ms: f32, budget_ms: f32) -> f32 {
+    if elapsed_ms > budget_ms {
+        (radius_scale * RADIUS_SHRINK_RATE).max(MIN_RADIUS_SCALE)
+    } else if elapsed_ms < budget_ms * BUDGET_HEADROOM {
+        (radius_scale * RADIUS_GROW_RATE).min(1.)
+    } else {
+        radius_scale
+    }
+}
+
+// attractor points listed in boids.toml, spawned as Attractor entities at startup
+#[derive(Default, Deserialize)]
+#[serde(transparent)]
+pub struct AttractorConfigs(pub Vec<AttractorConfig>);
+
+#[derive(Clone, Deserialize)]
+pub struct AttractorConfig {
+    pub position: [f32; 2],
+    // negative strength repels
+    pub strength: f32,
+    pub radius: f32,
+}
+
+// Corner treats translation as rect.min (the original behavior), Center treats it as the
+// midpoint, which matches where the sprite is drawn and how magnify_rect scales
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum SpawnAlignment {
+    #[default]
+    Corner,
+    Center,
+}
+
+impl SpawnAlignment {
+    // rect covered by a boid with this transform
+    pub fn transform_to_rect(&self, transform: &Transform) -> Rect {
+        match self {
+            SpawnAlignment::Corner => transform_to_rect(transform),
+            SpawnAlignment::Center => transform_to_centered_rect(transform),
+        }
+    }
+}
+
+// region boids are expected to stay in, matches the quadtree rect
+pub struct WorldBounds {
+    pub rect: Rect,
+    // pull escaped boids back inside instead of only reporting them
+    pub clamp_escaped: bool,
+}
+
+impl WorldBounds {
+    pub fn new(rect: Rect) -> Self {
+        WorldBounds {
+            rect,
+            clamp_escaped: true,
+        }
+    }
+}
+
+// periodically re-root the boid quadtree around the flock so its nodes aren't spent on empty
+// space; boids that leave the fitted rect between refits are missing from the tree until the next
+// one, so padding should cover how far a boid can travel in one interval
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct QuadtreeFit {
+    pub enabled: bool,
+    // seconds of simulated time between refits
+    pub interval: f32,
+    // margin added around the flock's bounding box, the result is clamped to WorldBounds
+    pub padding: f32,
+}
+
+impl Default for QuadtreeFit {
+    fn default() -> Self {
+        QuadtreeFit {
+            enabled: false,
+            interval: 1.,
+            padding: 2. * BOID_SPEED,
+        }
+    }
+}
+
+// window settings read once at startup, the world (and so the quadtree) spans the resolution
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    // width and height in logical pixels
+    pub resolution: [f32; 2],
+    pub mode: DisplayMode,
+    pub vsync: bool,
+}
+
+impl WindowConfig {
+    // rect of the world visible at startup, centered on the origin like the camera
+    pub fn world_rect(&self) -> Rect {
+        let half_size = Vec2::from(self.resolution) / 2.;
+        Rect {
+            min: -half_size,
+            max: half_size,
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        }
+    }
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            resolution: SCREEN_SIZE.to_array(),
+            mode: DisplayMode::Windowed,
+            vsync: true,
+        }
+    }
+}
+
+// mirrors bevy's WindowMode so it can be read from boids.toml
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum DisplayMode {
+    #[default]
+    Windowed,
+    BorderlessFullscreen,
+    SizedFullscreen,
+    Fullscreen,
+}
+
+impl From<DisplayMode> for WindowMode {
+    fn from(mode: DisplayMode) -> Self {
+        match mode {
+            DisplayMode::Windowed => WindowMode::Windowed,
+            DisplayMode::BorderlessFullscreen => WindowMode::BorderlessFullscreen,
+            DisplayMode::SizedFullscreen => WindowMode::SizedFullscreen,
+            DisplayMode::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+}
+
+// static obstacles are indexed once at startup and only read afterwards
+pub struct ObstacleQuadtree(pub Quadtree<EntityWrapper>);
+
+impl ObstacleQuadtree {
+    pub fn empty(size: Rect) -> Self {
+        ObstacleQuadtree(Quadtree::empty(size))
+    }
+}
+
+impl Deref for ObstacleQuadtree {
+    type Target = Quadtree<EntityWrapper>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falloff_curves_decrease_with_distance() {
+        let diagonal = BOID_DIAG_LENGTH;
+        for falloff in [
+            SeparationFalloff::InverseSquare,
+            SeparationFalloff::Linear,
+            SeparationFalloff::Exponential,
+        ] {
+            let weights: Vec<f32> = (0..40)
+                .map(|step| falloff.weight(step as f32 * 0.25, diagonal))
+                .collect();
+            assert!(weights[0] > *weights.last().unwrap());
+            for pair in weights.windows(2) {
+                assert!(
+                    pair[1] <= pair[0],
+                    "{:?} rose from {} to {}",
+                    falloff,
+                    pair[0],
+                    pair[1]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn radius_scale_shrinks_over_budget_and_recovers_under_it() {
+        let mut budget = PerfBudget::default();
+        // sustained overload bottoms out at the floor
+        for _ in 0..100 {
+            budget.record_tick(budget.budget_ms * 2.);
+        }
+        assert_eq!(budget.radius_scale, MIN_RADIUS_SCALE);
+
+        // within the headroom band the scale holds steady
+        budget.record_tick(budget.budget_ms * 0.9);
+        assert_eq!(budget.radius_scale, MIN_RADIUS_SCALE);
+
+        let mut previous = budget.radius_scale;
+        budget.record_tick(budget.budget_ms * 0.5);
+        assert!(budget.radius_scale > previous);
+        for _ in 0..100 {
+            previous = budget.radius_scale;
+            budget.record_tick(budget.budget_ms * 0.5);
+            assert!(budget.radius_scale >= previous);
+        }
+        assert_eq!(budget.radius_scale, 1.);
+
+        // a single slow tick shrinks it by one step
+        budget.record_tick(budget.budget_ms + 1.);
+        assert_eq!(budget.radius_scale, RADIUS_SHRINK_RATE);
+    }
+
+    #[test]
+    fn larger_boids_repel_from_further_away() {
+        let small = BOID_DIAG_LENGTH;
+        let large = BOID_DIAG_LENGTH * 2.;
+        for falloff in [
+            SeparationFalloff::InverseSquare,
+            SeparationFalloff::Linear,
+            SeparationFalloff::Exponential,
+        ] {
+            let distance = small * 1.5;
+            assert!(
+                falloff.weight(distance, large) > falloff.weight(distance, small),
+                "{:?}",
+                falloff
+            );
+        }
+        // the inverse-square and linear curves only depend on distance in diagonals
+        for distance in [0.5, 2., 4.] {
+            assert_eq!(
+                inverse_square_falloff(distance * 2., large),
+                inverse_square_falloff(distance, small)
+            );
+            assert_eq!(
+                linear_falloff(distance * 2., large),
+                linear_falloff(distance, small)
+            );
+        }
+    }
+
+    #[test]
+    fn world_rect_spans_the_resolution_around_the_origin() {
+        let window = WindowConfig {
+            resolution: [1280., 720.],
+            ..WindowConfig::default()
+        };
+        let world_rect = window.world_rect();
+        assert_eq!(world_rect.min, Vec2::new(-640., -360.));
+        assert_eq!(world_rect.max, Vec2::new(640., 360.));
+        let default_rect = WindowConfig::default().world_rect();
+        assert_eq!(default_rect.max - default_rect.min, SCREEN_SIZE);
+    }
+
+    #[test]
+    fn edge_behavior_reads_one_mode_or_one_per_axis() {
+        let edges = |toml: &str| toml::from_str::<BoidParams>(toml).unwrap().edge_behavior;
+        assert_eq!(
+            edges(r#"edge_behavior = "Wrap""#),
+            EdgeBehavior::uniform(EdgeMode::Wrap)
+        );
+        assert_eq!(
+            edges(r#"edge_behavior = { x = "Wrap", y = "Bounce" }"#),
+            EdgeBehavior {
+                x: EdgeMode::Wrap,
+                y: EdgeMode::Bounce
+            }
+        );
+    }
+}
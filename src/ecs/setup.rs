@@ -1,86 +1,859 @@
-use bevy::{prelude::*, sprite::Rect};
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::{MaterialMesh2dBundle, Rect},
+};
 use rand::prelude::*;
 
-use crate::util::quadtree::quadtree_stats::QuadtreeStats;
+use crate::util::{
+    coords::cursor_world_position,
+    rect::{centered_rect, grid_points, rect_contains_rect},
+};
 
 use super::{
-    components::{Boid, Collider, Kinematics},
-    resources::{EntityQuadtree, EntityWrapper},
+    components::{
+        Attractor, Boid, BoidSpawnRejected, Collider, Kinematics, Layer, Leader,
+        PreviousAcceleration, PreviousVelocity, QuadtreeRect, SpawnAnim, Species, StallWatch,
+    },
+    resources::{
+        AttractorConfigs, BoidMeshes, EntityQuadtree, EntityWrapper, Integrator, MaxBoids,
+        ObstacleQuadtree, PhysicsConfig, RenderMode, ReplayBuffer, SettlePhase, SpawnAlignment,
+        SpawnConfig, WorldBounds,
+    },
 };
 
 pub const BOID_SPEED: f32 = 100.;
 pub const BOID_SCALE: Vec2 = Vec2::new(2.5, 2.5);
 pub const BOID_COUNT: IVec2 = IVec2::new(75, 68);
-pub const BOID_DIAG_LENGTH: f32 = 3.53553390593; //sqrt(2)*2.5
-pub const BOID_DIAG_LEN_RECIP: f32 = 0.28284271247; //1/(sqrt(2)*2.5)
+pub const BOID_DIAG_LENGTH: f32 = 3.535_534; //sqrt(2)*2.5
+pub const SPAWN_ANIM_DURATION: f32 = 0.25;
 pub const BOID_SPAWN_SPACING: Vec2 = Vec2::new(12., 6.);
 pub const BOID_SPAWN_OFFSET: Vec2 = Vec2::new(
     BOID_COUNT.x as f32 * BOID_SPAWN_SPACING.x / 2.,
     BOID_COUNT.y as f32 * BOID_SPAWN_SPACING.y / 2.,
 );
+pub const BOID_COLOR: Color = Color::AQUAMARINE;
+pub const LEADER_COLOR: Color = Color::ORANGE_RED;
+pub const OBSTACLE_COLOR: Color = Color::DARK_GRAY;
+// boids draw over obstacles and under the velocity overlay (z = 1)
+pub const BOID_Z: f32 = 0.5;
+pub const OBSTACLE_Z: f32 = 0.;
+pub const ATTRACTOR_COLOR: Color = Color::VIOLET;
+pub const ATTRACTOR_SIZE: Vec2 = Vec2::new(8., 8.);
+// hue step between generated species colors, the golden angle keeps neighbors far apart
+const SPECIES_HUE_STEP: f32 = 137.508;
+const SPECIES_SATURATION: f32 = 0.7;
+const SPECIES_LIGHTNESS: f32 = 0.6;
+// each Layer is drawn this much above the previous one, MAX_LAYERS of them stay under the
+// velocity overlay
+pub const LAYER_Z_SPACING: f32 = 0.05;
+pub const MAX_LAYERS: u8 = 8;
+// spawn_single_boid copies the mean velocity of boids this close to the spawn point
+pub const INHERIT_VELOCITY_RADIUS: f32 = 30.;
+// below this mean speed the neighbors' headings cancel out and a random velocity is used instead
+const INHERIT_MIN_SPEED: f32 = 1.;
+// (center, size) of each static obstacle
+pub const OBSTACLES: [(Vec2, Vec2); 2] = [
+    (Vec2::new(-600., 0.), Vec2::new(80., 240.)),
+    (Vec2::new(600., 0.), Vec2::new(80., 240.)),
+];
 
 /* Public Functions */
 
-pub fn spawn_boids(mut commands: Commands, mut quadtree: ResMut<EntityQuadtree>) {
+// runs in PreStartup so spawn_boids can use the handles, only needed by the mesh render modes
+pub fn setup_boid_meshes(
+    mut commands: Commands,
+    spawn_config: Res<SpawnConfig>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if !spawn_config.render_mode.uses_meshes() {
+        return;
+    }
+    let mesh = if spawn_config.render_mode == RenderMode::Triangle {
+        boid_triangle_mesh()
+    } else {
+        Mesh::from(shape::Quad::new(Vec2::ONE))
+    };
+    commands.insert_resource(BoidMeshes {
+        mesh: meshes.add(mesh).into(),
+        species_materials: (0..spawn_config.species_count)
+            .map(|species| {
+                materials.add(ColorMaterial::from(species_color(
+                    &spawn_config.species_colors,
+                    species,
+                )))
+            })
+            .collect(),
+        leader_material: materials.add(ColorMaterial::from(LEADER_COLOR)),
+    });
+}
+
+pub fn spawn_boids(
+    mut commands: Commands,
+    mut quadtree: ResMut<EntityQuadtree>,
+    spawn_config: Res<SpawnConfig>,
+    boid_meshes: Option<Res<BoidMeshes>>,
+    max_boids: Res<MaxBoids>,
+) {
+    populate_flock(
+        &mut commands,
+        &mut quadtree,
+        &spawn_config,
+        boid_meshes.as_deref(),
+        max_boids.0,
+    );
+}
+
+// despawn the whole flock and spawn a fresh one from the current SpawnConfig
+#[allow(clippy::too_many_arguments)]
+pub fn reset_simulation(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    boid_query: Query<Entity, With<Boid>>,
+    mut quadtree: ResMut<EntityQuadtree>,
+    spawn_config: Res<SpawnConfig>,
+    boid_meshes: Option<Res<BoidMeshes>>,
+    bounds: Res<WorldBounds>,
+    mut settle: ResMut<SettlePhase>,
+    mut replay: ResMut<ReplayBuffer>,
+    max_boids: Res<MaxBoids>,
+) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+    for entity in boid_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    // the quadtree may have been refit around the old flock
+    *quadtree = EntityQuadtree::empty(bounds.rect);
+    *settle = SettlePhase::new(spawn_config.settle_ticks);
+    // the recorded frames refer to the despawned boids
+    replay.clear();
+    populate_flock(
+        &mut commands,
+        &mut quadtree,
+        &spawn_config,
+        boid_meshes.as_deref(),
+        max_boids.0,
+    );
+}
+
+// boid_meshes is None when spawning sprites, the grid is cut short after max_boids boids
+pub fn populate_flock(
+    commands: &mut Commands,
+    quadtree: &mut EntityQuadtree,
+    spawn_config: &SpawnConfig,
+    boid_meshes: Option<&BoidMeshes>,
+    max_boids: usize,
+) {
     let mut rng = rand::thread_rng();
+    // spread leaders evenly through the spawn order
+    let total_count = (BOID_COUNT.x * BOID_COUNT.y) as usize;
+    let leader_spacing = (total_count / spawn_config.leader_count.max(1)).max(1);
+    let mut values = Vec::with_capacity(total_count);
+    let mut jitter_rng = StdRng::seed_from_u64(spawn_config.jitter_seed);
+    let (cols, rows) = (BOID_COUNT.x as usize, BOID_COUNT.y as usize);
+    // cells are BOID_SPAWN_SPACING wide, so boids sit a spacing apart around the screen center
+    let grid_rect = Rect {
+        min: -BOID_SPAWN_OFFSET - BOID_SPAWN_SPACING / 2.,
+        max: BOID_SPAWN_OFFSET - BOID_SPAWN_SPACING / 2.,
+    };
     // create (count.x * count.y) boids
-    for x_i32 in 0..BOID_COUNT.x {
-        for y_i32 in 0..BOID_COUNT.y {
-            // center boids on screen
-            let translation = Vec2::new(
-                (x_i32 as f32) * BOID_SPAWN_SPACING.x - BOID_SPAWN_OFFSET.x,
-                (y_i32 as f32) * BOID_SPAWN_SPACING.y - BOID_SPAWN_OFFSET.y,
-            );
-            let velocity = Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))
-                .normalize_or_zero()
-                .extend(0.)
-                * BOID_SPEED;
-            //spawn boid
-            let entity = commands
-                .spawn()
-                .insert(Boid)
-                .insert(Kinematics {
-                    velocity: velocity.clone(),
-                    acceleration: Vec3::ZERO,
-                })
-                .insert(Collider)
-                .insert_bundle(create_boid_sprite(
-                    translation.extend(0.),
-                    BOID_SCALE.extend(0.),
-                ))
-                .id();
-            //add to quadtree
-            let rect = Rect {
-                min: translation.clone(),
-                max: translation + BOID_SCALE,
-            };
-            quadtree.add(EntityWrapper {
-                entity,
-                rect,
-                velocity,
+    for (index, grid_translation) in grid_points(&grid_rect, cols, rows)
+        .enumerate()
+        .take(max_boids)
+    {
+        let is_leader =
+            index % leader_spacing == 0 && index / leader_spacing < spawn_config.leader_count;
+        let species = species_for_column(index / rows, spawn_config.species_count);
+        let layer = Layer((index % spawn_config.layer_count.max(1) as usize) as u8);
+        let jittered = grid_translation + spawn_jitter(&mut jitter_rng, spawn_config.spawn_jitter);
+        // a nudge that would push the boid out of the quadtree keeps it on the grid instead
+        let jittered_rect = boid_rect(spawn_config.alignment, jittered);
+        let translation = if rect_contains_rect(&quadtree.root.rect, &jittered_rect) {
+            jittered
+        } else {
+            grid_translation
+        };
+        let velocity = random_velocity(&mut rng, spawn_config.initial_speed);
+        values.push(spawn_boid(
+            commands,
+            spawn_config,
+            boid_meshes,
+            translation,
+            velocity,
+            species,
+            layer,
+            is_leader,
+        ));
+    }
+    quadtree.extend(values);
+    quadtree.validate();
+}
+
+// add one boid at position, moving with the boids around it if spawn_config.inherit_velocity is
+// set and there are any, otherwise in a random direction
+pub fn spawn_single_boid(
+    commands: &mut Commands,
+    quadtree: &mut EntityQuadtree,
+    spawn_config: &SpawnConfig,
+    boid_meshes: Option<&BoidMeshes>,
+    position: Vec2,
+) -> Entity {
+    let inherited = if spawn_config.inherit_velocity {
+        average_velocity(&quadtree.query_radius(position, INHERIT_VELOCITY_RADIUS))
+    } else {
+        None
+    };
+    let velocity = inherited
+        .unwrap_or_else(|| random_velocity(&mut rand::thread_rng(), spawn_config.initial_speed));
+    let value = spawn_boid(
+        commands,
+        spawn_config,
+        boid_meshes,
+        position,
+        velocity,
+        0,
+        Layer::default(),
+        false,
+    );
+    let entity = value.entity;
+    quadtree.add(value);
+    entity
+}
+
+// right click drops a new boid into the flock under the cursor, unless it's already at MaxBoids
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_boid_at_cursor(
+    mut commands: Commands,
+    mouse: Res<Input<MouseButton>>,
+    mut quadtree: ResMut<EntityQuadtree>,
+    spawn_config: Res<SpawnConfig>,
+    boid_meshes: Option<Res<BoidMeshes>>,
+    windows: Res<Windows>,
+    camera_query: Query<&GlobalTransform, With<Camera>>,
+    boid_query: Query<(), With<Boid>>,
+    max_boids: Res<MaxBoids>,
+    mut rejected_events: EventWriter<BoidSpawnRejected>,
+) {
+    if !mouse.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let cursor = windows
+        .get_primary()
+        .zip(camera_query.get_single().ok())
+        .and_then(|(window, camera)| cursor_world_position(camera, window));
+    if let Some(cursor) = cursor {
+        spawn_capped_boid(
+            &mut commands,
+            &mut quadtree,
+            &spawn_config,
+            boid_meshes.as_deref(),
+            boid_query.iter().count(),
+            &max_boids,
+            &mut rejected_events,
+            cursor,
+        );
+    }
+}
+
+// spawn_single_boid, unless boid_count is already at max_boids; the rejected spawn is reported
+// with a BoidSpawnRejected event instead
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_capped_boid(
+    commands: &mut Commands,
+    quadtree: &mut EntityQuadtree,
+    spawn_config: &SpawnConfig,
+    boid_meshes: Option<&BoidMeshes>,
+    boid_count: usize,
+    max_boids: &MaxBoids,
+    rejected_events: &mut EventWriter<BoidSpawnRejected>,
+    position: Vec2,
+) -> Option<Entity> {
+    if boid_count >= max_boids.0 {
+        rejected_events.send(BoidSpawnRejected { position });
+        return None;
+    }
+    Some(spawn_single_boid(
+        commands,
+        quadtree,
+        spawn_config,
+        boid_meshes,
+        position,
+    ))
+}
+
+// mean velocity of the given boids, None if there are none or they cancel out
+pub fn average_velocity(neighbors: &[&EntityWrapper]) -> Option<Vec3> {
+    if neighbors.is_empty() {
+        return None;
+    }
+    let sum = neighbors
+        .iter()
+        .fold(Vec3::ZERO, |sum, value| sum + value.velocity);
+    let average = sum / neighbors.len() as f32;
+    if average.truncate().length_squared() > INHERIT_MIN_SPEED * INHERIT_MIN_SPEED {
+        Some(average)
+    } else {
+        None
+    }
+}
+
+pub fn spawn_obstacles(mut commands: Commands) {
+    for (center, size) in OBSTACLES {
+        commands
+            .spawn()
+            .insert(Collider)
+            .insert_bundle(SpriteBundle {
+                transform: Transform {
+                    scale: size.extend(1.),
+                    translation: center.extend(OBSTACLE_Z),
+                    ..default()
+                },
+                sprite: Sprite {
+                    color: OBSTACLE_COLOR,
+                    ..default()
+                },
+                ..default()
             });
-        }
     }
-    QuadtreeStats::calculate(&quadtree).print();
+}
+
+pub fn spawn_attractors(mut commands: Commands, attractors: Res<AttractorConfigs>) {
+    for config in &attractors.0 {
+        let position = Vec2::from(config.position);
+        commands
+            .spawn()
+            .insert(Attractor {
+                position,
+                strength: config.strength,
+                radius: config.radius,
+            })
+            .insert_bundle(SpriteBundle {
+                transform: Transform {
+                    scale: ATTRACTOR_SIZE.extend(1.),
+                    translation: position.extend(OBSTACLE_Z),
+                    ..default()
+                },
+                sprite: Sprite {
+                    color: ATTRACTOR_COLOR,
+                    ..default()
+                },
+                ..default()
+            });
+    }
+}
+
+// runs after spawn_obstacles has been applied; obstacles never move so this is done once
+#[allow(clippy::type_complexity)]
+pub fn build_obstacle_quadtree(
+    obstacle_query: Query<(Entity, &Transform), (With<Collider>, Without<Boid>)>,
+    mut obstacles: ResMut<ObstacleQuadtree>,
+) {
+    for (entity, transform) in obstacle_query.iter() {
+        // obstacle sprites are centered on their translation
+        let rect = centered_rect(transform.translation.truncate(), transform.scale.truncate());
+        obstacles.0.add(EntityWrapper {
+            entity,
+            rect,
+            velocity: Vec3::ZERO,
+        });
+    }
+}
+
+// runs once the flock exists; Verlet would silently leave acceleration out of the velocity of any
+// boid without a PreviousAcceleration, so fall back to Euler instead
+pub fn validate_integrator(
+    mut physics: ResMut<PhysicsConfig>,
+    missing_query: Query<(), (With<Boid>, Without<PreviousAcceleration>)>,
+) {
+    if physics.integrator.needs_previous_acceleration() && !missing_query.is_empty() {
+        warn!(
+            "{:?} integration needs PreviousAcceleration on every boid, falling back to {:?}",
+            physics.integrator,
+            Integrator::Euler
+        );
+        physics.integrator = Integrator::Euler;
+    }
 }
 
 pub fn setup_camera(mut commands: Commands) {
     commands.spawn_bundle(Camera2dBundle::default());
 }
 
+// configured color of a species, or a generated one once the configured colors run out
+pub fn species_color(species_colors: &[Color], species: usize) -> Color {
+    match species_colors.get(species) {
+        Some(color) => *color,
+        None => Color::hsl(
+            (species as f32 * SPECIES_HUE_STEP) % 360.,
+            SPECIES_SATURATION,
+            SPECIES_LIGHTNESS,
+        ),
+    }
+}
+
+// spawn grid columns are split into species_count bands of (nearly) equal width
+pub fn species_for_column(column: usize, species_count: usize) -> usize {
+    column * species_count.max(1) / BOID_COUNT.x as usize
+}
+
 /* Internal-only Functions */
 
-fn create_boid_sprite(translation: Vec3, scale: Vec3) -> SpriteBundle {
-    SpriteBundle {
-        transform: Transform {
-            scale,
-            translation,
-            ..default()
-        },
-        sprite: Sprite {
-            color: Color::AQUAMARINE,
-            ..default()
+// spawn a boid scaling in at translation, returns the value to store in the quadtree
+#[allow(clippy::too_many_arguments)]
+fn spawn_boid(
+    commands: &mut Commands,
+    spawn_config: &SpawnConfig,
+    boid_meshes: Option<&BoidMeshes>,
+    translation: Vec2,
+    velocity: Vec3,
+    species: usize,
+    layer: Layer,
+    is_leader: bool,
+) -> EntityWrapper {
+    let mut boid = commands.spawn();
+    boid.insert(Boid)
+        .insert(Kinematics {
+            velocity,
+            acceleration: Vec3::ZERO,
+            preferred_speed: spawn_config.initial_speed,
+        })
+        .insert(PreviousVelocity(velocity))
+        .insert(PreviousAcceleration::default())
+        .insert(StallWatch::new(translation))
+        .insert(Collider)
+        .insert(Species(species))
+        .insert(layer)
+        .insert(SpawnAnim::spawning());
+    // scaled up to BOID_SCALE by animate_spawn_transitions
+    let transform = Transform {
+        translation: translation.extend(spawn_config.z + layer.0 as f32 * LAYER_Z_SPACING),
+        scale: Vec3::ZERO,
+        ..default()
+    };
+    match boid_meshes {
+        Some(boid_meshes) => {
+            boid.insert_bundle(create_boid_mesh(transform, boid_meshes, species, is_leader));
+        }
+        None => {
+            let color = if is_leader {
+                LEADER_COLOR
+            } else {
+                species_color(&spawn_config.species_colors, species)
+            };
+            boid.insert_bundle(create_boid_sprite(transform, color));
+        }
+    }
+    if is_leader {
+        boid.insert(Leader);
+    }
+    let entity = boid.id();
+    let rect = boid_rect(spawn_config.alignment, translation);
+    boid.insert(QuadtreeRect(rect));
+    EntityWrapper {
+        entity,
+        rect,
+        velocity,
+    }
+}
+
+// rect a full-size boid at translation will occupy
+fn boid_rect(alignment: SpawnAlignment, translation: Vec2) -> Rect {
+    match alignment {
+        SpawnAlignment::Corner => Rect {
+            min: translation,
+            max: translation + BOID_SCALE,
         },
+        SpawnAlignment::Center => centered_rect(translation, BOID_SCALE),
+    }
+}
+
+// uniformly spread over the disk of radius max_offset
+fn spawn_jitter(rng: &mut impl Rng, max_offset: f32) -> Vec2 {
+    if max_offset <= 0. {
+        return Vec2::ZERO;
+    }
+    let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+    // sqrt keeps offsets from bunching up near the grid point
+    let radius = max_offset * rng.gen::<f32>().sqrt();
+    (Vec2::new(angle.cos(), angle.sin()) * radius).clamp_length_max(max_offset)
+}
+
+fn random_velocity(rng: &mut impl Rng, speed: f32) -> Vec3 {
+    Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))
+        .normalize_or_zero()
+        .extend(0.)
+        * speed
+}
+
+// unit triangle pointing along +Y, inscribed in the circle of radius 0.5 so it stays inside the
+// boid's unrotated rect however orient_to_velocity turns it
+fn boid_triangle_mesh() -> Mesh {
+    let half_base = 0.25 * 3f32.sqrt();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        vec![
+            [0., 0.5, 0.],
+            [-half_base, -0.25, 0.],
+            [half_base, -0.25, 0.],
+        ],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; 3]);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_UV_0,
+        vec![[0.5, 0.], [0.5 - half_base, 0.75], [0.5 + half_base, 0.75]],
+    );
+    // counter-clockwise, facing the camera
+    mesh.set_indices(Some(Indices::U32(vec![0, 1, 2])));
+    mesh
+}
+
+fn create_boid_sprite(transform: Transform, color: Color) -> SpriteBundle {
+    SpriteBundle {
+        transform,
+        sprite: Sprite { color, ..default() },
         ..default()
     }
 }
+
+// every boid shares one mesh and one of two materials
+fn create_boid_mesh(
+    transform: Transform,
+    boid_meshes: &BoidMeshes,
+    species: usize,
+    is_leader: bool,
+) -> MaterialMesh2dBundle<ColorMaterial> {
+    let material = if is_leader {
+        &boid_meshes.leader_material
+    } else {
+        &boid_meshes.species_materials[species]
+    };
+    MaterialMesh2dBundle {
+        mesh: boid_meshes.mesh.clone(),
+        material: material.clone(),
+        transform,
+        ..default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{asset::HandleId, render::mesh::VertexAttributeValues, sprite::Mesh2dHandle};
+
+    use super::*;
+    use crate::ecs::{
+        systems::{avoid_obstacles, orient_to_velocity},
+        test_support::{run_system, spawn_test_boid, test_world, velocity_of},
+    };
+
+    #[test]
+    fn obstacle_tree_is_built_once_and_only_read_by_physics() {
+        let mut world = test_world();
+        run_system(&mut world, spawn_obstacles);
+        run_system(&mut world, build_obstacle_quadtree);
+        assert_eq!(world.resource::<ObstacleQuadtree>().len(), OBSTACLES.len());
+        // just right of the first obstacle and above its center, flying into it
+        let (center, size) = OBSTACLES[0];
+        let boid = spawn_test_boid(
+            &mut world,
+            center + Vec2::new(size.x / 2. + 1., 20.),
+            Vec2::new(-BOID_SPEED, 0.),
+        );
+        world.clear_trackers();
+        run_system(&mut world, avoid_obstacles);
+        assert!(!world.is_resource_changed::<ObstacleQuadtree>());
+        assert!(velocity_of(&world, boid).y > 0.);
+    }
+
+    #[test]
+    fn spawned_boids_move_at_the_configured_speed() {
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().initial_speed = 42.;
+        world.insert_resource(MaxBoids(50));
+        run_system(&mut world, spawn_boids);
+        let mut kinematics_query = world.query::<&Kinematics>();
+        assert_eq!(kinematics_query.iter(&world).count(), 50);
+        for kinematics in kinematics_query.iter(&world) {
+            assert!((kinematics.velocity.length() - 42.).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn center_aligned_boids_are_centered_on_their_grid_position() {
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().alignment = SpawnAlignment::Center;
+        world.insert_resource(MaxBoids(30));
+        run_system(&mut world, spawn_boids);
+        let mut boid_query = world.query::<(&Transform, &QuadtreeRect)>();
+        assert_eq!(boid_query.iter(&world).count(), 30);
+        for (transform, rect) in boid_query.iter(&world) {
+            let center = (rect.0.min + rect.0.max) / 2.;
+            assert!(center.abs_diff_eq(transform.translation.truncate(), 0.0001));
+            assert!((rect.0.max - rect.0.min).abs_diff_eq(BOID_SCALE, 0.0001));
+        }
+        // the default keeps the grid position on the rect's corner
+        let mut world = test_world();
+        world.insert_resource(MaxBoids(30));
+        run_system(&mut world, spawn_boids);
+        for (transform, rect) in world.query::<(&Transform, &QuadtreeRect)>().iter(&world) {
+            assert_eq!(rect.0.min, transform.translation.truncate());
+        }
+    }
+
+    #[test]
+    fn spawned_boids_sit_at_the_configured_z() {
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().z = 7.5;
+        world.insert_resource(MaxBoids(20));
+        run_system(&mut world, spawn_boids);
+        let mut transforms = world.query_filtered::<&Transform, With<Boid>>();
+        assert_eq!(transforms.iter(&world).count(), 20);
+        for transform in transforms.iter(&world) {
+            assert_eq!(transform.translation.z, 7.5);
+        }
+    }
+
+    #[test]
+    fn reset_replaces_the_flock_and_repopulates_the_quadtree() {
+        let mut world = test_world();
+        world.insert_resource(MaxBoids(40));
+        run_system(&mut world, spawn_boids);
+        let mut boid_query = world.query_filtered::<Entity, With<Boid>>();
+        let old_flock: Vec<Entity> = boid_query.iter(&world).collect();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::R);
+        world.insert_resource(keys);
+        run_system(&mut world, reset_simulation);
+        let new_flock: Vec<Entity> = boid_query.iter(&world).collect();
+        assert_eq!(new_flock.len(), 40);
+        assert!(new_flock.iter().all(|boid| !old_flock.contains(boid)));
+        let quadtree = world.resource::<EntityQuadtree>();
+        assert_eq!(quadtree.len(), 40);
+        for value in quadtree.values_intersecting(&quadtree.rect) {
+            assert!(new_flock.contains(&value.entity));
+        }
+    }
+
+    #[test]
+    fn instanced_flock_spawns_one_shared_mesh_instance_per_boid() {
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().render_mode = RenderMode::Instanced;
+        world.insert_resource(MaxBoids(60));
+        let mesh = Handle::weak(HandleId::random::<Mesh>());
+        let species_material = Handle::weak(HandleId::random::<ColorMaterial>());
+        let leader_material = Handle::weak(HandleId::random::<ColorMaterial>());
+        world.insert_resource(BoidMeshes {
+            mesh: mesh.clone().into(),
+            species_materials: vec![species_material.clone()],
+            leader_material: leader_material.clone(),
+        });
+        run_system(&mut world, spawn_boids);
+        let mut instances = world
+            .query_filtered::<(&Mesh2dHandle, &Handle<ColorMaterial>, Option<&Leader>), With<Boid>>(
+            );
+        assert_eq!(instances.iter(&world).count(), 60);
+        for (instance_mesh, material, leader) in instances.iter(&world) {
+            assert_eq!(instance_mesh.0, mesh);
+            let expected = if leader.is_some() {
+                &leader_material
+            } else {
+                &species_material
+            };
+            assert_eq!(material, expected);
+        }
+        assert_eq!(world.query::<&Sprite>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn triangle_flock_spawns_the_shared_triangle_mesh_on_every_boid() {
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().render_mode = RenderMode::Triangle;
+        world.insert_resource(MaxBoids(60));
+        let mesh = Handle::weak(HandleId::random::<Mesh>());
+        world.insert_resource(BoidMeshes {
+            mesh: mesh.clone().into(),
+            species_materials: vec![Handle::weak(HandleId::random::<ColorMaterial>())],
+            leader_material: Handle::weak(HandleId::random::<ColorMaterial>()),
+        });
+        run_system(&mut world, spawn_boids);
+        let mut boids = world.query_filtered::<Option<&Mesh2dHandle>, With<Boid>>();
+        assert_eq!(boids.iter(&world).count(), 60);
+        for boid_mesh in boids.iter(&world) {
+            assert_eq!(boid_mesh.map(|handle| &handle.0), Some(&mesh));
+        }
+        assert_eq!(world.query::<&Sprite>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn boids_take_their_species_color() {
+        let mut world = test_world();
+        {
+            let mut spawn_config = world.resource_mut::<SpawnConfig>();
+            // one species per grid column, only the first two have a configured color
+            spawn_config.species_count = BOID_COUNT.x as usize;
+            spawn_config.species_colors = vec![Color::RED, Color::GREEN];
+        }
+        world.insert_resource(MaxBoids(3 * BOID_COUNT.y as usize));
+        run_system(&mut world, spawn_boids);
+        let mut boid_query = world.query_filtered::<(&Species, &Sprite), Without<Leader>>();
+        let mut seen = [false; 3];
+        for (species, sprite) in boid_query.iter(&world) {
+            let expected = match species.0 {
+                0 => Color::RED,
+                1 => Color::GREEN,
+                _ => species_color(&[], species.0),
+            };
+            assert_eq!(sprite.color, expected);
+            seen[species.0] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+        // generated colors still tell neighboring species apart
+        assert_ne!(species_color(&[], 2), species_color(&[], 3));
+    }
+
+    #[test]
+    fn single_boid_inherits_its_neighbors_mean_velocity() {
+        let mut world = test_world();
+        let neighbors = [
+            spawn_test_boid(&mut world, Vec2::new(-5., 0.), Vec2::new(20., 40.)),
+            spawn_test_boid(&mut world, Vec2::new(5., 0.), Vec2::new(40., 40.)),
+        ];
+        // too far out to count
+        spawn_test_boid(&mut world, Vec2::new(200., 0.), Vec2::new(-300., 0.));
+        run_system(
+            &mut world,
+            |mut commands: Commands,
+             mut quadtree: ResMut<EntityQuadtree>,
+             spawn_config: Res<SpawnConfig>| {
+                spawn_single_boid(
+                    &mut commands,
+                    &mut quadtree,
+                    &spawn_config,
+                    None,
+                    Vec2::ZERO,
+                );
+            },
+        );
+        let mut boid_query = world.query_filtered::<Entity, With<SpawnAnim>>();
+        let spawned = boid_query.single(&world);
+        assert!(!neighbors.contains(&spawned));
+        assert_eq!(velocity_of(&world, spawned), Vec2::new(30., 40.));
+        assert_eq!(world.resource::<EntityQuadtree>().len(), 4);
+    }
+
+    #[test]
+    fn spawns_past_max_boids_are_rejected_with_an_event() {
+        let mut world = test_world();
+        world.insert_resource(MaxBoids(3));
+        for i in 0..5 {
+            let position = Vec2::new(i as f32 * 100., 0.);
+            run_system(
+                &mut world,
+                move |mut commands: Commands,
+                      mut quadtree: ResMut<EntityQuadtree>,
+                      spawn_config: Res<SpawnConfig>,
+                      boid_query: Query<(), With<Boid>>,
+                      max_boids: Res<MaxBoids>,
+                      mut rejected_events: EventWriter<BoidSpawnRejected>| {
+                    spawn_capped_boid(
+                        &mut commands,
+                        &mut quadtree,
+                        &spawn_config,
+                        None,
+                        boid_query.iter().count(),
+                        &max_boids,
+                        &mut rejected_events,
+                        position,
+                    );
+                },
+            );
+        }
+        assert_eq!(world.query::<&Boid>().iter(&world).count(), 3);
+        assert_eq!(world.resource::<EntityQuadtree>().len(), 3);
+        let events = world.resource::<Events<BoidSpawnRejected>>();
+        let rejected: Vec<Vec2> = events
+            .get_reader()
+            .iter(events)
+            .map(|event| event.position)
+            .collect();
+        assert_eq!(rejected, vec![Vec2::new(300., 0.), Vec2::new(400., 0.)]);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_bound_and_repeats_for_a_seed() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for max_offset in [0., 0.5, 3., 1000.] {
+            for _ in 0..10_000 {
+                assert!(spawn_jitter(&mut rng, max_offset).length() <= max_offset);
+            }
+        }
+
+        let layout = |seed: u64| {
+            let mut world = test_world();
+            {
+                let mut spawn_config = world.resource_mut::<SpawnConfig>();
+                spawn_config.spawn_jitter = 2.;
+                spawn_config.jitter_seed = seed;
+            }
+            world.insert_resource(MaxBoids(200));
+            run_system(&mut world, spawn_boids);
+            let mut boids: Vec<(Entity, Vec2)> = world
+                .query_filtered::<(Entity, &Transform), With<Boid>>()
+                .iter(&world)
+                .map(|(entity, transform)| (entity, transform.translation.truncate()))
+                .collect();
+            boids.sort_unstable_by_key(|(entity, _)| *entity);
+            boids
+                .into_iter()
+                .map(|(_, position)| position)
+                .collect::<Vec<_>>()
+        };
+        let grid_rect = Rect {
+            min: -BOID_SPAWN_OFFSET - BOID_SPAWN_SPACING / 2.,
+            max: BOID_SPAWN_OFFSET - BOID_SPAWN_SPACING / 2.,
+        };
+        let grid = grid_points(&grid_rect, BOID_COUNT.x as usize, BOID_COUNT.y as usize);
+        let positions = layout(7);
+        assert_eq!(positions.len(), 200);
+        let mut moved = 0;
+        for (position, grid_point) in positions.iter().zip(grid) {
+            let offset = position.distance(grid_point);
+            assert!(offset <= 2. + 1e-4, "offset {}", offset);
+            moved += (offset > 0.) as usize;
+        }
+        assert!(moved > 0);
+        assert_eq!(layout(7), positions);
+        assert_ne!(layout(8), positions);
+    }
+
+    #[test]
+    fn triangle_boids_point_along_their_velocity_and_stay_inside_their_rect() {
+        let positions = match boid_triangle_mesh().attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+            _ => panic!("triangle mesh has no positions"),
+        };
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().render_mode = RenderMode::Triangle;
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        for step in 0..16 {
+            let heading = Vec2::from_angle(step as f32 * std::f32::consts::TAU / 16.);
+            world.get_mut::<Kinematics>(boid).unwrap().velocity = (heading * 50.).extend(0.);
+            run_system(&mut world, orient_to_velocity);
+            let rotation = world.get::<Transform>(boid).unwrap().rotation;
+            // the tip, the mesh's +Y, turns to the heading
+            assert!((rotation * Vec3::Y).truncate().abs_diff_eq(heading, 1e-5));
+            for position in &positions {
+                let turned = rotation * Vec3::from(*position);
+                assert!(turned.truncate().abs().cmple(Vec2::splat(0.5 + 1e-5)).all());
+            }
+        }
+        // a stopped boid keeps its heading
+        let rotation = world.get::<Transform>(boid).unwrap().rotation;
+        world.get_mut::<Kinematics>(boid).unwrap().velocity = Vec3::ZERO;
+        run_system(&mut world, orient_to_velocity);
+        assert_eq!(world.get::<Transform>(boid).unwrap().rotation, rotation);
+    }
+}
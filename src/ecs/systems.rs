@@ -1,168 +1,1076 @@
-use bevy::prelude::*;
+use std::{cell::Cell, time::Instant};
+
+use bevy::{
+    ecs::schedule::ShouldRun,
+    prelude::*,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle, Rect},
+    utils::{HashMap, HashSet},
+};
+use rand::prelude::*;
 
 use crate::util::{
-    quadtree::{quadtree_stats::QuadtreeStats, quadtree_value::QuadtreeValue},
-    rect::{magnify_rect, transform_to_rect},
+    coords::cursor_world_position,
+    quadtree::{
+        neighbor_query::NeighborQuery, quadtree_node::QuadtreeNode, quadtree_value::QuadtreeValue,
+        query_scratch::QueryScratch,
+    },
+    rect::{
+        centered_rect, circles_overlap, magnify_rect, rect_contains_rect, rect_intersects_rect,
+        rect_union_all, segment_intersects_rect,
+    },
 };
 
 use super::{
-    components::{Boid, Kinematics},
-    resources::{EntityQuadtree, EntityWrapper},
-    setup::{BOID_DIAG_LENGTH, BOID_DIAG_LEN_RECIP, BOID_SCALE},
-    PHYSICS_FRAME_RATE,
+    components::{
+        Alarmed, Attractor, Boid, BoidEscaped, BoidStalled, ClusterId, Frozen, Impulse, Kinematics,
+        Layer, Leader, PreviousAcceleration, PreviousVelocity, QuadtreeRect, SpawnAnim, StallWatch,
+        WrapGhost,
+    },
+    flock_stats::{detect_clusters, FlockStats},
+    resources::{
+        AlignmentMode, BehaviorMask, BoidParams, BoidSnapshot, CollisionShape, EdgeBehavior,
+        EdgeMode, EntityQuadtree, EntityWrapper, Integrator, NeighborStrategy, ObstacleQuadtree,
+        PerfBudget, PhysicsConfig, PhysicsPause, QuadtreeFit, RenderMode, ReplayBuffer, Scatter,
+        SeparationFalloff, SettlePhase, SpawnAlignment, SpawnConfig, WorldBounds,
+    },
+    setup::{species_color, BOID_DIAG_LENGTH, BOID_SCALE, BOID_SPEED, SPAWN_ANIM_DURATION},
 };
 
 const EPS: f32 = 0.00001;
-const DELTA_TIME_FIXED: f32 = 1. / PHYSICS_FRAME_RATE as f32;
-const BOID_DETECTION_RADIUS: f32 = 1.5;
-const BOID_GROUP_APPROACH_RADIUS: f32 = 2.;
-const BOID_AVOID_DAMPENING: f32 = 0.03;
-const BOID_APPROACH_DAMPENING: f32 = 0.015;
+const BOID_COHESION_DAMPENING: f32 = 0.01;
+const BOID_OBSTACLE_DETECTION_RADIUS: f32 = 10.;
+const BOID_OBSTACLE_DAMPENING: f32 = 0.1;
+const LEADER_WANDER_ANGLE: f32 = 0.05;
+// speed added to every boid by one arrow key press
+const IMPULSE_SPEED: f32 = 50.;
+// headings sampled by seek_open_space, straight ahead first so ties keep the current course
+const OPEN_SPACE_SAMPLE_ANGLES: [f32; 5] = [0., -0.4, 0.4, -0.8, 0.8];
+
+// how far inside the bounds detect_escaped_boids puts an escaped boid; containment is strict, and
+// EPS is below f32 resolution at world coordinates of a few hundred
+const ESCAPE_INSET: f32 = 0.01;
+
+// extra reach of NodeCache's shared query, covers rounding between the node and boid rects
+const NODE_CACHE_SLACK: f32 = 1.;
 
 // const THREADS_SMALL: usize = 8;
 const THREADS_MEDIUM: usize = 16;
 const THREADS_LARGE: usize = 32;
 
-pub fn apply_kinematics(mut boid_query: Query<(&Kinematics, &mut Transform)>) {
-    boid_query.par_for_each_mut(THREADS_LARGE, |(kinematics, mut transform)| {
-        transform.translation += kinematics.integrate_rk4(DELTA_TIME_FIXED);
+// corner bits and depth of a quadtree node, see node_key
+type NodeKey = (u32, u32, usize);
+// shared neighbor candidates per node, see node_neighbor_cache
+type NodeNeighbors<'a> = HashMap<NodeKey, Vec<&'a EntityWrapper>>;
+
+// buffers flocking reuses for every boid it steers on a thread, so the hot loop doesn't allocate
+#[derive(Default)]
+struct FlockingScratch {
+    // the boids near the one being steered, borrowed from the quadtree
+    neighbors: QueryScratch<EntityWrapper>,
+    // live velocity of each neighbor, by index
+    velocities: Vec<Vec3>,
+    // indices of the neighbors separation pushes away from
+    separating: Vec<usize>,
+}
+
+thread_local! {
+    static FLOCKING_SCRATCH: Cell<FlockingScratch> = Cell::new(FlockingScratch::default());
+}
+
+// run criteria piped after the physics set's FixedTimestep, holds the whole set while
+// PhysicsPause is on; the timestep keeps draining its accumulator meanwhile, so resuming
+// doesn't run a burst of catch-up ticks
+pub fn unless_physics_paused(In(should_run): In<ShouldRun>, pause: Res<PhysicsPause>) -> ShouldRun {
+    if pause.paused {
+        ShouldRun::No
+    } else {
+        should_run
+    }
+}
+
+// first and last systems of the physics set, timing the tick for PerfBudget
+pub fn start_physics_timer(mut budget: ResMut<PerfBudget>) {
+    budget.tick_started = Some(Instant::now());
+}
+
+pub fn stop_physics_timer(mut budget: ResMut<PerfBudget>) {
+    if let Some(started) = budget.tick_started.take() {
+        budget.record_tick(started.elapsed().as_secs_f32() * 1000.);
+    }
+}
+
+// remembers where steering starts from this tick, so anything that set velocity since the last
+// tick (impulses, replays) isn't smoothed away
+pub fn record_previous_velocity(
+    mut boid_query: Query<(&Kinematics, &mut PreviousVelocity), Without<Frozen>>,
+    params: Res<BoidParams>,
+) {
+    if params.smoothing <= 0. {
+        return;
+    }
+    boid_query.par_for_each_mut(THREADS_LARGE, |(kinematics, mut previous)| {
+        previous.0 = kinematics.velocity;
     });
 }
 
+// exponential moving average over ticks, runs after every steering system so alternating rules
+// don't make boids jitter
+pub fn smooth_velocity(
+    mut boid_query: Query<(&mut Kinematics, &PreviousVelocity), Without<Frozen>>,
+    params: Res<BoidParams>,
+) {
+    if params.smoothing <= 0. {
+        return;
+    }
+    boid_query.par_for_each_mut(THREADS_LARGE, |(mut kinematics, previous)| {
+        // recorded before sanitize_kinematics had a chance to reset it
+        if previous.0.is_finite() {
+            kinematics.velocity =
+                smoothed_velocity(previous.0, kinematics.velocity, params.smoothing);
+        }
+    });
+}
+
+pub fn apply_kinematics(
+    mut boid_query: Query<
+        (
+            &mut Kinematics,
+            &mut Transform,
+            Option<&mut PreviousAcceleration>,
+        ),
+        Without<Frozen>,
+    >,
+    physics: Res<PhysicsConfig>,
+    bounds: Res<WorldBounds>,
+    params: Res<BoidParams>,
+    mask: Res<BehaviorMask>,
+) {
+    let delta_time = physics.delta_time();
+    let substeps = physics.substeps.max(1);
+    let integrator = physics.integrator;
+    let check_edges =
+        mask.contains(BehaviorMask::EDGE) && params.edge_behavior.any(EdgeMode::Bounce);
+    boid_query.par_for_each_mut(
+        THREADS_LARGE,
+        |(mut kinematics, mut transform, previous_acceleration)| {
+            // summed forces can add up to a jump the boid visibly snaps through
+            kinematics.acceleration = kinematics
+                .acceleration
+                .clamp_length_max(params.max_acceleration);
+            for substep in 0..substeps {
+                // avoid_screen_edges already checked the first substep
+                if substep > 0 && check_edges {
+                    let displacement = kinematics.substep(delta_time, substeps, integrator);
+                    bounce_off_edges(
+                        &mut kinematics,
+                        transform.translation + displacement,
+                        &bounds.rect,
+                        params.edge_behavior,
+                        params.bounce_restitution,
+                    );
+                }
+                transform.translation += kinematics.substep(delta_time, substeps, integrator);
+            }
+            // validate_integrator made sure every boid has one before Verlet is used
+            if integrator == Integrator::Verlet {
+                if let Some(mut previous_acceleration) = previous_acceleration {
+                    kinematics.velocity =
+                        kinematics.verlet_velocity(previous_acceleration.0, delta_time);
+                    previous_acceleration.0 = kinematics.acceleration;
+                }
+            }
+        },
+    );
+}
+
+// a boid outside the world bounds can't be placed in the quadtree, report it and optionally
+// pull it back inside
+pub fn detect_escaped_boids(
+    mut boid_query: Query<(Entity, &mut Transform), With<Boid>>,
+    mut escaped_events: EventWriter<BoidEscaped>,
+    bounds: Res<WorldBounds>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    for (entity, mut transform) in boid_query.iter_mut() {
+        let rect = spawn_config.alignment.transform_to_rect(&transform);
+        if rect_contains_rect(&bounds.rect, &rect) {
+            continue;
+        }
+        escaped_events.send(BoidEscaped {
+            entity,
+            position: transform.translation.truncate(),
+        });
+        if bounds.clamp_escaped {
+            // containment is strict, so aim slightly inside the bounds
+            let inset = Vec2::splat(ESCAPE_INSET);
+            let offset = (bounds.rect.min + inset - rect.min).max(Vec2::ZERO)
+                + (bounds.rect.max - inset - rect.max).min(Vec2::ZERO);
+            transform.translation += offset.extend(0.);
+        }
+    }
+}
+
+// reports boids that have barely moved for a while, e.g. pinned in a corner with their velocity
+// flipping back and forth, and optionally kicks them free
+#[allow(clippy::type_complexity)]
+pub fn detect_stalls(
+    mut boid_query: Query<
+        (Entity, &mut Kinematics, &Transform, &mut StallWatch),
+        (With<Boid>, Without<Frozen>),
+    >,
+    mut stalled_events: EventWriter<BoidStalled>,
+    params: Res<BoidParams>,
+) {
+    if params.stall_ticks == 0 {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+    for (entity, mut kinematics, transform, mut watch) in boid_query.iter_mut() {
+        let position = transform.translation.truncate();
+        if !watch.observe(position, params.stall_distance, params.stall_ticks) {
+            continue;
+        }
+        stalled_events.send(BoidStalled { entity });
+        if params.stall_nudge {
+            let direction = Vec2::from_angle(rng.gen_range(0. ..std::f32::consts::TAU));
+            kinematics.velocity =
+                (direction * kinematics.preferred_speed).extend(kinematics.velocity.z);
+        }
+    }
+}
+
+// refresh every boid's stored rect and velocity, moving it between nodes when needed
 pub fn update_quadtree(
-    entity_query: Query<(Entity, &Kinematics, &Transform), With<Boid>>,
+    mut entity_query: Query<(Entity, &Kinematics, &Transform, &mut QuadtreeRect), With<Boid>>,
+    mut quadtree: ResMut<EntityQuadtree>,
+    spawn_config: Res<SpawnConfig>,
+    physics: Res<PhysicsConfig>,
+) {
+    entity_query.for_each_mut(|(entity, kinematics, transform, mut last_rect)| {
+        let value = EntityWrapper::new(
+            entity,
+            &kinematics.velocity,
+            transform,
+            spawn_config.alignment,
+        );
+        let rect = value.rect;
+        // flocking reads live velocities, so a skipped boid only has a slightly stale rect
+        if !needs_reindex(&last_rect.0, &rect, physics.reindex_threshold) {
+            return;
+        }
+        quadtree.relocate(&last_rect.0, value);
+        last_rect.0 = rect;
+    });
+    quadtree.refresh_bounds();
+}
+
+// drop every boid despawned this frame from the quadtree, whichever system despawned it, so
+// neighbor queries never see a stale entity; one pass over the tree however many went
+pub fn remove_despawned_boids(
+    removed: RemovedComponents<Boid>,
+    mut quadtree: ResMut<EntityQuadtree>,
+) {
+    let removed: HashSet<Entity> = removed.iter().collect();
+    if removed.is_empty() {
+        return;
+    }
+    quadtree.retain(|value| !removed.contains(&value.entity));
+}
+
+// snapshot every boid after the tick has been applied, skipped while a replay is on screen
+pub fn record_replay_frame(
+    boid_query: Query<(Entity, &Transform, &Kinematics), With<Boid>>,
+    mut replay: ResMut<ReplayBuffer>,
+) {
+    if replay.capacity == 0 || replay.is_replaying() {
+        return;
+    }
+    let frame = boid_query
+        .iter()
+        .map(|(entity, transform, kinematics)| BoidSnapshot {
+            entity,
+            transform: *transform,
+            velocity: kinematics.velocity,
+        })
+        .collect();
+    replay.record(frame);
+}
+
+// rebuild the quadtree around the flock's bounding box every fit.interval, from the boids
+// themselves so any that drifted out of the previous root are picked up again
+pub fn refit_quadtree(
+    mut entity_query: Query<(Entity, &Kinematics, &Transform, &mut QuadtreeRect), With<Boid>>,
     mut quadtree: ResMut<EntityQuadtree>,
+    mut since_refit: Local<f32>,
+    fit: Res<QuadtreeFit>,
+    bounds: Res<WorldBounds>,
+    physics: Res<PhysicsConfig>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    if !fit.enabled {
+        return;
+    }
+    *since_refit += physics.delta_time();
+    if *since_refit < fit.interval {
+        return;
+    }
+    *since_refit = 0.;
+    let values: Vec<EntityWrapper> = entity_query
+        .iter_mut()
+        .map(|(entity, kinematics, transform, mut last_rect)| {
+            let value = EntityWrapper::new(
+                entity,
+                &kinematics.velocity,
+                transform,
+                spawn_config.alignment,
+            );
+            last_rect.0 = value.rect;
+            value
+        })
+        .collect();
+    let flock_rect = match rect_union_all(values.iter().map(|v| &v.rect)) {
+        Some(flock_rect) => flock_rect,
+        None => return,
+    };
+    let root_rect = Rect {
+        min: (flock_rect.min - fit.padding).max(bounds.rect.min),
+        max: (flock_rect.max + fit.padding).min(bounds.rect.max),
+    };
+    quadtree.rebuild(root_rect, values);
+}
+
+// scale boids in after spawning and out before despawning, remove_despawned_boids drops them
+// from the quadtree
+pub fn animate_spawn_transitions(
+    mut commands: Commands,
+    mut anim_query: Query<(Entity, &mut SpawnAnim, &mut Transform), With<Boid>>,
+    time: Res<Time>,
+) {
+    for (entity, mut anim, mut transform) in anim_query.iter_mut() {
+        anim.t = (anim.t + time.delta_seconds() / SPAWN_ANIM_DURATION).min(1.);
+        let progress = if anim.despawning { 1. - anim.t } else { anim.t };
+        transform.scale = (BOID_SCALE * progress).extend(0.);
+        if anim.t < 1. {
+            continue;
+        }
+        if anim.despawning {
+            commands.entity(entity).despawn();
+        } else {
+            commands.entity(entity).remove::<SpawnAnim>();
+        }
+    }
+}
+
+// separation, alignment and cohesion from a single neighbor lookup, blended into one correction
+// so no rule overrides another by running last
+#[allow(clippy::too_many_arguments)]
+pub fn flocking(
+    // frozen boids are still queried so they count as neighbors
+    mut kinematics_query: Query<(&mut Kinematics, Entity, &Transform, Option<&Frozen>), With<Boid>>,
+    quadtree: Res<EntityQuadtree>,
+    params: Res<BoidParams>,
+    strategy: Res<NeighborStrategy>,
+    spawn_config: Res<SpawnConfig>,
+    budget: Res<PerfBudget>,
+    mask: Res<BehaviorMask>,
+    settle: Res<SettlePhase>,
+    layer_query: Query<&Layer>,
+    alarmed_query: Query<&Alarmed>,
+    obstacles: Res<ObstacleQuadtree>,
 ) {
-    entity_query.for_each(|(entity, kinematics, transform)| {
-        let value = EntityWrapper::new(entity, &kinematics.velocity, transform);
-        if let Some(node) = quadtree.query_rect_mut(value.get_rect()) {
-            if !node.contains_value(&value) {
-                quadtree.delete(&value);
-                quadtree.add(value);
+    if !(mask.contains(BehaviorMask::SEPARATION)
+        || mask.contains(BehaviorMask::ALIGNMENT)
+        || mask.contains(BehaviorMask::COHESION))
+    {
+        return;
+    }
+    let all_boids = snapshot_boids(*strategy, spawn_config.alignment, &kinematics_query);
+    let velocities = live_velocities(&kinematics_query);
+    // separation stays exact, the wider neighborhoods are what the budget trades away
+    let alignment_radius = params.alignment_radius * budget.radius_scale;
+    let cohesion_radius = params.cohesion_radius * budget.radius_scale;
+    let max_radius = params
+        .separation_radius
+        .max(alignment_radius)
+        .max(cohesion_radius);
+    let node_cache = node_neighbor_cache(
+        *strategy,
+        &quadtree,
+        spawn_config.alignment,
+        max_radius,
+        &kinematics_query,
+    );
+    kinematics_query.par_for_each_mut(
+        THREADS_MEDIUM,
+        |(mut kinematics, entity, transform, frozen)| {
+            if frozen.is_some() {
+                return;
             }
+            let my_rect = spawn_config.alignment.transform_to_rect(transform);
+            let detection_rect = neighborhood_rect(&my_rect, max_radius);
+            let my_layer = layer_query.get(entity).ok();
+            // borrowed from the thread and handed back once this boid is steered
+            let mut scratch = FLOCKING_SCRATCH.with(Cell::take);
+            let mut found = scratch.neighbors.take();
+            nearby_boids(
+                *strategy,
+                &quadtree,
+                &all_boids,
+                &node_cache,
+                &detection_rect,
+                |v| v.entity == entity || !same_layer(my_layer, layer_query.get(v.entity).ok()),
+                &mut found,
+            );
+            let neighbors = found.as_slice();
+            // the tree's copies are only as fresh as the last update_quadtree, so use the
+            // velocity each neighbor has right now
+            scratch.velocities.clear();
+            scratch.velocities.extend(
+                neighbors
+                    .iter()
+                    .map(|value| live_velocity(value, &velocities)),
+            );
+            let live = scratch.velocities.as_slice();
+            // each rule only considers the neighbors inside its own radius, picked out by index
+            // so no rule collects its own list
+            let within = |radius: f32| {
+                let rule_rect = neighborhood_rect(&my_rect, radius);
+                (0..neighbors.len())
+                    .filter(move |&i| rect_intersects_rect(&rule_rect, neighbors[i].get_rect()))
+            };
+            let my_center = (my_rect.min + my_rect.max) / 2.;
+            // collected once, closing_speed and separation_force both walk them
+            scratch.separating.clear();
+            scratch
+                .separating
+                .extend(within(params.separation_radius).filter(|&i| {
+                    let value = neighbors[i];
+                    // the square neighborhood holds the circle, so it still works as a broad
+                    // phase
+                    let overlaps = match params.collision_shape {
+                        CollisionShape::Rect => true,
+                        CollisionShape::Circle => circles_overlap(
+                            my_center,
+                            params.separation_radius,
+                            value.get_center(),
+                            inscribed_radius(value.get_rect()),
+                        ),
+                    };
+                    overlaps
+                        && (!params.separation_line_of_sight
+                            || line_of_sight(&obstacles, my_center, value.get_center()))
+                }));
+            let separating = &scratch.separating;
+            let closing = closing_speed(
+                &my_rect,
+                kinematics.velocity,
+                separating.iter().map(|&i| (neighbors[i], live[i])),
+            );
+            // a disabled rule contributes a zero force, which combine_steering skips
+            let separation = if mask.contains(BehaviorMask::SEPARATION) {
+                separation_force(
+                    entity,
+                    &my_rect,
+                    separating.iter().map(|&i| neighbors[i]),
+                    params.separation_falloff,
+                    params.collision_shape,
+                )
+            } else {
+                Vec2::ZERO
+            };
+            // separation still sees crossing boids, only the rules that follow neighbors skip them
+            let heading = kinematics.velocity.truncate();
+            let cutoff = params.heading_similarity_cutoff;
+            let following = |radius: f32| {
+                within(radius)
+                    .filter(move |&i| similar_heading(heading, live[i].truncate(), cutoff))
+            };
+            let alignment = if mask.contains(BehaviorMask::ALIGNMENT) {
+                alignment_force(
+                    following(alignment_radius).map(|i| live[i]),
+                    params.alignment_mode,
+                )
+            } else {
+                Vec2::ZERO
+            };
+            let cohesion = if mask.contains(BehaviorMask::COHESION) {
+                cohesion_force(
+                    &my_rect,
+                    following(cohesion_radius).map(|i| neighbors[i]),
+                    params.cohesion_floor,
+                )
+            } else {
+                Vec2::ZERO
+            };
+            let alarmed = alarmed_query.contains(entity);
+            let cohesion_weight = if alarmed {
+                BOID_COHESION_DAMPENING * params.alarm_cohesion_boost
+            } else {
+                BOID_COHESION_DAMPENING
+            };
+            let (force_vec, total_weight) = combine_steering(&[
+                (
+                    separation,
+                    separation_weight(closing, &params) * settle.separation_scale(),
+                ),
+                (alignment, params.alignment_strength),
+                (cohesion, cohesion_weight),
+            ]);
+            // only apply correction if not NaN and above threshold
+            if total_weight > 0. && force_vec.length_squared() > EPS {
+                kinematics.velocity = steer_towards(
+                    kinematics.velocity,
+                    force_vec / total_weight,
+                    total_weight,
+                    params.min_speed,
+                );
+            }
+            if alarmed {
+                kinematics.velocity = kinematics.velocity.clamp_length_min(params.alarm_speed);
+            }
+            scratch.neighbors.restore(found);
+            FLOCKING_SCRATCH.with(|cell| cell.set(scratch));
+        },
+    );
+}
+
+// counts down the settling phase, runs once per physics tick after flocking has used it
+pub fn advance_settle_phase(mut settle: ResMut<SettlePhase>) {
+    if settle.is_settling() {
+        settle.tick();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn avoid_obstacles(
+    mut kinematics_query: Query<(&mut Kinematics, &Transform), (With<Boid>, Without<Frozen>)>,
+    obstacles: Res<ObstacleQuadtree>,
+    params: Res<BoidParams>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    kinematics_query.par_for_each_mut(THREADS_MEDIUM, |(mut kinematics, transform)| {
+        let my_rect = spawn_config.alignment.transform_to_rect(transform);
+        let detection_rect = magnify_rect(&my_rect, Vec2::splat(BOID_OBSTACLE_DETECTION_RADIUS));
+        let my_center = (my_rect.min + my_rect.max) / 2.;
+        // obstacle tree is static, so this is a read-only lookup
+        let mut nearby_obstacles = obstacles.values_intersecting(&detection_rect);
+        nearby_obstacles.sort_unstable_by_key(|v| v.entity);
+        let mut force_vec = Vec2::ZERO;
+        for value in nearby_obstacles {
+            force_vec += (my_center - value.get_center()).normalize_or_zero();
+        }
+        // only apply correction if not NaN and above threshold
+        if force_vec.length_squared() > EPS {
+            let force_direction = force_vec.normalize_or_zero();
+            kinematics.velocity = steer_towards(
+                kinematics.velocity,
+                force_direction,
+                BOID_OBSTACLE_DAMPENING,
+                params.min_speed,
+            );
         }
     });
-    // QuadtreeStats::calculate(&quadtree).print();
 }
 
-pub fn approach_nearby_boid_groups(
-    mut kinematics_query: Query<(&mut Kinematics, Entity, &Transform), With<Boid>>,
+// steer toward whichever sampled heading has the fewest boids ahead, spreading into open space
+#[allow(clippy::type_complexity)]
+pub fn seek_open_space(
+    mut kinematics_query: Query<(&mut Kinematics, &Transform), (With<Boid>, Without<Frozen>)>,
     quadtree: Res<EntityQuadtree>,
+    params: Res<BoidParams>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    if !params.open_space {
+        return;
+    }
+    let distance = params.open_space_distance;
+    kinematics_query.par_for_each_mut(THREADS_MEDIUM, |(mut kinematics, transform)| {
+        let my_rect = spawn_config.alignment.transform_to_rect(transform);
+        let my_center = (my_rect.min + my_rect.max) / 2.;
+        // each sample is a square one look-ahead wide, far enough ahead to exclude the boid itself
+        let direction = emptiest_direction(kinematics.velocity.truncate(), |direction| {
+            let sample_rect =
+                centered_rect(my_center + direction * distance, Vec2::splat(distance));
+            quadtree.count_in_rect(&sample_rect)
+        });
+        if direction.length_squared() > EPS {
+            kinematics.velocity = steer_towards(
+                kinematics.velocity,
+                direction,
+                params.open_space_weight,
+                params.min_speed,
+            );
+        }
+    });
+}
+
+// boids in a cluster larger than flock_split_size steer slightly away from its centroid, so an
+// oversized flock pulls apart into smaller ones
+pub fn split_large_flocks(
+    mut kinematics_query: Query<&mut Kinematics, (With<Boid>, Without<Frozen>)>,
+    boid_query: Query<(Entity, &Transform), With<Boid>>,
+    neighbors: NeighborQuery<EntityWrapper>,
+    params: Res<BoidParams>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    if params.flock_split_size == 0 {
+        return;
+    }
+    let (centers, clusters) = boid_clusters(
+        &boid_query,
+        &neighbors,
+        spawn_config.alignment,
+        params.flock_split_radius,
+    );
+    for cluster in clusters {
+        if cluster.len() <= params.flock_split_size {
+            continue;
+        }
+        let cluster_centers: Vec<Vec2> = cluster.iter().map(|entity| centers[entity]).collect();
+        let centroid = cluster_centers.iter().sum::<Vec2>() / cluster.len() as f32;
+        for (entity, center) in cluster.iter().zip(cluster_centers) {
+            let force_vec = split_force(center, centroid);
+            if force_vec.length_squared() < EPS {
+                continue;
+            }
+            if let Ok(mut kinematics) = kinematics_query.get_mut(*entity) {
+                kinematics.velocity = steer_towards(
+                    kinematics.velocity,
+                    force_vec,
+                    params.flock_split_weight,
+                    params.min_speed,
+                );
+            }
+        }
+    }
+}
+
+// tags every boid with the ClusterId of its cluster, see flock_split_radius for what links boids.
+// Only boids whose cluster changed are touched, so color_by_cluster can skip the rest
+pub fn assign_cluster_ids(
+    mut commands: Commands,
+    boid_query: Query<(Entity, &Transform), With<Boid>>,
+    id_query: Query<&ClusterId>,
+    neighbors: NeighborQuery<EntityWrapper>,
+    params: Res<BoidParams>,
+    spawn_config: Res<SpawnConfig>,
 ) {
-    kinematics_query.par_for_each_mut(THREADS_MEDIUM, |(mut kinematics, entity, transform)| {
-        let my_rect = transform_to_rect(transform);
-        let detection_rect = magnify_rect(&my_rect, Vec2::splat(BOID_GROUP_APPROACH_RADIUS));
-        // find other nearby boids using quadtree lookup and calculate velocity_correction
-        if let Some(node) = quadtree.query_rect(&detection_rect) {
-            // loop through nearby boids and sum up velocity_correction
-            let mut num_values = 0;
-            let mut average_velocity = Vec3::ZERO;
-            for value in node
-                .get_all_descendant_values()
-                .filter(|v| v.entity != entity)
-            {
-                average_velocity += value.velocity;
-                num_values += 1;
-            }
-            if num_values > 1 {
-                average_velocity /= num_values as f32;
-                // only apply correction if not NaN and above threshold
-                if average_velocity.length_squared() > EPS {
-                    let current_dir = kinematics.velocity.normalize_or_zero();
-                    let force_direction = average_velocity.normalize_or_zero();
-                    let new_dir = current_dir
-                        .lerp(force_direction, BOID_APPROACH_DAMPENING)
-                        .normalize_or_zero();
-                    kinematics.velocity = new_dir * kinematics.velocity.length();
+    if !spawn_config.color_by_cluster {
+        return;
+    }
+    let (_, clusters) = boid_clusters(
+        &boid_query,
+        &neighbors,
+        spawn_config.alignment,
+        params.flock_split_radius,
+    );
+    for cluster in clusters {
+        // clusters are sorted, so this is the lowest entity in the cluster
+        let id = ClusterId(cluster[0].id());
+        for entity in cluster {
+            if id_query.get(entity) != Ok(&id) {
+                commands.entity(entity).insert(id);
+            }
+        }
+    }
+}
+
+// tints sprite boids by cluster; instanced and triangle boids share materials and keep their
+// species colors
+pub fn color_by_cluster(mut boid_query: Query<(&ClusterId, &mut Sprite), Changed<ClusterId>>) {
+    for (cluster_id, mut sprite) in boid_query.iter_mut() {
+        sprite.color = cluster_color(*cluster_id);
+    }
+}
+
+// the cursor acts as a predator, boids near it flee with a strong weight and become alarmed
+pub fn scare_from_cursor(
+    mut commands: Commands,
+    mut kinematics_query: Query<&mut Kinematics, (With<Boid>, Without<Frozen>)>,
+    neighbors: NeighborQuery<EntityWrapper>,
+    params: Res<BoidParams>,
+    windows: Res<Windows>,
+    camera_query: Query<&GlobalTransform, With<Camera>>,
+) {
+    let cursor = match (windows.get_primary(), camera_query.get_single()) {
+        (Some(window), Ok(camera)) => match cursor_world_position(camera, window) {
+            Some(cursor) => cursor,
+            None => return,
+        },
+        _ => return,
+    };
+    // a single radius query around the cursor instead of a query per boid
+    for value in neighbors.within_radius(cursor, params.scare_radius) {
+        if let Ok(mut kinematics) = kinematics_query.get_mut(value.entity) {
+            let force_vec = scare_force(value.get_center(), cursor, params.scare_radius);
+            if force_vec.length_squared() > EPS {
+                kinematics.velocity = steer_towards(
+                    kinematics.velocity,
+                    force_vec.normalize_or_zero(),
+                    params.scare_strength,
+                    params.min_speed,
+                );
+                if params.alarm_ttl > 0. {
+                    commands.entity(value.entity).insert(Alarmed {
+                        ttl: params.alarm_ttl,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// while a scatter is running every boid flees the flock centroid, the scatter counts down by one
+// physics step per tick
+#[allow(clippy::type_complexity)]
+pub fn scatter_flock(
+    mut kinematics_query: Query<
+        (Entity, &mut Kinematics, &Transform),
+        (With<Boid>, Without<Frozen>),
+    >,
+    mut scatter: ResMut<Scatter>,
+    params: Res<BoidParams>,
+    physics: Res<PhysicsConfig>,
+) {
+    if !scatter.is_active() {
+        return;
+    }
+    scatter.ttl = (scatter.ttl - physics.delta_time()).max(0.);
+    let mut samples: Vec<_> = kinematics_query
+        .iter()
+        .map(|(entity, kinematics, transform)| {
+            (
+                entity,
+                transform.translation.truncate(),
+                kinematics.velocity.truncate(),
+            )
+        })
+        .collect();
+    let centroid = FlockStats::calculate(&mut samples).centroid;
+    kinematics_query.par_for_each_mut(THREADS_LARGE, |(_, mut kinematics, transform)| {
+        let force_vec = split_force(transform.translation.truncate(), centroid);
+        if force_vec.length_squared() > EPS {
+            kinematics.velocity = steer_towards(
+                kinematics.velocity,
+                force_vec,
+                params.scatter_strength,
+                params.min_speed,
+            );
+        }
+    });
+}
+
+// every alarmed boid alarms the boids within alarm_radius for alarm_falloff of its remaining
+// ttl, a boid already alarmed for longer keeps its own ttl
+pub fn propagate_alarm(
+    mut commands: Commands,
+    mut alarm_query: Query<(Entity, &Transform, Option<&mut Alarmed>), With<Boid>>,
+    neighbors: NeighborQuery<EntityWrapper>,
+    params: Res<BoidParams>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    let mut spread: HashMap<Entity, f32> = HashMap::default();
+    for (entity, transform, alarmed) in alarm_query.iter() {
+        let ttl = match alarmed {
+            Some(alarmed) => alarmed.ttl * params.alarm_falloff,
+            None => continue,
+        };
+        let rect = spawn_config.alignment.transform_to_rect(transform);
+        let center = (rect.min + rect.max) / 2.;
+        for value in neighbors.within_radius(center, params.alarm_radius) {
+            if value.entity == entity {
+                continue;
+            }
+            let passed = spread.entry(value.entity).or_insert(0.);
+            *passed = passed.max(ttl);
+        }
+    }
+    for (entity, ttl) in spread {
+        match alarm_query.get_mut(entity) {
+            Ok((_, _, Some(mut alarmed))) => {
+                if ttl > alarmed.ttl {
+                    alarmed.ttl = ttl;
+                }
+            }
+            Ok((_, _, None)) => {
+                if ttl > 0. {
+                    commands.entity(entity).insert(Alarmed { ttl });
                 }
             }
+            // despawned since the quadtree was last updated
+            Err(_) => {}
+        }
+    }
+}
+
+// counts every alarm down by one physics step and calms boids whose ttl has run out
+pub fn decay_alarm(
+    mut commands: Commands,
+    mut alarm_query: Query<(Entity, &mut Alarmed)>,
+    physics: Res<PhysicsConfig>,
+) {
+    let delta_time = physics.delta_time();
+    for (entity, mut alarmed) in alarm_query.iter_mut() {
+        alarmed.ttl = (alarmed.ttl - delta_time).max(0.);
+        if alarmed.ttl == 0. {
+            commands.entity(entity).remove::<Alarmed>();
+        }
+    }
+}
+
+// arrow keys push the whole flock, keys pressed on the same frame add up to one diagonal push
+pub fn impulse_from_arrow_keys(keys: Res<Input<KeyCode>>, mut impulses: EventWriter<Impulse>) {
+    let direction = [
+        (KeyCode::Up, Vec2::Y),
+        (KeyCode::Down, Vec2::NEG_Y),
+        (KeyCode::Left, Vec2::NEG_X),
+        (KeyCode::Right, Vec2::X),
+    ]
+    .iter()
+    .filter(|(key, _)| keys.just_pressed(*key))
+    .fold(Vec2::ZERO, |sum, (_, direction)| sum + *direction);
+    if direction.length_squared() > EPS {
+        impulses.send(Impulse {
+            velocity: direction.normalize() * IMPULSE_SPEED,
+        });
+    }
+}
+
+// S starts a scatter, pressing it again mid-scatter restarts the countdown
+pub fn scatter_from_key(
+    keys: Res<Input<KeyCode>>,
+    mut scatter: ResMut<Scatter>,
+    params: Res<BoidParams>,
+) {
+    if keys.just_pressed(KeyCode::S) {
+        scatter.ttl = params.scatter_ttl;
+    }
+}
+
+// each Impulse is read once, so its velocity is added exactly once and steering takes it from
+// there
+pub fn apply_impulse(
+    mut impulses: EventReader<Impulse>,
+    mut kinematics_query: Query<&mut Kinematics, (With<Boid>, Without<Frozen>)>,
+) {
+    let velocity = impulses
+        .iter()
+        .fold(Vec2::ZERO, |sum, impulse| sum + impulse.velocity);
+    if velocity.length_squared() < EPS {
+        return;
+    }
+    kinematics_query.par_for_each_mut(THREADS_LARGE, |mut kinematics| {
+        kinematics.velocity += velocity.extend(0.);
+    });
+}
+
+// turns RenderMode::Triangle boids to point along their velocity, the triangle mesh points along
+// +Y. Only rotation changes, so quadtree rects are unaffected
+pub fn orient_to_velocity(
+    mut boid_query: Query<(&Kinematics, &mut Transform), With<Boid>>,
+    spawn_config: Res<SpawnConfig>,
+) {
+    if spawn_config.render_mode != RenderMode::Triangle {
+        return;
+    }
+    boid_query.par_for_each_mut(THREADS_LARGE, |(kinematics, mut transform)| {
+        let velocity = kinematics.velocity.truncate();
+        // a stopped boid keeps facing the way it was going
+        if velocity.length_squared() > EPS {
+            let angle = velocity.y.atan2(velocity.x) - std::f32::consts::FRAC_PI_2;
+            transform.rotation = Quat::from_rotation_z(angle);
         }
     });
 }
 
-pub fn avoid_nearby_boids(
-    mut kinematics_query: Query<(&mut Kinematics, Entity, &Transform), With<Boid>>,
+#[allow(clippy::type_complexity)]
+pub fn follow_leaders(
+    mut follower_query: Query<&mut Kinematics, (With<Boid>, Without<Leader>, Without<Frozen>)>,
+    leader_query: Query<(Entity, &Transform), With<Leader>>,
     quadtree: Res<EntityQuadtree>,
+    params: Res<BoidParams>,
+    spawn_config: Res<SpawnConfig>,
+    mask: Res<BehaviorMask>,
 ) {
-    kinematics_query.par_for_each_mut(THREADS_MEDIUM, |(mut kinematics, entity, transform)| {
-        let my_rect = transform_to_rect(transform);
-        let detection_rect = magnify_rect(&my_rect, Vec2::splat(BOID_DETECTION_RADIUS));
-        // find other nearby boids using quadtree lookup and calculate velocity_correction
-        if let Some(node) = quadtree.query_rect(&detection_rect) {
-            // loop through nearby boids and sum up velocity_correction
-            let mut force_vec = Vec2::ZERO;
-            for value in node
-                .get_all_descendant_values()
-                .filter(|v| v.entity != entity)
-            {
-                let delta_vec = my_rect.min - value.rect.min;
-                let direction_away = delta_vec.normalize_or_zero();
-                force_vec -= direction_away
-                    / (1.
-                        + BOID_DIAG_LEN_RECIP
-                            * (delta_vec.length_squared() - BOID_DIAG_LENGTH).exp());
+    if !mask.contains(BehaviorMask::GOAL) {
+        return;
+    }
+    // leaders are rare, so search outward from each leader and keep the nearest one per follower
+    // (distance squared, leader center, follower center)
+    let mut nearest_leaders: HashMap<Entity, (f32, Vec2, Vec2)> = HashMap::default();
+    for (leader, transform) in leader_query.iter() {
+        let leader_rect = spawn_config.alignment.transform_to_rect(transform);
+        let leader_center = (leader_rect.min + leader_rect.max) / 2.;
+        for value in quadtree.query_radius(leader_center, params.leader_radius) {
+            if value.entity == leader {
+                continue;
             }
-            // only apply correction if not NaN and above threshold
+            let follower_center = value.get_center();
+            let distance_squared = follower_center.distance_squared(leader_center);
+            let nearest = nearest_leaders.entry(value.entity).or_insert((
+                f32::MAX,
+                leader_center,
+                follower_center,
+            ));
+            if distance_squared < nearest.0 {
+                *nearest = (distance_squared, leader_center, follower_center);
+            }
+        }
+    }
+    for (entity, (_, leader_center, follower_center)) in nearest_leaders {
+        if let Ok(mut kinematics) = follower_query.get_mut(entity) {
+            let force_vec = leader_center - follower_center;
             if force_vec.length_squared() > EPS {
-                let current_dir = kinematics.velocity.normalize_or_zero();
-                let force_direction = force_vec.normalize_or_zero().extend(0.);
-                let new_dir = current_dir
-                    .lerp(force_direction, BOID_AVOID_DAMPENING)
-                    .normalize_or_zero();
-                kinematics.velocity = new_dir * kinematics.velocity.length();
+                kinematics.velocity = steer_towards(
+                    kinematics.velocity,
+                    force_vec.normalize_or_zero(),
+                    params.leader_weight,
+                    params.min_speed,
+                );
             }
         }
+    }
+}
+
+// attractors accelerate boids toward them, adding to whatever the steering systems decided
+#[allow(clippy::type_complexity)]
+pub fn apply_attractors(
+    mut kinematics_query: Query<(&mut Kinematics, &Transform), (With<Boid>, Without<Frozen>)>,
+    attractor_query: Query<&Attractor>,
+    physics: Res<PhysicsConfig>,
+    mask: Res<BehaviorMask>,
+) {
+    if !mask.contains(BehaviorMask::GOAL) {
+        return;
+    }
+    let attractors: Vec<Attractor> = attractor_query.iter().cloned().collect();
+    if attractors.is_empty() {
+        return;
+    }
+    let delta_time = physics.delta_time();
+    kinematics_query.par_for_each_mut(THREADS_MEDIUM, |(mut kinematics, transform)| {
+        let position = transform.translation.truncate();
+        let pull = attractors.iter().fold(Vec2::ZERO, |sum, attractor| {
+            sum + attractor_pull(position, attractor)
+        });
+        kinematics.velocity += (pull * delta_time).extend(0.);
     });
 }
 
+// leaders have no goal, so they drift by a small random turn each tick
+#[allow(clippy::type_complexity)]
+pub fn wander_leaders(
+    mut leader_query: Query<&mut Kinematics, (With<Boid>, With<Leader>, Without<Frozen>)>,
+) {
+    let mut rng = rand::thread_rng();
+    for mut kinematics in leader_query.iter_mut() {
+        let turn = Vec2::from_angle(rng.gen_range(-LEADER_WANDER_ANGLE..LEADER_WANDER_ANGLE));
+        let velocity = turn.rotate(kinematics.velocity.truncate());
+        kinematics.velocity = velocity.extend(kinematics.velocity.z);
+    }
+}
+
+#[allow(clippy::type_complexity)]
 pub fn avoid_screen_edges(
-    mut kinematics_query: Query<(&mut Kinematics, &Transform), With<Boid>>,
-    windows: Res<Windows>,
+    mut kinematics_query: Query<(&mut Kinematics, &Transform), (With<Boid>, Without<Frozen>)>,
+    bounds: Res<WorldBounds>,
+    physics: Res<PhysicsConfig>,
+    params: Res<BoidParams>,
+    mask: Res<BehaviorMask>,
 ) {
-    let mut window_size = Vec2::ZERO;
-    if let Some(window) = windows.get_primary() {
-        window_size.x = window.width();
-        window_size.y = window.height();
-    } else {
+    if !mask.contains(BehaviorMask::EDGE) || !params.edge_behavior.any(EdgeMode::Bounce) {
         return;
     }
-    let left_edge_x = -window_size.x / 2.0;
-    let right_edge_x = window_size.x / 2.0;
-    let top_edge_y = window_size.y / 2.0;
-    let bottom_edge_y = -window_size.y / 2.0;
-    let margin = (BOID_SCALE / 2.).extend(0.);
+    let delta_time = physics.delta_time();
+    let substeps = physics.substeps.max(1);
     kinematics_query.par_for_each_mut(THREADS_LARGE, |(mut kinematics, transform)| {
-        let loc = transform.translation + kinematics.integrate(DELTA_TIME_FIXED) + margin;
-        // calculate distances
-        let distance_to_left = loc.x - left_edge_x - margin.x;
-        let distance_to_right = right_edge_x - loc.x - margin.x;
-        let distance_to_top = top_edge_y - loc.y - margin.y;
-        let distance_to_bottom = loc.y - bottom_edge_y - margin.y;
-        // bounce if too close to screen edge
-        if distance_to_left < EPS || distance_to_right < EPS {
-            kinematics.velocity.x *= -1.;
-        }
-        if distance_to_top < EPS || distance_to_bottom < EPS {
-            kinematics.velocity.y *= -1.;
+        // predict with the same integrator apply_kinematics moves by, apply_kinematics checks
+        // any later substeps itself
+        let displacement = kinematics.substep(delta_time, substeps, physics.integrator);
+        bounce_off_edges(
+            &mut kinematics,
+            transform.translation + displacement,
+            &bounds.rect,
+            params.edge_behavior,
+            params.bounce_restitution,
+        );
+    });
+}
+
+// eases each boid's speed back toward its preferred speed, keeping its heading
+pub fn ease_to_preferred_speed(
+    mut kinematics_query: Query<&mut Kinematics, (With<Boid>, Without<Frozen>)>,
+    params: Res<BoidParams>,
+) {
+    if params.speed_return_rate <= 0. {
+        return;
+    }
+    kinematics_query.par_for_each_mut(THREADS_LARGE, |mut kinematics| {
+        kinematics.velocity = eased_velocity(
+            kinematics.velocity,
+            kinematics.preferred_speed,
+            params.speed_return_rate,
+        );
+    });
+}
+
+#[allow(clippy::type_complexity)]
+pub fn normalize_flock_speed(
+    mut kinematics_query: Query<
+        (Entity, &mut Kinematics, &Transform),
+        (With<Boid>, Without<Frozen>),
+    >,
+    params: Res<BoidParams>,
+) {
+    if !params.uniform_speed {
+        return;
+    }
+    // reduced in entity order so the mean, and every speed set from it, is reproducible
+    let mut samples: Vec<_> = kinematics_query
+        .iter()
+        .map(|(entity, kinematics, transform)| {
+            (
+                entity,
+                transform.translation.truncate(),
+                kinematics.velocity.truncate(),
+            )
+        })
+        .collect();
+    let stats = FlockStats::calculate(&mut samples);
+    if stats.num_boids == 0 {
+        return;
+    }
+    kinematics_query.par_for_each_mut(THREADS_LARGE, |(_, mut kinematics, _)| {
+        kinematics.velocity = kinematics.velocity.normalize_or_zero() * stats.mean_speed;
+    });
+}
+
+// last line of defense before integration, a NaN velocity would otherwise stick forever
+pub fn sanitize_kinematics(mut kinematics_query: Query<(Entity, &mut Kinematics), With<Boid>>) {
+    kinematics_query.par_for_each_mut(THREADS_LARGE, |(entity, mut kinematics)| {
+        if !kinematics.is_finite() {
+            warn!("Resetting non-finite kinematics on {:?}", entity);
+            // a stationary boid gets kicked back into motion by steering
+            kinematics.velocity = Vec3::ZERO;
+            kinematics.acceleration = Vec3::ZERO;
         }
     });
 }
 
+// only wraps axes whose EdgeMode is Wrap, at the world edge rather than the window's
 pub fn wrap_screen_edges(
-    mut kinematics_query: Query<&mut Transform, With<Boid>>,
-    windows: Res<Windows>,
+    mut kinematics_query: Query<&mut Transform, (With<Boid>, Without<Frozen>)>,
+    bounds: Res<WorldBounds>,
+    params: Res<BoidParams>,
 ) {
-    let mut window_size = Vec2::ZERO;
-    if let Some(window) = windows.get_primary() {
-        window_size.x = window.width();
-        window_size.y = window.height();
-    } else {
+    let edges = params.edge_behavior;
+    if !edges.any(EdgeMode::Wrap) {
         return;
     }
-    let left_edge_x = -window_size.x / 2.0;
-    let right_edge_x = window_size.x / 2.0;
-    let top_edge_y = window_size.y / 2.0;
-    let bottom_edge_y = -window_size.y / 2.0;
+    let left_edge_x = bounds.rect.min.x;
+    let right_edge_x = bounds.rect.max.x;
+    let top_edge_y = bounds.rect.max.y;
+    let bottom_edge_y = bounds.rect.min.y;
     let margin = (BOID_SCALE / 2.).extend(0.);
     kinematics_query.par_for_each_mut(THREADS_LARGE, |mut transform| {
         let loc = transform.translation + margin;
@@ -172,17 +1080,1803 @@ pub fn wrap_screen_edges(
         let distance_to_top = top_edge_y - loc.y - margin.y;
         let distance_to_bottom = loc.y - bottom_edge_y - margin.y;
         // wrap if too close to screen edge
-        if distance_to_left < EPS {
-            transform.translation.x += distance_to_right;
+        if edges.x == EdgeMode::Wrap {
+            if distance_to_left < EPS {
+                transform.translation.x += distance_to_right;
+            }
+            if distance_to_right < EPS {
+                transform.translation.x -= distance_to_left;
+            }
         }
-        if distance_to_right < EPS {
-            transform.translation.x -= distance_to_left;
+        if edges.y == EdgeMode::Wrap {
+            if distance_to_top < EPS {
+                transform.translation.y -= distance_to_bottom;
+            }
+            if distance_to_bottom < EPS {
+                transform.translation.y += distance_to_top;
+            }
         }
-        if distance_to_top < EPS {
-            transform.translation.y -= distance_to_bottom;
+    });
+}
+
+// keeps a ghost of every boid within wrap_ghost_margin of a wrapping edge, drawn where the boid
+// will reappear so it slides across the seam instead of popping from one side to the other
+#[allow(clippy::type_complexity)]
+pub fn draw_wrap_ghosts(
+    mut commands: Commands,
+    params: Res<BoidParams>,
+    bounds: Res<WorldBounds>,
+    spawn_config: Res<SpawnConfig>,
+    boid_query: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&Sprite>,
+            Option<&Mesh2dHandle>,
+            Option<&Handle<ColorMaterial>>,
+        ),
+        With<Boid>,
+    >,
+    mut ghost_query: Query<(Entity, &WrapGhost, &mut Transform), Without<Boid>>,
+) {
+    let mut wanted = HashMap::default();
+    if params.edge_behavior.any(EdgeMode::Wrap) && params.wrap_ghost_margin > 0. {
+        for (boid, transform, _, _, _) in boid_query.iter() {
+            let rect = spawn_config.alignment.transform_to_rect(transform);
+            for direction in wrap_ghost_directions(
+                &rect,
+                &bounds.rect,
+                params.wrap_ghost_margin,
+                params.edge_behavior,
+            ) {
+                wanted.insert(
+                    (boid, direction),
+                    wrap_ghost_transform(transform, &rect, &bounds.rect, direction),
+                );
+            }
         }
-        if distance_to_bottom < EPS {
-            transform.translation.y += distance_to_top;
+    }
+    // move the ghosts that are still needed, despawn the rest
+    for (ghost, wrap_ghost, mut transform) in ghost_query.iter_mut() {
+        match wanted.remove(&(wrap_ghost.boid, wrap_ghost.direction)) {
+            Some(ghost_transform) => *transform = ghost_transform,
+            None => commands.entity(ghost).despawn(),
         }
+    }
+    for ((boid, direction), transform) in wanted {
+        let wrap_ghost = WrapGhost { boid, direction };
+        // drawn the same way as the boid it copies
+        match boid_query.get(boid) {
+            Ok((_, _, Some(sprite), _, _)) => {
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: sprite.clone(),
+                        transform,
+                        ..default()
+                    })
+                    .insert(wrap_ghost);
+            }
+            Ok((_, _, None, Some(mesh), Some(material))) => {
+                commands
+                    .spawn_bundle(MaterialMesh2dBundle {
+                        mesh: mesh.clone(),
+                        material: material.clone(),
+                        transform,
+                        ..default()
+                    })
+                    .insert(wrap_ghost);
+            }
+            _ => {}
+        }
+    }
+}
+
+/* Internal-only Functions */
+
+// a stopped boid has no heading to keep, so steering has to get it moving first
+fn eased_velocity(velocity: Vec3, preferred_speed: f32, rate: f32) -> Vec3 {
+    let speed = velocity.length();
+    if speed < EPS {
+        return velocity;
+    }
+    velocity * (speed + (preferred_speed - speed) * rate) / speed
+}
+
+// -1, 0 or 1 on each axis for every ghost a boid at rect needs, one per wrapping edge it's within
+// margin of plus one for the corner when it's near two
+fn wrap_ghost_directions(
+    rect: &Rect,
+    bounds: &Rect,
+    margin: f32,
+    edges: EdgeBehavior,
+) -> Vec<IVec2> {
+    let axis_direction = |mode: EdgeMode, min: f32, max: f32, bounds_min: f32, bounds_max: f32| {
+        if mode != EdgeMode::Wrap {
+            0
+        } else if max > bounds_max - margin {
+            -1
+        } else if min < bounds_min + margin {
+            1
+        } else {
+            0
+        }
+    };
+    let x = axis_direction(edges.x, rect.min.x, rect.max.x, bounds.min.x, bounds.max.x);
+    let y = axis_direction(edges.y, rect.min.y, rect.max.y, bounds.min.y, bounds.max.y);
+    let mut directions = vec![];
+    if x != 0 {
+        directions.push(IVec2::new(x, 0));
+    }
+    if y != 0 {
+        directions.push(IVec2::new(0, y));
+    }
+    if x != 0 && y != 0 {
+        directions.push(IVec2::new(x, y));
+    }
+    directions
+}
+
+// wrap_screen_edges moves a boid touching one edge to touch the opposite one, so positions a
+// world size less the boid's own size apart are the same point
+fn wrap_ghost_transform(
+    transform: &Transform,
+    rect: &Rect,
+    bounds: &Rect,
+    direction: IVec2,
+) -> Transform {
+    let period = (bounds.max - bounds.min) - (rect.max - rect.min);
+    let mut ghost = *transform;
+    ghost.translation += (direction.as_vec2() * period).extend(0.);
+    ghost
+}
+
+fn smoothed_velocity(previous: Vec3, steered: Vec3, smoothing: f32) -> Vec3 {
+    steered.lerp(previous, smoothing)
+}
+
+// both corners are compared so a boid that only grows or shrinks, e.g. while spawning, is still
+// reindexed once the change adds up
+fn needs_reindex(indexed_rect: &Rect, rect: &Rect, threshold: f32) -> bool {
+    let threshold_squared = threshold * threshold;
+    indexed_rect.min.distance_squared(rect.min) >= threshold_squared
+        || indexed_rect.max.distance_squared(rect.max) >= threshold_squared
+}
+
+// whether no obstacle blocks the straight line between two points
+fn line_of_sight(obstacles: &ObstacleQuadtree, from: Vec2, to: Vec2) -> bool {
+    let segment_bounds = Rect {
+        min: from.min(to),
+        max: from.max(to),
+    };
+    !obstacles
+        .values_intersecting(&segment_bounds)
+        .iter()
+        .any(|obstacle| segment_intersects_rect(from, to, obstacle.get_rect()))
+}
+
+// center of every boid, and the clusters of boids chained together within radius of each other
+fn boid_clusters(
+    boid_query: &Query<(Entity, &Transform), With<Boid>>,
+    neighbors: &NeighborQuery<EntityWrapper>,
+    alignment: SpawnAlignment,
+    radius: f32,
+) -> (HashMap<Entity, Vec2>, Vec<Vec<Entity>>) {
+    let centers: HashMap<Entity, Vec2> = boid_query
+        .iter()
+        .map(|(entity, transform)| {
+            let rect = alignment.transform_to_rect(transform);
+            (entity, (rect.min + rect.max) / 2.)
+        })
+        .collect();
+    let entities: Vec<Entity> = centers.keys().copied().collect();
+    let links = centers.iter().flat_map(|(&entity, &center)| {
+        neighbors
+            .within_radius(center, radius)
+            .into_iter()
+            .map(move |value| (entity, value.entity))
     });
+    let clusters = detect_clusters(&entities, links);
+    (centers, clusters)
+}
+
+// a cluster keeps its color for as long as it keeps its id. Ids are entity indices, so stepping
+// the hue by the golden angle per id spreads nearby ids around the color wheel
+pub fn cluster_color(cluster_id: ClusterId) -> Color {
+    species_color(&[], cluster_id.0 as usize)
+}
+
+// unit vector away from neighbors, closer neighbors weigh more according to the falloff
+fn separation_force<'a>(
+    entity: Entity,
+    my_rect: &Rect,
+    neighbors: impl IntoIterator<Item = &'a EntityWrapper>,
+    falloff: SeparationFalloff,
+    shape: CollisionShape,
+) -> Vec2 {
+    let diagonal = boid_diagonal(my_rect);
+    let mut force_vec = Vec2::ZERO;
+    for value in neighbors {
+        let delta_vec = match shape {
+            CollisionShape::Rect => my_rect.min - value.rect.min,
+            CollisionShape::Circle => (my_rect.min + my_rect.max) / 2. - value.get_center(),
+        };
+        // co-located boids have no direction to push apart along, so make one up
+        let direction_away = if delta_vec.length_squared() > EPS {
+            delta_vec.normalize_or_zero()
+        } else {
+            coincident_direction(entity, value.entity)
+        };
+        force_vec += direction_away * falloff.weight(delta_vec.length(), diagonal);
+    }
+    force_vec.normalize_or_zero()
+}
+
+// unit direction for pushing entity away from a boid at the same position, derived from both
+// entity ids so it is stable between ticks and the other boid gets exactly the opposite one
+fn coincident_direction(entity: Entity, other: Entity) -> Vec2 {
+    let (low, high) = if entity < other {
+        (entity, other)
+    } else {
+        (other, entity)
+    };
+    // multiplicative hash of the pair, spread over a full turn
+    let hash = low
+        .to_bits()
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .wrapping_add(high.to_bits())
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let angle = (hash >> 40) as f32 / (1u64 << 24) as f32 * std::f32::consts::TAU;
+    let direction = Vec2::from_angle(angle);
+    if entity < other {
+        direction
+    } else {
+        -direction
+    }
+}
+
+// radius of the circle a boid is treated as with CollisionShape::Circle
+fn inscribed_radius(rect: &Rect) -> f32 {
+    (rect.max - rect.min).min_element() / 2.
+}
+
+// diagonal of the boid's current rect, which follows its scale (e.g. while spawning in); never
+// zero so the falloffs can divide by it
+fn boid_diagonal(rect: &Rect) -> f32 {
+    (rect.max - rect.min).length().max(EPS)
+}
+
+// fastest rate at which any neighbor is approaching, given each neighbor with its velocity
+fn closing_speed<'a>(
+    my_rect: &Rect,
+    velocity: Vec3,
+    neighbors: impl IntoIterator<Item = (&'a EntityWrapper, Vec3)>,
+) -> f32 {
+    neighbors
+        .into_iter()
+        .map(|(value, neighbor_velocity)| {
+            let direction_toward = (value.rect.min - my_rect.min).normalize_or_zero();
+            (velocity - neighbor_velocity)
+                .truncate()
+                .dot(direction_toward)
+        })
+        .fold(0., f32::max)
+}
+
+// separation steers harder the faster a neighbor closes in, capped at two boids meeting head-on
+fn separation_weight(closing_speed: f32, params: &BoidParams) -> f32 {
+    let closing_ratio = (closing_speed / BOID_SPEED).clamp(0., 2.);
+    params.separation_strength * (1. + params.separation_closing_gain * closing_ratio)
+}
+
+// unit vector along the average of the neighbors' velocities or headings, needs at least two
+// neighbors
+fn alignment_force(velocities: impl IntoIterator<Item = Vec3>, mode: AlignmentMode) -> Vec2 {
+    let mut average_velocity = Vec2::ZERO;
+    let mut count = 0;
+    for velocity in velocities {
+        let velocity = velocity.truncate();
+        average_velocity += match mode {
+            AlignmentMode::Velocity => velocity,
+            // a stationary neighbor has no heading and adds nothing
+            AlignmentMode::Heading => velocity.normalize_or_zero(),
+        };
+        count += 1;
+    }
+    if count < 2 {
+        return Vec2::ZERO;
+    }
+    average_velocity /= count as f32;
+    if average_velocity.length_squared() > EPS {
+        average_velocity.normalize_or_zero()
+    } else {
+        Vec2::ZERO
+    }
+}
+
+// whether a neighbor heads close enough to the boid's own direction, a stationary boid or neighbor
+// has no heading and always counts. A cutoff of -1 is checked up front, rounding can put the dot
+// of opposite headings just below it
+fn similar_heading(velocity: Vec2, neighbor_velocity: Vec2, cutoff: f32) -> bool {
+    let heading = velocity.normalize_or_zero();
+    let neighbor_heading = neighbor_velocity.normalize_or_zero();
+    if cutoff <= -1. || heading == Vec2::ZERO || neighbor_heading == Vec2::ZERO {
+        return true;
+    }
+    heading.dot(neighbor_heading) >= cutoff
+}
+
+// vector toward the centroid of the neighbors at least floor away, as long as the share of
+// neighbors that are; zero once every neighbor is within floor
+fn cohesion_force<'a>(
+    my_rect: &Rect,
+    neighbors: impl IntoIterator<Item = &'a EntityWrapper>,
+    floor: f32,
+) -> Vec2 {
+    let my_center = (my_rect.min + my_rect.max) / 2.;
+    let mut centroid = Vec2::ZERO;
+    let mut count = 0;
+    let mut total = 0;
+    for value in neighbors {
+        let center = value.get_center();
+        if center.distance_squared(my_center) >= floor * floor {
+            centroid += center;
+            count += 1;
+        }
+        total += 1;
+    }
+    if count == 0 {
+        return Vec2::ZERO;
+    }
+    centroid /= count as f32;
+    let share = count as f32 / total as f32;
+    (centroid - my_center).normalize_or_zero() * share
+}
+
+// weighted sum of (direction, weight) pairs and the total weight of the rules that fired;
+// lerping by the total weight toward sum / total matches applying each rule's lerp in turn
+fn combine_steering(forces: &[(Vec2, f32)]) -> (Vec2, f32) {
+    forces
+        .iter()
+        .filter(|(direction, _)| direction.length_squared() > EPS)
+        .fold((Vec2::ZERO, 0.), |(sum, total), (direction, weight)| {
+            (sum + *direction * *weight, total + weight)
+        })
+}
+
+// inverse-square acceleration toward the attractor, zero outside its radius
+fn attractor_pull(position: Vec2, attractor: &Attractor) -> Vec2 {
+    let offset = attractor.position - position;
+    let distance = offset.length();
+    if distance > attractor.radius || distance < EPS {
+        return Vec2::ZERO;
+    }
+    // don't let the pull blow up as a boid passes through the attractor
+    let clamped_distance = distance.max(BOID_DIAG_LENGTH);
+    offset / distance * attractor.strength / (clamped_distance * clamped_distance)
+}
+
+// square neighborhood with the given half-width around the center of rect
+fn neighborhood_rect(rect: &Rect, radius: f32) -> Rect {
+    centered_rect((rect.min + rect.max) / 2., Vec2::splat(radius * 2.))
+}
+
+// flat copy of every boid, only needed by the brute-force neighbor strategy
+fn snapshot_boids(
+    strategy: NeighborStrategy,
+    alignment: SpawnAlignment,
+    kinematics_query: &Query<(&mut Kinematics, Entity, &Transform, Option<&Frozen>), With<Boid>>,
+) -> Vec<EntityWrapper> {
+    match strategy {
+        NeighborStrategy::Quadtree | NeighborStrategy::NodeCache => vec![],
+        NeighborStrategy::BruteForce => kinematics_query
+            .iter()
+            .map(|(kinematics, entity, transform, _)| {
+                EntityWrapper::new(entity, &kinematics.velocity, transform, alignment)
+            })
+            .collect(),
+    }
+}
+
+// candidate neighbors of every occupied quadtree node, empty unless the strategy is NodeCache;
+// boids are grouped by the node holding the center of their detection rect, which is what
+// nearby_boids looks up
+fn node_neighbor_cache<'a>(
+    strategy: NeighborStrategy,
+    quadtree: &'a EntityQuadtree,
+    alignment: SpawnAlignment,
+    radius: f32,
+    kinematics_query: &Query<(&mut Kinematics, Entity, &Transform, Option<&Frozen>), With<Boid>>,
+) -> NodeNeighbors<'a> {
+    let mut cache = NodeNeighbors::default();
+    if strategy != NeighborStrategy::NodeCache {
+        return cache;
+    }
+    for (_, _, transform, _) in kinematics_query.iter() {
+        let detection_rect = neighborhood_rect(&alignment.transform_to_rect(transform), radius);
+        let center = (detection_rect.min + detection_rect.max) / 2.;
+        if let Some(node) = quadtree.query_point(&center) {
+            cache.entry(node_key(node)).or_insert_with(|| {
+                // every detection rect centered in the node fits inside it grown by radius, plus
+                // some slack for rounding
+                let reach = Vec2::splat(radius + NODE_CACHE_SLACK);
+                quadtree.values_intersecting(&Rect {
+                    min: node.rect.min - reach,
+                    max: node.rect.max + reach,
+                })
+            });
+        }
+    }
+    cache
+}
+
+// nodes never overlap at the same depth, so a corner and a depth identify one
+fn node_key(node: &QuadtreeNode<EntityWrapper>) -> NodeKey {
+    (
+        node.rect.min.x.to_bits(),
+        node.rect.min.y.to_bits(),
+        node.depth,
+    )
+}
+
+// boids without a Layer are in the default one
+fn same_layer(layer: Option<&Layer>, other: Option<&Layer>) -> bool {
+    layer.copied().unwrap_or_default() == other.copied().unwrap_or_default()
+}
+
+// velocity of every boid as of now, keyed by entity
+fn live_velocities(
+    kinematics_query: &Query<(&mut Kinematics, Entity, &Transform, Option<&Frozen>), With<Boid>>,
+) -> HashMap<Entity, Vec3> {
+    kinematics_query
+        .iter()
+        .map(|(kinematics, entity, _, _)| (entity, kinematics.velocity))
+        .collect()
+}
+
+// value's entity's live velocity, or the stored one if the entity is gone
+fn live_velocity(value: &EntityWrapper, velocities: &HashMap<Entity, Vec3>) -> Vec3 {
+    velocities
+        .get(&value.entity)
+        .copied()
+        .unwrap_or(value.velocity)
+}
+
+// fill neighbors with the boids overlapping rect that aren't excluded, in a stable order so sums
+// are reproducible between runs; exclude is applied while searching, and must at least exclude
+// the boid itself
+fn nearby_boids<'a>(
+    strategy: NeighborStrategy,
+    quadtree: &'a EntityQuadtree,
+    all_boids: &'a [EntityWrapper],
+    node_cache: &NodeNeighbors<'a>,
+    rect: &Rect,
+    exclude: impl Fn(&EntityWrapper) -> bool,
+    neighbors: &mut Vec<&'a EntityWrapper>,
+) {
+    let cached = || {
+        let center = (rect.min + rect.max) / 2.;
+        node_cache.get(&node_key(quadtree.query_point(&center)?))
+    };
+    match strategy {
+        NeighborStrategy::Quadtree => {
+            quadtree.values_intersecting_into(rect, |v| !exclude(v), neighbors)
+        }
+        // a boid outside every cached node falls back to its own query
+        NeighborStrategy::NodeCache => match cached() {
+            Some(candidates) => {
+                collect_matching(candidates.iter().copied(), rect, &exclude, neighbors)
+            }
+            None => quadtree.values_intersecting_into(rect, |v| !exclude(v), neighbors),
+        },
+        NeighborStrategy::BruteForce => {
+            collect_matching(all_boids.iter(), rect, &exclude, neighbors)
+        }
+    }
+    neighbors.sort_unstable_by_key(|v| v.entity);
+}
+
+// nearby_boids for strategies that already have a candidate list instead of a tree query
+fn collect_matching<'a>(
+    candidates: impl Iterator<Item = &'a EntityWrapper>,
+    rect: &Rect,
+    exclude: &impl Fn(&EntityWrapper) -> bool,
+    neighbors: &mut Vec<&'a EntityWrapper>,
+) {
+    neighbors.clear();
+    neighbors.extend(
+        candidates.filter(|value| rect_intersects_rect(rect, value.get_rect()) && !exclude(value)),
+    );
+}
+
+// unit heading among OPEN_SPACE_SAMPLE_ANGLES off heading with the lowest density, zero if the
+// boid has no heading
+fn emptiest_direction(heading: Vec2, density: impl Fn(Vec2) -> usize) -> Vec2 {
+    let heading = heading.normalize_or_zero();
+    if heading == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    OPEN_SPACE_SAMPLE_ANGLES
+        .iter()
+        .map(|&angle| Vec2::from_angle(angle).rotate(heading))
+        .min_by_key(|&direction| density(direction))
+        .unwrap_or(heading)
+}
+
+// reflect velocity off any bouncing edge of bounds the boid at translation would be touching;
+// bounds is the quadtree's rect rather than the window so boids never leave the tree
+fn bounce_off_edges(
+    kinematics: &mut Kinematics,
+    translation: Vec3,
+    bounds: &Rect,
+    edges: EdgeBehavior,
+    restitution: f32,
+) {
+    let margin = (BOID_SCALE / 2.).extend(0.);
+    let loc = translation + margin;
+    // calculate distances
+    let distance_to_left = loc.x - bounds.min.x - margin.x;
+    let distance_to_right = bounds.max.x - loc.x - margin.x;
+    let distance_to_top = bounds.max.y - loc.y - margin.y;
+    let distance_to_bottom = loc.y - bounds.min.y - margin.y;
+    // bounce if too close to screen edge
+    if edges.x == EdgeMode::Bounce && (distance_to_left < EPS || distance_to_right < EPS) {
+        kinematics.velocity.x *= -restitution;
+    }
+    if edges.y == EdgeMode::Bounce && (distance_to_top < EPS || distance_to_bottom < EPS) {
+        kinematics.velocity.y *= -restitution;
+    }
+}
+
+// unit vector from a centroid out to the boid, zero for a boid sitting on the centroid
+fn split_force(boid_center: Vec2, centroid: Vec2) -> Vec2 {
+    (boid_center - centroid).normalize_or_zero()
+}
+
+// unit vector pointing away from the cursor, or zero if the boid is out of range
+fn scare_force(boid_center: Vec2, cursor: Vec2, radius: f32) -> Vec2 {
+    let delta_vec = boid_center - cursor;
+    if delta_vec.length_squared() > radius * radius {
+        return Vec2::ZERO;
+    }
+    delta_vec.normalize_or_zero()
+}
+
+// All steering forces act in the XY plane and are computed as Vec2, the z component of
+// velocity is carried through untouched so 2D steering can never introduce z motion.
+// Rotate velocity toward force_direction while preserving speed; boids that are
+// (nearly) stationary have no heading to rotate, so kick them along the force instead.
+fn steer_towards(velocity: Vec3, force_direction: Vec2, dampening: f32, min_speed: f32) -> Vec3 {
+    let planar_velocity = velocity.truncate();
+    let speed = planar_velocity.length();
+    if speed < min_speed {
+        return (force_direction.normalize_or_zero() * min_speed).extend(velocity.z);
+    }
+    let new_dir = planar_velocity
+        .normalize_or_zero()
+        .lerp(force_direction, dampening)
+        .normalize_or_zero();
+    (new_dir * speed).extend(velocity.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::schedule::{Stage, SystemStage},
+        prelude::*,
+        utils::HashSet,
+    };
+
+    use super::*;
+    use crate::ecs::test_support::{
+        advance_time, run_system, spawn_test_boid, test_world, velocity_of, WORLD,
+    };
+
+    #[test]
+    fn stationary_boid_with_neighbors_starts_moving() {
+        let mut world = test_world();
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        spawn_test_boid(&mut world, Vec2::new(1., 0.), Vec2::new(BOID_SPEED, 0.));
+        run_system(&mut world, flocking);
+        let speed = velocity_of(&world, boid).length();
+        let min_speed = world.resource::<BoidParams>().min_speed;
+        assert!((speed - min_speed).abs() < EPS, "speed {}", speed);
+    }
+
+    #[test]
+    fn uniform_speed_gives_every_boid_the_mean_speed() {
+        let mut world = test_world();
+        world.resource_mut::<BoidParams>().uniform_speed = true;
+        let boids = [
+            spawn_test_boid(&mut world, Vec2::new(-100., 0.), Vec2::new(50., 0.)),
+            spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., -100.)),
+            spawn_test_boid(&mut world, Vec2::new(100., 0.), Vec2::new(90., 120.)),
+        ];
+        run_system(&mut world, normalize_flock_speed);
+        for boid in boids {
+            let speed = velocity_of(&world, boid).length();
+            assert!((speed - 100.).abs() < 0.001, "speed {}", speed);
+        }
+    }
+
+    #[test]
+    fn spawn_animation_reaches_full_scale_over_its_duration() {
+        let mut world = test_world();
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        world.entity_mut(boid).insert(SpawnAnim::spawning());
+        let scale = |world: &World| world.get::<Transform>(boid).unwrap().scale.truncate();
+        advance_time(&mut world, SPAWN_ANIM_DURATION / 2.);
+        run_system(&mut world, animate_spawn_transitions);
+        assert!(scale(&world).abs_diff_eq(BOID_SCALE / 2., EPS));
+        assert!(world.get::<SpawnAnim>(boid).is_some());
+        advance_time(&mut world, SPAWN_ANIM_DURATION / 2.);
+        run_system(&mut world, animate_spawn_transitions);
+        assert!(scale(&world).abs_diff_eq(BOID_SCALE, EPS));
+        assert!(world.get::<SpawnAnim>(boid).is_none());
+    }
+
+    #[test]
+    fn despawn_animation_removes_the_boid_once_it_has_shrunk() {
+        let mut world = test_world();
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        world.entity_mut(boid).insert(SpawnAnim::despawning());
+        advance_time(&mut world, SPAWN_ANIM_DURATION / 2.);
+        run_system(&mut world, animate_spawn_transitions);
+        assert!(world.get_entity(boid).is_some());
+        advance_time(&mut world, SPAWN_ANIM_DURATION / 2.);
+        run_system(&mut world, animate_spawn_transitions);
+        assert!(world.get_entity(boid).is_none());
+    }
+
+    #[test]
+    fn steering_keeps_boids_in_the_plane() {
+        // the same flock with and without depth: z must neither steer nor be steered
+        let steer = |depth: f32| {
+            let mut world = test_world();
+            let boids: Vec<Entity> = (0..9)
+                .map(|i| {
+                    let position = Vec2::new((i % 3) as f32 * 4., (i / 3) as f32 * 4.);
+                    let velocity = Vec2::new(i as f32 * 10. - 40., 30. - i as f32 * 5.);
+                    let boid = spawn_test_boid(&mut world, position, velocity);
+                    world.get_mut::<Kinematics>(boid).unwrap().velocity.z = depth * (i + 1) as f32;
+                    world.get_mut::<Transform>(boid).unwrap().translation.z += depth * i as f32;
+                    boid
+                })
+                .collect();
+            run_system(&mut world, flocking);
+            boids
+                .into_iter()
+                .map(|boid| world.get::<Kinematics>(boid).unwrap().velocity)
+                .collect::<Vec<_>>()
+        };
+        let flat = steer(0.);
+        let deep = steer(25.);
+        for (i, (flat, deep)) in flat.iter().zip(&deep).enumerate() {
+            assert_eq!(deep.z, 25. * (i + 1) as f32);
+            assert_eq!(deep.truncate(), flat.truncate());
+        }
+        // and steering did turn the flock, so the comparison isn't between two untouched runs
+        let before = Vec2::new(-40., 30.);
+        assert_ne!(flat[0].truncate(), before);
+    }
+
+    #[test]
+    fn steering_is_bit_identical_whatever_order_the_tree_holds_neighbors_in() {
+        let steered_velocities = |reverse: bool| {
+            let mut world = test_world();
+            let boids: Vec<Entity> = (0..16)
+                .map(|i| {
+                    let position = Vec2::new((i % 4) as f32 * 3., (i / 4) as f32 * 3.);
+                    let velocity = Vec2::new(20. + i as f32 * 7.3, 45. - i as f32 * 3.1);
+                    spawn_test_boid(&mut world, position, velocity)
+                })
+                .collect();
+            // same boids, same entity ids, inserted into a fresh tree in the opposite order
+            if reverse {
+                let mut values: Vec<EntityWrapper> = world
+                    .resource::<EntityQuadtree>()
+                    .values_intersecting(&WORLD)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                values.sort_unstable_by_key(|v| std::cmp::Reverse(v.entity));
+                let mut quadtree = EntityQuadtree::empty(WORLD);
+                for value in values {
+                    quadtree.add(value);
+                }
+                world.insert_resource(quadtree);
+            }
+            run_system(&mut world, flocking);
+            boids
+                .into_iter()
+                .map(|boid| velocity_of(&world, boid).to_array())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(steered_velocities(false), steered_velocities(false));
+        assert_eq!(steered_velocities(false), steered_velocities(true));
+    }
+
+    #[test]
+    fn scare_pushes_boids_inside_the_radius_away_from_the_cursor() {
+        let cursor = Vec2::new(10., -5.);
+        let inside = cursor + Vec2::new(3., 4.);
+        let force = scare_force(inside, cursor, 20.);
+        assert!(
+            force.abs_diff_eq(Vec2::new(0.6, 0.8), EPS),
+            "force {}",
+            force
+        );
+        assert!(force.dot(inside - cursor) > 0.);
+        let outside = cursor + Vec2::new(0., 21.);
+        assert_eq!(scare_force(outside, cursor, 20.), Vec2::ZERO);
+    }
+
+    #[test]
+    fn follower_next_to_a_leader_steers_toward_it() {
+        let mut world = test_world();
+        let follower = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., 100.));
+        let leader = spawn_test_boid(&mut world, Vec2::new(10., 0.), Vec2::new(0., 100.));
+        world.entity_mut(leader).insert(Leader);
+        run_system(&mut world, follow_leaders);
+        let velocity = velocity_of(&world, follower);
+        assert!(velocity.x > 0., "velocity {}", velocity);
+        assert!((velocity.length() - 100.).abs() < 0.001);
+        assert_eq!(velocity_of(&world, leader), Vec2::new(0., 100.));
+    }
+
+    #[test]
+    fn brute_force_finds_the_same_neighbors_as_the_quadtree() {
+        let mut world = test_world();
+        for i in 0..300 {
+            let cell = i * 7919 % 300;
+            let position = Vec2::new((cell % 20) as f32, (cell / 20) as f32) * 6. - 60.;
+            spawn_test_boid(&mut world, position, Vec2::new(10., 0.));
+        }
+        let alignment = world.resource::<SpawnConfig>().alignment;
+        let all_boids: Vec<EntityWrapper> = world
+            .query::<(&Kinematics, Entity, &Transform)>()
+            .iter(&world)
+            .map(|(kinematics, entity, transform)| {
+                EntityWrapper::new(entity, &kinematics.velocity, transform, alignment)
+            })
+            .collect();
+        let quadtree = world.resource::<EntityQuadtree>();
+        let radius = world.resource::<BoidParams>().cohesion_radius;
+        let neighbor_ids = |strategy: NeighborStrategy, rect: &Rect, entity: Entity| {
+            let mut neighbors = vec![];
+            nearby_boids(
+                strategy,
+                quadtree,
+                &all_boids,
+                &NodeNeighbors::default(),
+                rect,
+                |v| v.entity == entity,
+                &mut neighbors,
+            );
+            neighbors.iter().map(|v| v.entity).collect::<Vec<_>>()
+        };
+        for boid in &all_boids {
+            let rect = neighborhood_rect(&boid.rect, radius);
+            let expected = neighbor_ids(NeighborStrategy::BruteForce, &rect, boid.entity);
+            assert!(!expected.is_empty());
+            assert_eq!(
+                neighbor_ids(NeighborStrategy::Quadtree, &rect, boid.entity),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn frozen_boid_stays_put_but_is_still_a_neighbor() {
+        let mut world = test_world();
+        let frozen = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(30., 0.));
+        world.entity_mut(frozen).insert(Frozen);
+        let other = spawn_test_boid(&mut world, Vec2::new(4., 4.), Vec2::new(-30., 0.));
+        let transform = *world.get::<Transform>(frozen).unwrap();
+        for _ in 0..5 {
+            run_system(&mut world, flocking);
+            run_system(&mut world, apply_kinematics);
+            run_system(&mut world, update_quadtree);
+        }
+        assert_eq!(*world.get::<Transform>(frozen).unwrap(), transform);
+        assert_eq!(velocity_of(&world, frozen), Vec2::new(30., 0.));
+        // the other boid kept moving, and still finds the frozen one around it
+        let other_position = world
+            .get::<Transform>(other)
+            .unwrap()
+            .translation
+            .truncate();
+        assert_ne!(other_position, Vec2::new(4., 4.));
+        let around_other = world
+            .resource::<EntityQuadtree>()
+            .query_radius(other_position, 30.);
+        assert!(around_other.iter().any(|v| v.entity == frozen));
+    }
+
+    #[test]
+    fn nan_velocity_is_reset() {
+        let mut world = test_world();
+        let broken = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(f32::NAN, 1.));
+        world.get_mut::<Kinematics>(broken).unwrap().acceleration = Vec3::splat(f32::INFINITY);
+        let healthy = spawn_test_boid(&mut world, Vec2::new(20., 0.), Vec2::new(5., 1.));
+        run_system(&mut world, sanitize_kinematics);
+        let kinematics = world.get::<Kinematics>(broken).unwrap();
+        assert_eq!(kinematics.velocity, Vec3::ZERO);
+        assert_eq!(kinematics.acceleration, Vec3::ZERO);
+        assert_eq!(velocity_of(&world, healthy), Vec2::new(5., 1.));
+    }
+
+    #[test]
+    fn boid_outside_the_bounds_is_reported_and_pulled_back() {
+        let mut world = test_world();
+        let inside = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        let outside_position = Vec2::new(WORLD.max.x + 5., 0.);
+        let outside = spawn_test_boid(&mut world, outside_position, Vec2::ZERO);
+        run_system(&mut world, detect_escaped_boids);
+        let events = world.resource::<Events<BoidEscaped>>();
+        let escaped: Vec<(Entity, Vec2)> = events
+            .get_reader()
+            .iter(events)
+            .map(|event| (event.entity, event.position))
+            .collect();
+        assert_eq!(escaped, vec![(outside, outside_position)]);
+        let rect =
+            SpawnAlignment::Corner.transform_to_rect(world.get::<Transform>(outside).unwrap());
+        assert!(rect_contains_rect(&WORLD, &rect));
+        assert_eq!(
+            world
+                .get::<Transform>(inside)
+                .unwrap()
+                .translation
+                .truncate(),
+            Vec2::ZERO
+        );
+    }
+
+    #[test]
+    fn each_radius_only_widens_its_own_rule() {
+        // velocity of a boid with two neighbors 15 away, with only behavior on and the given radii
+        let steered = |behavior: BehaviorMask, radii: [f32; 3]| {
+            let mut world = test_world();
+            world.insert_resource(behavior);
+            let mut params = world.resource_mut::<BoidParams>();
+            params.separation_radius = radii[0];
+            params.alignment_radius = radii[1];
+            params.cohesion_radius = radii[2];
+            // the default curve has fallen off to nothing this far out
+            params.separation_falloff = SeparationFalloff::InverseSquare;
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., 50.));
+            spawn_test_boid(&mut world, Vec2::new(15., 0.), Vec2::new(30., 40.));
+            spawn_test_boid(&mut world, Vec2::new(15., 3.), Vec2::new(30., 40.));
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid)
+        };
+        let (near, far) = (1., 40.);
+        let rules = [
+            BehaviorMask::SEPARATION,
+            BehaviorMask::ALIGNMENT,
+            BehaviorMask::COHESION,
+        ];
+        for (index, behavior) in rules.into_iter().enumerate() {
+            let mut own_far = [near; 3];
+            own_far[index] = far;
+            let mut others_far = [far; 3];
+            others_far[index] = near;
+            assert_eq!(steered(behavior, [near; 3]), Vec2::new(0., 50.));
+            assert_eq!(steered(behavior, others_far), Vec2::new(0., 50.));
+            assert_ne!(steered(behavior, own_far), Vec2::new(0., 50.));
+        }
+    }
+
+    #[test]
+    fn combined_steering_is_the_weighted_sum_of_the_rules() {
+        let separation = Vec2::new(-1., 0.);
+        let alignment = Vec2::new(0.6, 0.8);
+        let (force, total_weight) = combine_steering(&[
+            (separation, 0.5),
+            (alignment, 0.25),
+            // a rule that found nothing doesn't count toward the total
+            (Vec2::ZERO, 0.75),
+        ]);
+        assert!(force.abs_diff_eq(separation * 0.5 + alignment * 0.25, EPS));
+        assert_eq!(total_weight, 0.75);
+    }
+
+    #[test]
+    fn fast_closing_neighbor_gets_more_separation() {
+        // angle a boid heading up turns through with one neighbor just right of it closing at speed
+        let turned = |closing: f32| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::SEPARATION);
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., 50.));
+            spawn_test_boid(&mut world, Vec2::new(1.5, 0.), Vec2::new(-closing, 50.));
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid).angle_between(Vec2::Y).abs()
+        };
+        let slow = turned(5.);
+        let fast = turned(200.);
+        assert!(slow > 0.);
+        assert!(fast > slow, "fast {} slow {}", fast, slow);
+    }
+
+    #[test]
+    fn edge_lookahead_predicts_where_apply_kinematics_moves_boids() {
+        for integrator in [Integrator::Euler, Integrator::RK4, Integrator::Verlet] {
+            for substeps in [1, 4] {
+                let mut world = test_world();
+                let mut physics = world.resource_mut::<PhysicsConfig>();
+                physics.integrator = integrator;
+                physics.substeps = substeps;
+                let delta_time = physics.delta_time();
+                let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(40., -25.));
+                let start = world.get::<Transform>(boid).unwrap().translation;
+                let mut kinematics = world.get_mut::<Kinematics>(boid).unwrap();
+                kinematics.acceleration = Vec3::new(-30., 20., 0.);
+                // the whole step, which avoid_screen_edges previews one substep of
+                let predicted = (0..substeps)
+                    .map(|_| kinematics.substep(delta_time, substeps, integrator))
+                    .fold(Vec3::ZERO, |sum, displacement| sum + displacement);
+                run_system(&mut world, apply_kinematics);
+                let moved = world.get::<Transform>(boid).unwrap().translation - start;
+                assert!(
+                    moved.abs_diff_eq(predicted, 0.0001),
+                    "{:?} x{}: moved {} predicted {}",
+                    integrator,
+                    substeps,
+                    moved,
+                    predicted
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn boid_crossing_into_a_sibling_cell_is_re_placed() {
+        let mut world = test_world();
+        // enough boids, clear of the center lines, to split the root and fill it again so the
+        // next one goes down to a child
+        for i in 0..600 {
+            let cell = Vec2::new((i % 30) as f32 * 64. + 32., (i / 30) as f32 * 54. + 20.);
+            spawn_test_boid(&mut world, WORLD.min + cell, Vec2::ZERO);
+        }
+        let boid = spawn_test_boid(&mut world, Vec2::new(-100., -100.), Vec2::ZERO);
+        let find = |world: &World| {
+            let wrapper = EntityWrapper::new(
+                boid,
+                &Vec3::ZERO,
+                world.get::<Transform>(boid).unwrap(),
+                SpawnAlignment::Corner,
+            );
+            let quadtree = world.resource::<EntityQuadtree>();
+            let node = quadtree.query_value(&wrapper).unwrap();
+            let stored = node.values.get(&wrapper).unwrap();
+            (node.depth, node.rect, stored.rect)
+        };
+        let (depth, before, _) = find(&world);
+        assert_eq!(depth, 1);
+
+        // across the vertical center line, still inside the root
+        world.get_mut::<Transform>(boid).unwrap().translation.x = 100.;
+        run_system(&mut world, update_quadtree);
+        let (depth, after, stored) = find(&world);
+        assert_eq!(depth, 1);
+        assert!(!rect_contains_rect(&before, &stored));
+        assert!(rect_contains_rect(&after, &stored));
+        assert_eq!(stored.min.x, 100.);
+        assert_eq!(world.get::<QuadtreeRect>(boid).unwrap().0.min.x, 100.);
+        world.resource::<EntityQuadtree>().validate();
+    }
+
+    #[test]
+    fn attractor_pulls_boids_in_range_and_ignores_the_rest() {
+        let mut world = test_world();
+        world.spawn().insert(Attractor {
+            position: Vec2::new(100., 0.),
+            strength: 50_000.,
+            radius: 50.,
+        });
+        let near = spawn_test_boid(&mut world, Vec2::new(70., 0.), Vec2::ZERO);
+        let far = spawn_test_boid(&mut world, Vec2::new(-100., 0.), Vec2::new(0., 20.));
+        run_system(&mut world, apply_attractors);
+        let pulled = velocity_of(&world, near);
+        assert!(pulled.x > 0.);
+        assert_eq!(pulled.y, 0.);
+        assert_eq!(velocity_of(&world, far), Vec2::new(0., 20.));
+    }
+
+    #[test]
+    fn disabling_every_behavior_leaves_velocities_unchanged() {
+        let mut world = test_world();
+        world.insert_resource(BehaviorMask(0));
+        world.spawn().insert(Attractor {
+            position: Vec2::new(20., 0.),
+            strength: 50_000.,
+            radius: 100.,
+        });
+        // crowded, following a leader, and one about to hit the right edge
+        let boids = [
+            spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., 100.)),
+            spawn_test_boid(&mut world, Vec2::new(1., 1.), Vec2::new(-80., 20.)),
+            spawn_test_boid(&mut world, Vec2::new(5., -3.), Vec2::new(30., 60.)),
+            spawn_test_boid(
+                &mut world,
+                Vec2::new(WORLD.max.x - 5., 0.),
+                Vec2::new(100., 0.),
+            ),
+        ];
+        world.entity_mut(boids[2]).insert(Leader);
+        let before: Vec<Vec2> = boids.iter().map(|&b| velocity_of(&world, b)).collect();
+        run_system(&mut world, flocking);
+        run_system(&mut world, follow_leaders);
+        run_system(&mut world, apply_attractors);
+        run_system(&mut world, avoid_screen_edges);
+        let after: Vec<Vec2> = boids.iter().map(|&b| velocity_of(&world, b)).collect();
+        assert_eq!(before, after);
+
+        // the same setup does steer with the behaviors on
+        world.insert_resource(BehaviorMask::ALL);
+        run_system(&mut world, flocking);
+        let steered: Vec<Vec2> = boids.iter().map(|&b| velocity_of(&world, b)).collect();
+        assert_ne!(before, steered);
+    }
+
+    #[test]
+    fn half_restitution_halves_the_perpendicular_speed() {
+        let mut world = test_world();
+        world.resource_mut::<BoidParams>().bounce_restitution = 0.5;
+        let boid = spawn_test_boid(
+            &mut world,
+            Vec2::new(WORLD.max.x - 3., 0.),
+            Vec2::new(100., 40.),
+        );
+        run_system(&mut world, avoid_screen_edges);
+        // only the component into the wall is reflected and damped
+        assert_eq!(velocity_of(&world, boid), Vec2::new(-50., 40.));
+    }
+
+    #[test]
+    fn excluded_boids_are_absent_from_the_neighbors() {
+        let mut world = test_world();
+        let boids: Vec<Entity> = (0..20)
+            .map(|i| {
+                let position = Vec2::new((i % 5) as f32, (i / 5) as f32) * 4.;
+                spawn_test_boid(&mut world, position, Vec2::ZERO)
+            })
+            .collect();
+        let excluded: HashSet<Entity> = boids.iter().copied().step_by(3).collect();
+        let alignment = world.resource::<SpawnConfig>().alignment;
+        let all_boids: Vec<EntityWrapper> = world
+            .query::<(&Kinematics, Entity, &Transform)>()
+            .iter(&world)
+            .map(|(kinematics, entity, transform)| {
+                EntityWrapper::new(entity, &kinematics.velocity, transform, alignment)
+            })
+            .collect();
+        let quadtree = world.resource::<EntityQuadtree>();
+        let rect = Rect {
+            min: Vec2::splat(-10.),
+            max: Vec2::splat(30.),
+        };
+        for strategy in [NeighborStrategy::Quadtree, NeighborStrategy::BruteForce] {
+            let mut neighbors = vec![];
+            nearby_boids(
+                strategy,
+                quadtree,
+                &all_boids,
+                &NodeNeighbors::default(),
+                &rect,
+                |v| excluded.contains(&v.entity),
+                &mut neighbors,
+            );
+            let found: Vec<Entity> = neighbors.iter().map(|v| v.entity).collect();
+            assert_eq!(found.len(), boids.len() - excluded.len(), "{:?}", strategy);
+            assert!(found.iter().all(|entity| !excluded.contains(entity)));
+        }
+    }
+
+    #[test]
+    fn refitting_around_a_clustered_flock_leaves_fewer_empty_leaves() {
+        let mut world = test_world();
+        world.insert_resource(QuadtreeFit {
+            enabled: true,
+            interval: 0.,
+            padding: 5.,
+        });
+        // packed into 60x60 near the top right corner of the world
+        for i in 0..600 {
+            let cell = Vec2::new((i % 25) as f32, (i / 25) as f32) * 2.5;
+            spawn_test_boid(&mut world, Vec2::new(300., 200.) + cell, Vec2::ZERO);
+        }
+        let empty_leaf_fraction = |quadtree: &EntityQuadtree| {
+            let leaves = quadtree
+                .root
+                .aggregate_statistic(&|node| node.is_leaf() as usize);
+            let empty = quadtree
+                .root
+                .aggregate_statistic(&|node| (node.is_leaf() && node.values.is_empty()) as usize);
+            empty as f32 / leaves as f32
+        };
+        let before = empty_leaf_fraction(world.resource::<EntityQuadtree>());
+        run_system(&mut world, refit_quadtree);
+        let quadtree = world.resource::<EntityQuadtree>();
+        assert_eq!(quadtree.len(), 600);
+        assert!(quadtree.rect.max.x - quadtree.rect.min.x < 100.);
+        let after = empty_leaf_fraction(quadtree);
+        assert!(after < before, "{} empty before, {} after", before, after);
+    }
+
+    #[test]
+    fn emptiest_direction_picks_the_least_dense_sample() {
+        let heading = Vec2::new(3., 0.);
+        // crowded everywhere except well to the right of the heading
+        let density = |direction: Vec2| if direction.y < -0.5 { 1 } else { 10 };
+        let direction = emptiest_direction(heading, density);
+        assert!(direction.abs_diff_eq(Vec2::from_angle(-0.8), EPS));
+
+        // a uniform field keeps the current course
+        assert!(emptiest_direction(heading, |_| 4).abs_diff_eq(Vec2::X, EPS));
+        // blocked straight ahead and emptier to the left, ties go to the smaller turn
+        let blocked = |direction: Vec2| match direction.y {
+            y if y.abs() < 0.1 => 9,
+            y if y > 0. => 3,
+            _ => 5,
+        };
+        let direction = emptiest_direction(heading, blocked);
+        assert!(direction.abs_diff_eq(Vec2::from_angle(0.4), EPS));
+        assert_eq!(emptiest_direction(Vec2::ZERO, density), Vec2::ZERO);
+    }
+
+    #[test]
+    fn stored_velocities_match_live_ones_after_an_update_tick() {
+        let mut world = test_world();
+        let boids: Vec<Entity> = (0..10)
+            .map(|i| spawn_test_boid(&mut world, Vec2::new(i as f32 * 20., 0.), Vec2::X))
+            .collect();
+        for (i, &boid) in boids.iter().enumerate() {
+            let velocity = Vec3::new(i as f32, -3., 0.);
+            world.get_mut::<Kinematics>(boid).unwrap().velocity = velocity;
+            // some barely move, some turn without moving at all
+            world.get_mut::<Transform>(boid).unwrap().translation.y += (i % 3) as f32 * 0.1;
+        }
+        run_system(&mut world, update_quadtree);
+        let quadtree = world.resource::<EntityQuadtree>();
+        let stored = quadtree.values_intersecting(&quadtree.rect);
+        assert_eq!(stored.len(), boids.len());
+        for value in stored {
+            let live = world.get::<Kinematics>(value.entity).unwrap().velocity;
+            assert_eq!(value.velocity, live);
+        }
+    }
+
+    #[test]
+    fn four_substeps_move_a_constant_velocity_boid_as_far_as_one_step() {
+        let velocity = Vec2::new(120., -45.);
+        let moved = |substeps: usize| {
+            let mut world = test_world();
+            world.resource_mut::<PhysicsConfig>().substeps = substeps;
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, velocity);
+            run_system(&mut world, apply_kinematics);
+            world.get::<Transform>(boid).unwrap().translation.truncate()
+        };
+        let delta_time = PhysicsConfig::default().delta_time();
+        let single = moved(1);
+        assert!(single.abs_diff_eq(velocity * delta_time, EPS));
+        assert!(moved(4).abs_diff_eq(single, EPS));
+
+        let kinematics = Kinematics {
+            velocity: velocity.extend(0.),
+            acceleration: Vec3::ZERO,
+            preferred_speed: BOID_SPEED,
+        };
+        let integrator = PhysicsConfig::default().integrator;
+        let substep = kinematics.substep(delta_time, 4, integrator);
+        assert!((substep * 4.).abs_diff_eq(kinematics.step(delta_time, integrator), EPS));
+    }
+
+    #[test]
+    fn separation_is_boosted_only_while_settling() {
+        let mut world = test_world();
+        world.insert_resource(SettlePhase::new(2));
+        for _ in 0..2 {
+            let settle = world.resource::<SettlePhase>();
+            assert_eq!(settle.separation_scale(), SettlePhase::SEPARATION_SCALE);
+            run_system(&mut world, advance_settle_phase);
+        }
+        for _ in 0..2 {
+            assert_eq!(world.resource::<SettlePhase>().separation_scale(), 1.);
+            run_system(&mut world, advance_settle_phase);
+        }
+
+        // the boosted weight turns a crowded boid harder than the normal one
+        let turned = |settle: SettlePhase| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::SEPARATION);
+            world.insert_resource(settle);
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., BOID_SPEED));
+            spawn_test_boid(&mut world, Vec2::new(2., 0.), Vec2::new(0., BOID_SPEED));
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid).x.abs()
+        };
+        assert!(turned(SettlePhase::new(5)) > turned(SettlePhase::new(0)));
+    }
+
+    #[test]
+    fn co_located_boids_are_pushed_in_opposite_directions() {
+        let mut world = test_world();
+        world.insert_resource(BehaviorMask::SEPARATION);
+        let a = spawn_test_boid(&mut world, Vec2::new(10., 10.), Vec2::ZERO);
+        let b = spawn_test_boid(&mut world, Vec2::new(10., 10.), Vec2::ZERO);
+        let direction = coincident_direction(a, b);
+        assert!((direction.length() - 1.).abs() < EPS);
+        assert_eq!(coincident_direction(b, a), -direction);
+
+        run_system(&mut world, flocking);
+        let (velocity_a, velocity_b) = (velocity_of(&world, a), velocity_of(&world, b));
+        assert!(velocity_a.length() > EPS);
+        assert_eq!(velocity_a, -velocity_b);
+    }
+
+    #[test]
+    fn boids_in_another_layer_are_ignored_by_alignment() {
+        // velocity of a northbound boid next to two eastbound ones in the given layer
+        let steered = |neighbor_layer: Layer| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::ALIGNMENT);
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., BOID_SPEED));
+            for position in [Vec2::new(5., 0.), Vec2::new(0., 5.)] {
+                let neighbor = spawn_test_boid(&mut world, position, Vec2::new(BOID_SPEED, 0.));
+                world.entity_mut(neighbor).insert(neighbor_layer);
+            }
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid)
+        };
+        assert_eq!(steered(Layer(1)), Vec2::new(0., BOID_SPEED));
+        // boids without a Layer are in layer 0, so these do turn it
+        assert!(steered(Layer(0)).x > 0.);
+    }
+
+    #[test]
+    fn zero_alignment_strength_leaves_the_heading_unchanged() {
+        // velocity of a northbound boid between two eastbound ones, with only alignment on
+        let steered = |alignment_strength: f32| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::ALIGNMENT);
+            world.resource_mut::<BoidParams>().alignment_strength = alignment_strength;
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., BOID_SPEED));
+            spawn_test_boid(&mut world, Vec2::new(5., 0.), Vec2::new(BOID_SPEED, 0.));
+            spawn_test_boid(&mut world, Vec2::new(0., 5.), Vec2::new(BOID_SPEED, 0.));
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid)
+        };
+        assert_eq!(steered(0.), Vec2::new(0., BOID_SPEED));
+        assert!(steered(BoidParams::default().alignment_strength).x > 0.);
+    }
+
+    #[test]
+    fn impulse_is_applied_once_and_skips_frozen_boids() {
+        let mut world = test_world();
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(10., 0.));
+        let frozen = spawn_test_boid(&mut world, Vec2::new(50., 0.), Vec2::new(10., 0.));
+        world.entity_mut(frozen).insert(Frozen);
+        world.send_event(Impulse {
+            velocity: Vec2::new(0., IMPULSE_SPEED),
+        });
+        // one stage, so its reader remembers what it has already read
+        let mut stage = SystemStage::single_threaded().with_system(apply_impulse);
+        for _ in 0..3 {
+            stage.run(&mut world);
+        }
+        assert_eq!(velocity_of(&world, boid), Vec2::new(10., IMPULSE_SPEED));
+        assert_eq!(velocity_of(&world, frozen), Vec2::new(10., 0.));
+    }
+
+    #[test]
+    fn node_cache_steers_every_boid_like_per_boid_queries() {
+        let steered = |strategy: NeighborStrategy| {
+            let mut world = test_world();
+            world.insert_resource(strategy);
+            // enough boids to split the root, so neighborhoods straddle several nodes
+            let boids: Vec<Entity> = (0..400)
+                .map(|i| {
+                    let cell = i * 7919 % 400;
+                    let position = Vec2::new((cell % 20) as f32, (cell / 20) as f32) * 7. - 70.;
+                    let velocity = Vec2::new(30. + (i % 7) as f32 * 5., (i % 11) as f32 * 4.);
+                    spawn_test_boid(&mut world, position, velocity)
+                })
+                .collect();
+            assert!(!world.resource::<EntityQuadtree>().root.is_leaf());
+            run_system(&mut world, flocking);
+            boids
+                .iter()
+                .map(|&boid| velocity_of(&world, boid))
+                .collect::<Vec<_>>()
+        };
+        let expected = steered(NeighborStrategy::Quadtree);
+        assert_eq!(steered(NeighborStrategy::NodeCache), expected);
+        assert_eq!(steered(NeighborStrategy::BruteForce), expected);
+    }
+
+    #[test]
+    fn heading_alignment_ignores_neighbor_speed() {
+        let neighbor = |id: u32, velocity: Vec2| {
+            let transform = Transform::from_translation(Vec3::new(id as f32 * 5., 0., 0.));
+            EntityWrapper::new(
+                Entity::from_raw(id),
+                &velocity.extend(0.),
+                &transform,
+                SpawnAlignment::Corner,
+            )
+        };
+        // a fast eastbound neighbor and a slow northbound one
+        let neighbors = [
+            neighbor(0, Vec2::new(200., 0.)),
+            neighbor(1, Vec2::new(0., 20.)),
+        ];
+        let velocities =
+            |neighbors: &[EntityWrapper]| neighbors.iter().map(|v| v.velocity).collect::<Vec<_>>();
+        let heading = alignment_force(velocities(&neighbors), AlignmentMode::Heading);
+        assert!(heading.abs_diff_eq(Vec2::new(1., 1.).normalize(), EPS));
+        let velocity = alignment_force(velocities(&neighbors), AlignmentMode::Velocity);
+        assert!(velocity.x > 0.99 && velocity.y > 0.);
+
+        // same directions at different speeds give the same target as at equal speeds
+        let uneven = [
+            neighbor(2, Vec2::new(5., 5.)),
+            neighbor(3, Vec2::new(-80., 80.)),
+        ];
+        let even = [
+            neighbor(4, Vec2::new(1., 1.)),
+            neighbor(5, Vec2::new(-1., 1.)),
+        ];
+        assert!(
+            alignment_force(velocities(&uneven), AlignmentMode::Heading).abs_diff_eq(
+                alignment_force(velocities(&even), AlignmentMode::Heading),
+                EPS
+            )
+        );
+        assert!(
+            alignment_force(velocities(&even), AlignmentMode::Heading).abs_diff_eq(Vec2::Y, EPS)
+        );
+    }
+
+    #[test]
+    fn despawned_boids_leave_the_quadtree_in_one_pass() {
+        let mut world = test_world();
+        let boids: Vec<Entity> = (0..30)
+            .map(|i| spawn_test_boid(&mut world, Vec2::new(i as f32 * 10., 0.), Vec2::ZERO))
+            .collect();
+        let despawned: Vec<Entity> = boids.iter().copied().step_by(3).collect();
+        run_system(&mut world, move |mut commands: Commands| {
+            for &entity in &despawned {
+                commands.entity(entity).despawn();
+            }
+        });
+        run_system(&mut world, remove_despawned_boids);
+
+        let quadtree = world.resource::<EntityQuadtree>();
+        assert_eq!(quadtree.len(), 20);
+        let stored: HashSet<Entity> = quadtree
+            .values_intersecting(&quadtree.rect)
+            .iter()
+            .map(|value| value.entity)
+            .collect();
+        let alive: HashSet<Entity> = boids
+            .iter()
+            .copied()
+            .filter(|&b| world.get_entity(b).is_some())
+            .collect();
+        assert_eq!(stored, alive);
+        quadtree.validate();
+    }
+
+    #[test]
+    fn alarm_spreads_weaker_to_neighbors_and_decays_away() {
+        let mut world = test_world();
+        let falloff = world.resource::<BoidParams>().alarm_falloff;
+        let source = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        let near = spawn_test_boid(&mut world, Vec2::new(10., 0.), Vec2::ZERO);
+        let far = spawn_test_boid(&mut world, Vec2::new(300., 0.), Vec2::ZERO);
+        world.entity_mut(source).insert(Alarmed { ttl: 1. });
+        let ttl_of = |world: &World, entity: Entity| world.get::<Alarmed>(entity).map(|a| a.ttl);
+
+        for _ in 0..2 {
+            run_system(&mut world, propagate_alarm);
+            assert_eq!(ttl_of(&world, source), Some(1.));
+            assert_eq!(ttl_of(&world, near), Some(falloff));
+            assert_eq!(ttl_of(&world, far), None);
+        }
+
+        let delta_time = world.resource::<PhysicsConfig>().delta_time();
+        run_system(&mut world, decay_alarm);
+        assert!((ttl_of(&world, source).unwrap() - (1. - delta_time)).abs() < EPS);
+        for _ in 0..(1. / delta_time).ceil() as usize {
+            run_system(&mut world, decay_alarm);
+        }
+        assert_eq!(world.query::<&Alarmed>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn boid_moving_less_than_the_threshold_is_not_reindexed() {
+        let mut world = test_world();
+        world.resource_mut::<PhysicsConfig>().reindex_threshold = 5.;
+        let boid = spawn_test_boid(&mut world, Vec2::new(40., 40.), Vec2::ZERO);
+        let indexed_at = |world: &World| {
+            let wrapper = EntityWrapper::new(
+                boid,
+                &Vec3::ZERO,
+                &Transform::default(),
+                SpawnAlignment::Corner,
+            );
+            let stored = world
+                .resource::<EntityQuadtree>()
+                .query_value(&wrapper)
+                .and_then(|node| node.values.get(&wrapper))
+                .unwrap()
+                .rect
+                .min;
+            assert_eq!(world.get::<QuadtreeRect>(boid).unwrap().0.min, stored);
+            stored
+        };
+
+        world.get_mut::<Transform>(boid).unwrap().translation.x += 3.;
+        run_system(&mut world, update_quadtree);
+        assert_eq!(indexed_at(&world), Vec2::new(40., 40.));
+
+        // drift adds up against where the boid was indexed, not the last tick
+        world.get_mut::<Transform>(boid).unwrap().translation.x += 3.;
+        run_system(&mut world, update_quadtree);
+        assert_eq!(indexed_at(&world), Vec2::new(46., 40.));
+    }
+
+    #[test]
+    fn neighbor_behind_an_obstacle_is_excluded_from_separation() {
+        // velocity of a resting boid with a neighbor just to its right and a wall between them
+        let separated = |line_of_sight: bool| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::SEPARATION);
+            world.resource_mut::<BoidParams>().separation_line_of_sight = line_of_sight;
+            let boid = spawn_test_boid(&mut world, Vec2::new(10., 10.), Vec2::ZERO);
+            spawn_test_boid(&mut world, Vec2::new(13., 10.), Vec2::ZERO);
+            let wall = world.spawn().id();
+            world
+                .resource_mut::<ObstacleQuadtree>()
+                .0
+                .add(EntityWrapper {
+                    entity: wall,
+                    rect: centered_rect(Vec2::new(12.75, 11.25), Vec2::new(0.5, 20.)),
+                    velocity: Vec3::ZERO,
+                });
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid)
+        };
+        assert!(separated(false).x < 0.);
+        assert_eq!(separated(true), Vec2::ZERO);
+    }
+
+    #[test]
+    fn two_clusters_get_two_distinct_colors() {
+        let mut world = test_world();
+        world.resource_mut::<SpawnConfig>().color_by_cluster = true;
+        let spawn_pair = |world: &mut World, position: Vec2| {
+            [position, position + Vec2::new(5., 0.)].map(|position| {
+                let boid = spawn_test_boid(world, position, Vec2::ZERO);
+                world.entity_mut(boid).insert(Sprite::default());
+                boid
+            })
+        };
+        let west = spawn_pair(&mut world, Vec2::new(-50., 0.));
+        let east = spawn_pair(&mut world, Vec2::new(50., 0.));
+        run_system(&mut world, assign_cluster_ids);
+        run_system(&mut world, color_by_cluster);
+        let color_of = |world: &World, boid: Entity| world.get::<Sprite>(boid).unwrap().color;
+        assert_eq!(color_of(&world, west[0]), color_of(&world, west[1]));
+        assert_eq!(color_of(&world, east[0]), color_of(&world, east[1]));
+        assert_ne!(color_of(&world, west[0]), color_of(&world, east[0]));
+        // a cluster is named after its lowest entity, so its color survives a recompute
+        assert_eq!(
+            world.get::<ClusterId>(east[1]),
+            Some(&ClusterId(east[0].id()))
+        );
+        world.clear_trackers();
+        run_system(&mut world, assign_cluster_ids);
+        let mut changed = world.query_filtered::<(), Changed<ClusterId>>();
+        assert_eq!(changed.iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn smoothing_blends_the_steered_velocity_with_the_last_tick() {
+        // velocity of an eastbound boid steered north within one tick
+        let smoothed = |smoothing: f32| {
+            let mut world = test_world();
+            world.resource_mut::<BoidParams>().smoothing = smoothing;
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(BOID_SPEED, 0.));
+            run_system(&mut world, record_previous_velocity);
+            world.get_mut::<Kinematics>(boid).unwrap().velocity = Vec3::new(0., BOID_SPEED, 0.);
+            run_system(&mut world, smooth_velocity);
+            velocity_of(&world, boid)
+        };
+        assert_eq!(smoothed(0.), Vec2::new(0., BOID_SPEED));
+        let barely_turned = smoothed(0.99);
+        assert!(barely_turned.abs_diff_eq(Vec2::new(BOID_SPEED, 0.), BOID_SPEED * 0.011));
+        assert!(barely_turned.y > 0.);
+    }
+
+    #[test]
+    fn boid_near_a_wrapping_edge_gets_a_ghost_where_it_reappears() {
+        let mut world = test_world();
+        let mut params = world.resource_mut::<BoidParams>();
+        params.edge_behavior = EdgeBehavior::uniform(EdgeMode::Wrap);
+        params.wrap_ghost_margin = 5.;
+        // 1 unit from the right edge, and one in the middle that needs no ghost
+        let near_edge = Vec2::new(WORLD.max.x - BOID_SCALE.x - 1., 0.);
+        for position in [near_edge, Vec2::ZERO] {
+            let boid = spawn_test_boid(&mut world, position, Vec2::ZERO);
+            world.entity_mut(boid).insert(Sprite::default());
+        }
+        run_system(&mut world, draw_wrap_ghosts);
+        let mut ghost_query = world.query::<(&WrapGhost, &Transform)>();
+        let ghosts: Vec<_> = ghost_query.iter(&world).collect();
+        assert_eq!(ghosts.len(), 1);
+        let (ghost, transform) = ghosts[0];
+        assert_eq!(ghost.direction, IVec2::new(-1, 0));
+        // 1 unit past the left edge, where wrapping puts the boid once it touches the right one
+        assert_eq!(
+            transform.translation.truncate(),
+            Vec2::new(WORLD.min.x - 1., 0.)
+        );
+        let boid = ghost.boid;
+        world.get_mut::<Transform>(boid).unwrap().translation.x += 1.;
+        run_system(&mut world, wrap_screen_edges);
+        let wrapped = world.get::<Transform>(boid).unwrap().translation;
+        assert_eq!(wrapped.x, WORLD.min.x);
+    }
+
+    #[test]
+    fn sped_up_boid_decelerates_toward_its_preferred_speed() {
+        let mut world = test_world();
+        world.resource_mut::<BoidParams>().speed_return_rate = 0.05;
+        let heading = Vec2::new(3., 4.).normalize();
+        let boid = spawn_test_boid(&mut world, Vec2::ZERO, heading * BOID_SPEED * 5.);
+        let mut stage = SystemStage::single_threaded().with_system(ease_to_preferred_speed);
+        let mut last_speed = BOID_SPEED * 5.;
+        for _ in 0..200 {
+            stage.run(&mut world);
+            let velocity = velocity_of(&world, boid);
+            let speed = velocity.length();
+            assert!(speed < last_speed && speed >= BOID_SPEED);
+            assert!(velocity.normalize().abs_diff_eq(heading, EPS));
+            last_speed = speed;
+        }
+        assert!((last_speed - BOID_SPEED).abs() < BOID_SPEED * 0.001);
+    }
+
+    #[test]
+    fn boid_held_in_place_for_the_window_is_reported_stalled() {
+        let mut world = test_world();
+        let mut params = world.resource_mut::<BoidParams>();
+        params.stall_ticks = 10;
+        params.stall_nudge = true;
+        let held = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        let moving = spawn_test_boid(&mut world, Vec2::new(50., 0.), Vec2::ZERO);
+        let stalled = |world: &World| {
+            let events = world.resource::<Events<BoidStalled>>();
+            let mut reader = events.get_reader();
+            reader
+                .iter(events)
+                .map(|event| event.entity)
+                .collect::<Vec<_>>()
+        };
+        let mut stage = SystemStage::single_threaded().with_system(detect_stalls);
+        for _ in 0..9 {
+            world.get_mut::<Transform>(moving).unwrap().translation.x += 5.;
+            stage.run(&mut world);
+        }
+        assert!(stalled(&world).is_empty());
+        world.get_mut::<Transform>(moving).unwrap().translation.x += 5.;
+        stage.run(&mut world);
+        assert_eq!(stalled(&world), vec![held]);
+        // nudged off at its preferred speed
+        assert!((velocity_of(&world, held).length() - BOID_SPEED).abs() < EPS * BOID_SPEED);
+        assert_eq!(velocity_of(&world, moving), Vec2::ZERO);
+    }
+
+    #[test]
+    fn every_integrator_moves_a_constant_velocity_boid_by_velocity_times_dt() {
+        for integrator in [Integrator::Euler, Integrator::RK4, Integrator::Verlet] {
+            let mut world = test_world();
+            let mut physics = world.resource_mut::<PhysicsConfig>();
+            physics.integrator = integrator;
+            physics.substeps = 4;
+            let delta_time = physics.delta_time();
+            let velocity = Vec2::new(30., -20.);
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, velocity);
+            run_system(&mut world, apply_kinematics);
+            let moved = world.get::<Transform>(boid).unwrap().translation.truncate();
+            assert!(
+                moved.abs_diff_eq(velocity * delta_time, EPS),
+                "{:?} moved {}",
+                integrator,
+                moved
+            );
+            assert_eq!(velocity_of(&world, boid), velocity);
+        }
+    }
+
+    #[test]
+    fn only_boids_in_oversized_clusters_are_pushed_outward() {
+        let mut world = test_world();
+        world.resource_mut::<BoidParams>().flock_split_size = 3;
+        // resting boids, so the push sets their heading outright. A cross of five, and a pair
+        // far enough away to be its own cluster
+        let center = Vec2::new(-50., 0.);
+        let offsets = [Vec2::ZERO, Vec2::X, -Vec2::X, Vec2::Y, -Vec2::Y];
+        let cross =
+            offsets.map(|offset| spawn_test_boid(&mut world, center + offset * 5., Vec2::ZERO));
+        let pair = [Vec2::new(50., 0.), Vec2::new(55., 0.)]
+            .map(|position| spawn_test_boid(&mut world, position, Vec2::ZERO));
+        run_system(&mut world, split_large_flocks);
+        // the middle boid sits on the centroid, the others head straight away from it
+        assert_eq!(velocity_of(&world, cross[0]), Vec2::ZERO);
+        for (boid, offset) in cross.iter().zip(offsets).skip(1) {
+            let velocity = velocity_of(&world, *boid);
+            assert!(velocity.normalize().abs_diff_eq(offset, EPS));
+        }
+        for boid in pair {
+            assert_eq!(velocity_of(&world, boid), Vec2::ZERO);
+        }
+    }
+
+    #[test]
+    fn cohesion_is_zero_once_every_neighbor_is_within_the_floor() {
+        let wrapper = |id: u32, center: Vec2| EntityWrapper {
+            entity: Entity::from_raw(id),
+            rect: centered_rect(center, BOID_SCALE),
+            velocity: Vec3::ZERO,
+        };
+        let my_rect = centered_rect(Vec2::ZERO, BOID_SCALE);
+        let near = [
+            wrapper(1, Vec2::new(3., 0.)),
+            wrapper(2, Vec2::new(0., -3.)),
+        ];
+        let far = wrapper(3, Vec2::new(0., 10.));
+        assert_eq!(
+            cohesion_force(&my_rect, [&near[0], &near[1]], 5.),
+            Vec2::ZERO
+        );
+        // only the far neighbor pulls, at the share of neighbors it makes up
+        let pull = cohesion_force(&my_rect, [&near[0], &near[1], &far], 5.);
+        assert!(pull.abs_diff_eq(Vec2::new(0., 1. / 3.), EPS));
+        // without a floor every neighbor counts at full strength
+        let pull = cohesion_force(&my_rect, [&near[0], &near[1], &far], 0.);
+        assert!((pull.length() - 1.).abs() < EPS);
+
+        // and flocking leaves a boid crowded inside the floor alone
+        let steered = |floor: f32| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::COHESION);
+            world.resource_mut::<BoidParams>().cohesion_floor = floor;
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., BOID_SPEED));
+            for position in [Vec2::new(3., 0.), Vec2::new(3., 1.)] {
+                spawn_test_boid(&mut world, position, Vec2::new(0., BOID_SPEED));
+            }
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid)
+        };
+        assert!(steered(0.).x > 0.);
+        assert_eq!(steered(5.), Vec2::new(0., BOID_SPEED));
+    }
+
+    #[test]
+    fn opposite_heading_neighbor_is_excluded_while_a_parallel_one_counts() {
+        // velocity of a northbound boid aligning with the given neighbors
+        let steered = |cutoff: f32, neighbor_velocities: &[Vec2]| {
+            let mut world = test_world();
+            world.insert_resource(BehaviorMask::ALIGNMENT);
+            world.resource_mut::<BoidParams>().heading_similarity_cutoff = cutoff;
+            let boid = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::new(0., BOID_SPEED));
+            for (i, velocity) in neighbor_velocities.iter().enumerate() {
+                spawn_test_boid(&mut world, Vec2::new(5., i as f32 * 3.), *velocity);
+            }
+            run_system(&mut world, flocking);
+            velocity_of(&world, boid)
+        };
+        let parallel = Vec2::new(BOID_SPEED, BOID_SPEED);
+        let opposite = Vec2::new(0., -BOID_SPEED);
+        // alignment needs two neighbors to act on
+        let parallel_only = steered(-1., &[parallel, parallel]);
+        assert!(parallel_only.x > 0.);
+        assert_eq!(steered(0., &[parallel, parallel, opposite]), parallel_only);
+        // the default cutoff counts every neighbor
+        assert_ne!(steered(-1., &[parallel, parallel, opposite]), parallel_only);
+    }
+
+    #[test]
+    fn scatter_pushes_every_boid_away_from_the_centroid_until_it_expires() {
+        let mut world = test_world();
+        world.insert_resource(Input::<KeyCode>::default());
+        // a ring of boids circling the origin, so none of them starts off moving outward
+        let offsets = [Vec2::X, Vec2::Y, -Vec2::X, -Vec2::Y].map(|offset| offset * 20.);
+        let boids = offsets.map(|offset| {
+            spawn_test_boid(&mut world, offset, offset.perp().normalize() * BOID_SPEED)
+        });
+        let mut stage = SystemStage::single_threaded()
+            .with_system(scatter_from_key)
+            .with_system(scatter_flock.after(scatter_from_key));
+
+        world.resource_mut::<Input<KeyCode>>().press(KeyCode::S);
+        stage.run(&mut world);
+        world.resource_mut::<Input<KeyCode>>().clear();
+        for (boid, offset) in boids.iter().zip(offsets) {
+            assert!(velocity_of(&world, *boid).dot(offset) > 0.);
+        }
+
+        let expected_ticks = (world.resource::<BoidParams>().scatter_ttl
+            / world.resource::<PhysicsConfig>().delta_time())
+        .round() as usize;
+        let mut ticks = 1;
+        while world.resource::<Scatter>().is_active() && ticks <= expected_ticks {
+            stage.run(&mut world);
+            ticks += 1;
+        }
+        // subtracting the tick length can leave a rounding error's worth for one more tick
+        assert!(!world.resource::<Scatter>().is_active());
+        assert!((expected_ticks..=expected_ticks + 1).contains(&ticks));
+        // once expired, the flock is left to regroup
+        let velocities = boids.map(|boid| velocity_of(&world, boid));
+        stage.run(&mut world);
+        assert_eq!(boids.map(|boid| velocity_of(&world, boid)), velocities);
+    }
+
+    #[test]
+    fn boid_wraps_horizontally_and_bounces_vertically() {
+        let mut world = test_world();
+        world.resource_mut::<BoidParams>().edge_behavior = EdgeBehavior {
+            x: EdgeMode::Wrap,
+            y: EdgeMode::Bounce,
+        };
+        // 1 unit from the top right corner, heading into it
+        let boid = spawn_test_boid(
+            &mut world,
+            WORLD.max - BOID_SCALE - Vec2::ONE,
+            Vec2::splat(BOID_SPEED),
+        );
+        let mut stage = SystemStage::single_threaded()
+            .with_system(avoid_screen_edges)
+            .with_system(apply_kinematics.after(avoid_screen_edges))
+            .with_system(wrap_screen_edges.after(apply_kinematics));
+        let mut wrapped = false;
+        for _ in 0..10 {
+            let before = world.get::<Transform>(boid).unwrap().translation;
+            stage.run(&mut world);
+            let after = world.get::<Transform>(boid).unwrap().translation;
+            wrapped |= after.x < before.x;
+            assert!(after.y + BOID_SCALE.y <= WORLD.max.y);
+        }
+        let velocity = velocity_of(&world, boid);
+        assert!(wrapped);
+        assert_eq!(velocity, Vec2::new(BOID_SPEED, -BOID_SPEED));
+        assert!(world.get::<Transform>(boid).unwrap().translation.x < 0.);
+    }
+
+    #[test]
+    fn acceleration_is_clamped_to_the_max_keeping_its_direction() {
+        let mut world = test_world();
+        world.resource_mut::<BoidParams>().max_acceleration = 50.;
+        let strong = spawn_test_boid(&mut world, Vec2::ZERO, Vec2::ZERO);
+        let weak = spawn_test_boid(&mut world, Vec2::new(50., 0.), Vec2::ZERO);
+        world.get_mut::<Kinematics>(strong).unwrap().acceleration = Vec3::new(300., -400., 0.);
+        world.get_mut::<Kinematics>(weak).unwrap().acceleration = Vec3::new(-30., 0., 0.);
+        run_system(&mut world, apply_kinematics);
+        let acceleration = |boid: Entity| world.get::<Kinematics>(boid).unwrap().acceleration;
+        assert!(acceleration(strong).abs_diff_eq(Vec3::new(30., -40., 0.), EPS));
+        assert_eq!(acceleration(weak), Vec3::new(-30., 0., 0.));
+        // and the step was integrated with the clamped acceleration
+        let delta_time = world.resource::<PhysicsConfig>().delta_time();
+        let moved = world
+            .get::<Transform>(strong)
+            .unwrap()
+            .translation
+            .truncate();
+        let expected = acceleration(strong).truncate() * delta_time * delta_time / 2.;
+        assert!(moved.abs_diff_eq(expected, EPS));
+    }
 }
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::schedule::{IntoSystemDescriptor, Stage, SystemStage},
+    prelude::*,
+    sprite::Rect,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+
+use super::{
+    components::{
+        Boid, BoidEscaped, BoidSpawnRejected, BoidStalled, Collider, Impulse, Kinematics,
+        PreviousAcceleration, PreviousVelocity, QuadtreeRect, StallWatch,
+    },
+    resources::{
+        BehaviorMask, BoidParams, EntityQuadtree, EntityWrapper, MaxBoids, NeighborStrategy,
+        ObstacleQuadtree, PerfBudget, PhysicsConfig, PhysicsPause, QuadtreeFit, ReplayBuffer,
+        Scatter, SettlePhase, SpawnConfig, WorldBounds,
+    },
+    setup::{BOID_SCALE, BOID_SPEED, BOID_Z},
+    SCREEN_SIZE,
+};
+
+// same world the app starts with at the default resolution
+pub const WORLD: Rect = Rect {
+    min: Vec2::new(-SCREEN_SIZE.x / 2., -SCREEN_SIZE.y / 2.),
+    max: Vec2::new(SCREEN_SIZE.x / 2., SCREEN_SIZE.y / 2.),
+};
+
+// world holding every resource the physics systems read, at their defaults and without boids
+pub fn test_world() -> World {
+    // par_for_each_mut runs on the compute pool, which the app's task plugin would set up
+    ComputeTaskPool::init(TaskPool::default);
+    let mut world = World::new();
+    world.insert_resource(EntityQuadtree::empty(WORLD));
+    world.insert_resource(ObstacleQuadtree::empty(WORLD));
+    world.insert_resource(WorldBounds::new(WORLD));
+    world.insert_resource(BoidParams::default());
+    world.insert_resource(SpawnConfig::default());
+    world.insert_resource(PhysicsConfig::default());
+    world.insert_resource(NeighborStrategy::default());
+    world.insert_resource(BehaviorMask::default());
+    world.insert_resource(SettlePhase::default());
+    world.insert_resource(QuadtreeFit::default());
+    world.insert_resource(ReplayBuffer::new(0));
+    world.insert_resource(Scatter::default());
+    world.insert_resource(PhysicsPause::default());
+    world.insert_resource(MaxBoids(usize::MAX));
+    // fixed radii, a slow test machine shouldn't shrink the neighborhoods
+    world.insert_resource(PerfBudget {
+        budget_ms: f32::INFINITY,
+        ..default()
+    });
+    world.insert_resource(Events::<BoidEscaped>::default());
+    world.insert_resource(Events::<BoidStalled>::default());
+    world.insert_resource(Events::<BoidSpawnRejected>::default());
+    world.insert_resource(Events::<Impulse>::default());
+    world.insert_resource(Time::default());
+    world
+}
+
+// full-size boid with its rect's corner at position, indexed in the world's quadtree
+pub fn spawn_test_boid(world: &mut World, position: Vec2, velocity: Vec2) -> Entity {
+    let velocity = velocity.extend(0.);
+    let transform = Transform {
+        translation: position.extend(BOID_Z),
+        scale: BOID_SCALE.extend(1.),
+        ..default()
+    };
+    let alignment = world.resource::<SpawnConfig>().alignment;
+    let entity = world
+        .spawn()
+        .insert(Boid)
+        .insert(Collider)
+        .insert(Kinematics {
+            velocity,
+            acceleration: Vec3::ZERO,
+            preferred_speed: BOID_SPEED,
+        })
+        .insert(PreviousVelocity(velocity))
+        .insert(PreviousAcceleration::default())
+        .insert(StallWatch::new(position))
+        .insert(transform)
+        .id();
+    let value = EntityWrapper::new(entity, &velocity, &transform, alignment);
+    world.entity_mut(entity).insert(QuadtreeRect(value.rect));
+    world.resource_mut::<EntityQuadtree>().add(value);
+    entity
+}
+
+// runs system once, applying its commands before returning
+pub fn run_system<Params>(world: &mut World, system: impl IntoSystemDescriptor<Params>) {
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(system);
+    stage.run(world);
+}
+
+pub fn velocity_of(world: &World, entity: Entity) -> Vec2 {
+    world.get::<Kinematics>(entity).unwrap().velocity.truncate()
+}
+
+// moves Time on by seconds, as if that long passed since the last frame
+pub fn advance_time(world: &mut World, seconds: f32) {
+    let mut time = world.resource_mut::<Time>();
+    // the first update only records an instant, delta stays zero until the second
+    if time.last_update().is_none() {
+        let startup = time.startup();
+        time.update_with_instant(startup);
+    }
+    let last_update = time.last_update().unwrap();
+    time.update_with_instant(last_update + Duration::from_secs_f32(seconds));
+}
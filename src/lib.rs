@@ -0,0 +1,65 @@
+//! Spatial indexing utilities behind the boids simulation.
+//!
+//! [`Quadtree`] stores any [`QuadtreeValue`] (anything with a bounding
+//! [`Rect`](bevy::sprite::Rect)) and answers rect, radius and point queries
+//! without looping over every value.
+//!
+//! ```
+//! use std::hash::{Hash, Hasher};
+//!
+//! use bevy::{prelude::Vec2, sprite::Rect};
+//! use bevy_boids::{Quadtree, QuadtreeValue};
+//!
+//! #[derive(Clone)]
+//! struct Marker {
+//!     id: u32,
+//!     rect: Rect,
+//! }
+//!
+//! // Rect is neither Eq nor Hash, so identity comes from the id
+//! impl PartialEq for Marker {
+//!     fn eq(&self, other: &Self) -> bool {
+//!         self.id == other.id
+//!     }
+//! }
+//!
+//! impl Eq for Marker {}
+//!
+//! impl Hash for Marker {
+//!     fn hash<H: Hasher>(&self, state: &mut H) {
+//!         self.id.hash(state);
+//!     }
+//! }
+//!
+//! impl QuadtreeValue for Marker {
+//!     fn get_rect(&self) -> &Rect {
+//!         &self.rect
+//!     }
+//! }
+//!
+//! let mut quadtree = Quadtree::empty(Rect {
+//!     min: Vec2::new(-100., -100.),
+//!     max: Vec2::new(100., 100.),
+//! });
+//! for (id, x) in [(0, -50.), (1, 10.), (2, 60.)] {
+//!     let min = Vec2::new(x, 0.);
+//!     quadtree.add(Marker {
+//!         id,
+//!         rect: Rect {
+//!             min,
+//!             max: min + Vec2::ONE,
+//!         },
+//!     });
+//! }
+//!
+//! let nearby = quadtree.query_radius(Vec2::ZERO, 20.);
+//! assert_eq!(nearby.len(), 1);
+//! assert_eq!(nearby[0].id, 1);
+//! ```
+pub mod util;
+
+pub use util::quadtree::{
+    neighbor_query::NeighborQuery, node_values::NodeValues, quadtree::Quadtree,
+    quadtree_node::QuadtreeNode, quadtree_stats::QuadtreeStats, quadtree_value::QuadtreeValue,
+    query_scratch::QueryScratch, vec_quadtree::{VecQuadtree, VecQuadtreeNode},
+};
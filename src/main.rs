@@ -1,5 +1,7 @@
 mod ecs;
-mod util;
+
+// the binary consumes the spatial index through the library crate
+use bevy_boids::util;
 
 use self::ecs::run_ecs_application;
 
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+// Positions come in two spaces:
+// - world space: what Transforms, quadtree rects and boid kinematics use. The origin is wherever
+//   the camera started (the screen center for the default Camera2dBundle), y points up.
+// - screen space: what Window::cursor_position reports, in logical pixels from the bottom-left
+//   corner of the window, y points up.
+// Both helpers assume an orthographic 2D camera whose projection maps one world unit to one
+// logical pixel (the Camera2dBundle default); the camera transform may pan, rotate or zoom.
+
+// None when the window has no area (e.g. minimized)
+pub fn screen_to_world(camera: &GlobalTransform, window: &Window, pos: Vec2) -> Option<Vec2> {
+    Some(screen_to_world_in(camera, window_size(window)?, pos))
+}
+
+// None when the window has no area, the result may lie outside the window
+pub fn world_to_screen(camera: &GlobalTransform, window: &Window, pos: Vec2) -> Option<Vec2> {
+    Some(world_to_screen_in(camera, window_size(window)?, pos))
+}
+
+// world position under the cursor, None while the cursor is outside the window
+pub fn cursor_world_position(camera: &GlobalTransform, window: &Window) -> Option<Vec2> {
+    window
+        .cursor_position()
+        .and_then(|pos| screen_to_world(camera, window, pos))
+}
+
+/* Internal-only Functions */
+
+// the conversions for a window of window_size logical pixels
+fn screen_to_world_in(camera: &GlobalTransform, window_size: Vec2, pos: Vec2) -> Vec2 {
+    let centered = pos - window_size / 2.;
+    camera
+        .compute_matrix()
+        .transform_point3(centered.extend(0.))
+        .truncate()
+}
+
+fn world_to_screen_in(camera: &GlobalTransform, window_size: Vec2, pos: Vec2) -> Vec2 {
+    let centered = camera
+        .compute_matrix()
+        .inverse()
+        .transform_point3(pos.extend(0.))
+        .truncate();
+    centered + window_size / 2.
+}
+
+fn window_size(window: &Window) -> Option<Vec2> {
+    let size = Vec2::new(window.width(), window.height());
+    if size.x > 0. && size.y > 0. {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW_SIZE: Vec2 = Vec2::new(1280., 720.);
+
+    #[test]
+    fn centered_camera_maps_the_window_center_to_the_origin_and_back() {
+        let camera = GlobalTransform::identity();
+        assert_eq!(
+            screen_to_world_in(&camera, WINDOW_SIZE, WINDOW_SIZE / 2.),
+            Vec2::ZERO
+        );
+        // bottom-left corner of the window, y up in both spaces
+        assert_eq!(
+            screen_to_world_in(&camera, WINDOW_SIZE, Vec2::ZERO),
+            Vec2::new(-640., -360.)
+        );
+        for pos in [
+            Vec2::new(10., 700.),
+            Vec2::new(1279., 3.5),
+            Vec2::new(-50., 800.),
+        ] {
+            let world = screen_to_world_in(&camera, WINDOW_SIZE, pos);
+            assert_eq!(world_to_screen_in(&camera, WINDOW_SIZE, world), pos);
+        }
+    }
+
+    #[test]
+    fn panned_and_zoomed_camera_round_trips() {
+        let camera = GlobalTransform::from(
+            Transform::from_xyz(200., -50., 999.).with_scale(Vec3::new(2., 2., 1.)),
+        );
+        assert_eq!(
+            screen_to_world_in(&camera, WINDOW_SIZE, WINDOW_SIZE / 2.),
+            Vec2::new(200., -50.)
+        );
+        let pos = Vec2::new(100., 600.);
+        let world = screen_to_world_in(&camera, WINDOW_SIZE, pos);
+        assert!(world_to_screen_in(&camera, WINDOW_SIZE, world).abs_diff_eq(pos, 0.001));
+    }
+}
@@ -1,2 +1,4 @@
+pub mod coords;
 pub mod quadtree;
-pub mod rect;
\ No newline at end of file
+pub mod rect;
+pub mod region;
\ No newline at end of file
@@ -1,7 +1,32 @@
+pub mod neighbor_query;
+pub mod node_values;
+#[allow(clippy::module_inception)]
 pub mod quadtree;
 pub mod quadtree_node;
 pub mod quadtree_stats;
 pub mod quadtree_value;
+pub mod query_scratch;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod vec_quadtree;
 
 pub const THRESHOLD: usize = 256;
-pub const MAX_DEPTH: usize = 7;
\ No newline at end of file
+pub const MAX_DEPTH: usize = 7;
+// smallest capacity depth_scaled_threshold goes down to
+pub const MIN_THRESHOLD: usize = 16;
+
+// max values a node at the given depth holds before subdividing
+pub type ThresholdFn = fn(usize) -> usize;
+
+// every depth subdivides at THRESHOLD, the default
+pub fn constant_threshold(_depth: usize) -> usize {
+    THRESHOLD
+}
+
+// halves capacity with each level, dense regions end up split finer than sparse ones
+pub fn depth_scaled_threshold(depth: usize) -> usize {
+    THRESHOLD
+        .checked_shr(depth as u32)
+        .unwrap_or(0)
+        .max(MIN_THRESHOLD)
+}
@@ -0,0 +1,77 @@
+use std::{hash::Hash, marker::PhantomData};
+
+use bevy::{ecs::system::SystemParam, prelude::*, sprite::Rect};
+
+use super::{quadtree::Quadtree, quadtree_value::QuadtreeValue};
+
+/// System param for read-only neighbor lookups against a `Quadtree<T>` resource, for systems
+/// that only need "which values are near here" and shouldn't depend on the quadtree's query
+/// methods.
+#[derive(SystemParam)]
+pub struct NeighborQuery<'w, 's, T: QuadtreeValue + Eq + Hash + Send + Sync + 'static> {
+    quadtree: Res<'w, Quadtree<T>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's, T: QuadtreeValue + Eq + Hash + Send + Sync + 'static> NeighborQuery<'w, 's, T> {
+    /// Values whose center is within `radius` of `center`.
+    pub fn within_radius(&self, center: Vec2, radius: f32) -> Vec<&T> {
+        self.quadtree.query_radius(center, radius)
+    }
+
+    /// Values whose rect overlaps `rect`, including ones only partially inside it.
+    pub fn within_rect(&self, rect: &Rect) -> Vec<&T> {
+        self.quadtree.values_intersecting(rect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::quadtree::test_support::TestValue;
+
+    const WORLD: Rect = Rect {
+        min: Vec2::new(-100., -100.),
+        max: Vec2::new(100., 100.),
+    };
+
+    // ids each lookup found, written by the system under test
+    #[derive(Default)]
+    struct Found {
+        radius: Vec<u32>,
+        rect: Vec<u32>,
+    }
+
+    fn look_around_origin(neighbors: NeighborQuery<TestValue>, mut found: ResMut<Found>) {
+        found.radius = neighbors
+            .within_radius(Vec2::ZERO, 20.)
+            .iter()
+            .map(|v| v.id)
+            .collect();
+        let rect = Rect {
+            min: Vec2::new(0., 0.),
+            max: Vec2::new(60., 60.),
+        };
+        found.rect = neighbors.within_rect(&rect).iter().map(|v| v.id).collect();
+    }
+
+    #[test]
+    fn system_sees_the_quadtree_resource() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.add(TestValue::new(0, Vec2::new(-1., -1.), Vec2::splat(2.)));
+        quadtree.add(TestValue::new(1, Vec2::new(50., 50.), Vec2::splat(20.)));
+        quadtree.add(TestValue::new(2, Vec2::new(-80., 60.), Vec2::splat(2.)));
+        let mut app = App::new();
+        app.insert_resource(quadtree)
+            .init_resource::<Found>()
+            .add_system(look_around_origin);
+        app.update();
+
+        let found = app.world.resource::<Found>();
+        assert_eq!(found.radius, vec![0]);
+        let mut rect = found.rect.clone();
+        rect.sort_unstable();
+        assert_eq!(rect, vec![0, 1]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{hash::Hash, mem};
+
+use bevy::utils::HashSet;
+
+/// Storage for the values of one quadtree node. Equality identifies a value when it is found,
+/// moved or deleted, and `insert` only deduplicates within the node it is called on.
+///
+/// `HashSet` is the default and needs values that are `Eq + Hash`; `Vec` only needs
+/// `PartialEq`, at the cost of linear membership checks within a node (see
+/// [`VecQuadtree`](super::vec_quadtree::VecQuadtree)). Code generic over the storage also
+/// needs `for<'a> &'a S: IntoIterator<Item = &'a T>` to iterate a node's values.
+pub trait NodeValues<T>: Default + IntoIterator<Item = T> {
+    /// Stores `value`, returns false if an equal value was already stored.
+    fn insert(&mut self, value: T) -> bool;
+
+    fn contains(&self, value: &T) -> bool;
+
+    /// Removes and returns the stored value equal to `value`.
+    fn take(&mut self, value: &T) -> Option<T>;
+
+    /// Stores `value` in place of an equal one and returns it, or adds `value` if there was none.
+    fn replace(&mut self, value: T) -> Option<T>;
+
+    /// Keeps only the values `keep` returns true for.
+    fn retain(&mut self, keep: impl FnMut(&T) -> bool);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Values the storage can hold without reallocating.
+    fn capacity(&self) -> usize;
+
+    fn shrink_to_fit(&mut self);
+}
+
+impl<T: Eq + Hash> NodeValues<T> for HashSet<T> {
+    fn insert(&mut self, value: T) -> bool {
+        HashSet::insert(self, value)
+    }
+
+    fn contains(&self, value: &T) -> bool {
+        HashSet::contains(self, value)
+    }
+
+    fn take(&mut self, value: &T) -> Option<T> {
+        HashSet::take(self, value)
+    }
+
+    fn replace(&mut self, value: T) -> Option<T> {
+        HashSet::replace(self, value)
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        HashSet::retain(self, keep)
+    }
+
+    fn len(&self) -> usize {
+        HashSet::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        HashSet::capacity(self)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        HashSet::shrink_to_fit(self)
+    }
+}
+
+impl<T: PartialEq> NodeValues<T> for Vec<T> {
+    fn insert(&mut self, value: T) -> bool {
+        if self.as_slice().contains(&value) {
+            return false;
+        }
+        self.push(value);
+        true
+    }
+
+    fn contains(&self, value: &T) -> bool {
+        self.as_slice().contains(value)
+    }
+
+    fn take(&mut self, value: &T) -> Option<T> {
+        let index = self.iter().position(|v| v == value)?;
+        Some(self.swap_remove(index))
+    }
+
+    fn replace(&mut self, value: T) -> Option<T> {
+        match self.iter().position(|v| *v == value) {
+            Some(index) => Some(mem::replace(&mut self[index], value)),
+            None => {
+                self.push(value);
+                None
+            }
+        }
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        Vec::retain(self, keep)
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn capacity(&self) -> usize {
+        Vec::capacity(self)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        Vec::shrink_to_fit(self)
+    }
+}
@@ -1,43 +1,822 @@
-use bevy::sprite::Rect;
+use std::hash::Hash;
 
-use super::{quadtree_node::QuadtreeNode, quadtree_value::QuadtreeValue};
+use bevy::{math::DVec2, prelude::Vec2, sprite::Rect, utils::HashSet};
 
-pub struct Quadtree<T: QuadtreeValue> {
+use crate::util::rect::{morton_code, rect_union};
+
+use super::{
+    constant_threshold, node_values::NodeValues, quadtree_node::QuadtreeNode,
+    quadtree_value::QuadtreeValue, ThresholdFn,
+};
+
+/// Region quadtree over values with bounding rects, rooted at `rect`. Nodes keep their values
+/// in `S`, a `HashSet` unless the values can't be hashed (see [`NodeValues`]).
+pub struct Quadtree<T: QuadtreeValue, S = HashSet<T>> {
     pub rect: Rect,
-    pub root: QuadtreeNode<T>,
+    pub root: QuadtreeNode<T, S>,
+    // running totals behind centroid() and bounds(), kept up to date by add and delete
+    len: usize,
+    // f64 so repeated add/delete of moving values doesn't accumulate drift
+    center_sum: DVec2,
+    bounds: Option<Rect>,
+    // set when a value on the edge of bounds moved or left, see refresh_bounds
+    bounds_stale: bool,
 }
 
-impl<T: QuadtreeValue> Quadtree<T> {
+impl<T: QuadtreeValue + Eq + Hash> Quadtree<T> {
+    /// Creates a tree covering `size` with no values.
     pub fn empty(size: Rect) -> Self {
+        Quadtree::with_threshold(size, constant_threshold)
+    }
+}
+
+impl<T: QuadtreeValue, S: NodeValues<T>> Quadtree<T, S>
+where
+    for<'a> &'a S: IntoIterator<Item = &'a T>,
+{
+    /// Creates an empty tree whose nodes subdivide at `threshold(depth)` values, for example
+    /// [`constant_threshold`] (the [`THRESHOLD`](super::THRESHOLD) that
+    /// [`Quadtree::empty`] uses) or [`depth_scaled_threshold`](super::depth_scaled_threshold).
+    /// Unlike `empty` this works with any node storage.
+    pub fn with_threshold(size: Rect, threshold: ThresholdFn) -> Self {
         Quadtree {
             rect: size,
-            root: QuadtreeNode::<T>::empty(size.clone(), 0),
+            root: QuadtreeNode::with_threshold(size, 0, threshold),
+            len: 0,
+            center_sum: DVec2::ZERO,
+            bounds: None,
+            bounds_stale: false,
         }
     }
 
+    /// Inserts a value, ignoring it unless the tree's rect fully contains it.
     pub fn add(&mut self, value: T) {
         //only add if value is contained within our rect
         if self.root.contains_rect(value.get_rect()) {
-            self.root.add(value);
+            let center = value.get_center();
+            let rect = *value.get_rect();
+            if self.root.add(value) {
+                self.len += 1;
+                self.center_sum += center.as_dvec2();
+                self.bounds = Some(grow_bounds(self.bounds, &rect));
+            }
+        }
+    }
+
+    /// Like [`Quadtree::add`], but skips `value` if an equal one is already stored in any node
+    /// containing its rect, returning whether it was inserted. `add` only notices an equal value
+    /// in the node the new one lands in, and as nodes fill and split an earlier copy can sit
+    /// above or below that node. Checking costs a second descent, so values known to be new
+    /// should go through `add` or [`Quadtree::extend`].
+    pub fn insert_unique(&mut self, value: T) -> bool {
+        let rect = *value.get_rect();
+        if self.root.find_value_within(&value, &rect).is_some() {
+            return false;
+        }
+        let len = self.len;
+        self.add(value);
+        self.len > len
+    }
+
+    /// Bulk insert, values outside the tree's rect are skipped just like [`Quadtree::add`].
+    /// Values are inserted in Z-order of their centers (see [`morton_code`]), so each node fills
+    /// and splits while its neighbors are still small instead of values being reshuffled between
+    /// nodes as the tree grows in random order.
+    pub fn extend(&mut self, values: impl IntoIterator<Item = T>) {
+        let mut values: Vec<T> = values.into_iter().collect();
+        values.sort_by_cached_key(|value| morton_code(value.get_center(), self.rect));
+        for value in values {
+            self.add(value);
         }
     }
 
+    /// Replaces the whole tree with one rooted at `rect` holding `values`, keeping the node
+    /// capacity. Values outside `rect` are skipped just like [`Quadtree::add`]. The new tree is
+    /// compacted, see [`Quadtree::compact`].
+    pub fn rebuild(&mut self, rect: Rect, values: impl IntoIterator<Item = T>) {
+        *self = Quadtree::with_threshold(rect, self.root.threshold);
+        self.extend(values);
+        self.compact();
+    }
+
+    /// Removes and returns the value equal to `value`, if present.
     pub fn delete(&mut self, value: &T) -> Option<T> {
-        match self.query_value_mut(value) {
-            Some(node) => node.delete(value),
-            None => None,
+        let deleted = self.remove(value);
+        self.refresh_bounds();
+        deleted
+    }
+
+    /// Keeps only the values `keep` returns true for, in one pass over the tree. Cheaper than a
+    /// [`Quadtree::delete`] per value when many values go at once, since each delete searches
+    /// the tree for its value.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        let bounds = self.bounds;
+        let mut removed = 0;
+        let mut removed_center_sum = DVec2::ZERO;
+        let mut removed_on_bounds = false;
+        self.root.retain(&mut |value| {
+            if keep(value) {
+                return true;
+            }
+            removed += 1;
+            removed_center_sum += value.get_center().as_dvec2();
+            removed_on_bounds |= bounds.is_some_and(|b| touches_bounds(&b, value.get_rect()));
+            false
+        });
+        self.len -= removed;
+        if self.len == 0 {
+            self.center_sum = DVec2::ZERO;
+            self.bounds = None;
+            self.bounds_stale = false;
+        } else {
+            self.center_sum -= removed_center_sum;
+            self.bounds_stale |= removed_on_bounds;
+        }
+        self.refresh_bounds();
+    }
+
+    /// Drops empty subtrees and shrinks every node's storage to fit its values. Deleting already
+    /// releases the storage of the nodes it empties and [`Quadtree::rebuild`] compacts the tree
+    /// it builds; this also trims nodes that churn left partly full. O(nodes).
+    pub fn compact(&mut self) {
+        self.root.compact();
+    }
+
+    /// Replaces the stored value equal to `value` with `value`, moving it to another node if
+    /// its new rect no longer fits where it is. `previous_rect` is the rect the value was last
+    /// stored with, which narrows the search to the nodes containing it; the whole tree is only
+    /// searched if the value isn't found there.
+    ///
+    /// Call [`Quadtree::refresh_bounds`] after a batch of relocations.
+    pub fn relocate(&mut self, previous_rect: &Rect, value: T) {
+        let rect = *value.get_rect();
+        let removed = match self.root.find_value_within_mut(&value, previous_rect) {
+            // values only move down when a child can hold them, otherwise they stay put
+            Some(node) if node.contains_rect(&rect) && !node.children_contain_rect(&rect) => {
+                if let Some(previous) = node.values.replace(value) {
+                    let center = (rect.min + rect.max) / 2.;
+                    self.center_sum += (center - previous.get_center()).as_dvec2();
+                    self.mark_bounds(previous.get_rect());
+                    self.bounds = Some(grow_bounds(self.bounds, &rect));
+                }
+                return;
+            }
+            // found but has to move, so take it out of the node we already have
+            Some(node) => node.delete(&value),
+            // previous_rect was stale, search the whole tree
+            None => self
+                .query_value_mut(&value)
+                .and_then(|node| node.delete(&value)),
+        };
+        if let Some(removed) = removed {
+            self.forget(&removed);
+        }
+        self.add(value);
+    }
+
+    /// Recomputes bounds if a relocation may have shrunk them. Does a full pass when needed.
+    pub fn refresh_bounds(&mut self) {
+        if self.bounds_stale {
+            self.bounds = None;
+            collect_bounds(&self.root, &mut self.bounds);
+            self.bounds_stale = false;
+        }
+    }
+
+    /// Number of values in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the tree holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Mean center of every value, or `None` if the tree is empty. O(1).
+    ///
+    /// Only tracks changes made through [`Quadtree::add`] and [`Quadtree::delete`], not
+    /// values edited in place through the node accessors.
+    pub fn centroid(&self) -> Option<Vec2> {
+        if self.len == 0 {
+            return None;
         }
+        Some((self.center_sum / self.len as f64).as_vec2())
+    }
+
+    /// Smallest rect covering every value, or `None` if the tree is empty. O(1), with the
+    /// same caveat as [`Quadtree::centroid`]. May be larger than necessary after
+    /// [`Quadtree::relocate`] until [`Quadtree::refresh_bounds`] runs.
+    pub fn bounds(&self) -> Option<Rect> {
+        self.bounds
+    }
+
+    /// Node currently holding `value`.
+    pub fn query_value(&self, value: &T) -> Option<&QuadtreeNode<T, S>> {
+        self.root.find_value(value)
     }
 
-    pub fn query_value_mut(&mut self, value: &T) -> Option<&mut QuadtreeNode<T>> {
+    /// Mutable version of [`Quadtree::query_value`].
+    pub fn query_value_mut(&mut self, value: &T) -> Option<&mut QuadtreeNode<T, S>> {
         self.root.find_value_mut(value)
     }
 
-    pub fn query_rect(&self, rect: &Rect) -> Option<&QuadtreeNode<T>> {
+    /// Deepest node that fully contains `rect`.
+    pub fn query_rect(&self, rect: &Rect) -> Option<&QuadtreeNode<T, S>> {
         self.root.query_rect(rect)
     }
 
-    pub fn query_rect_mut(&mut self, rect: &Rect) -> Option<&mut QuadtreeNode<T>> {
+    /// Rects of the nodes [`Quadtree::query_rect`] descends through, from the root down to the
+    /// node it returns. Empty if the root doesn't contain `rect`. Meant for debugging.
+    pub fn query_rect_path(&self, rect: &Rect) -> Vec<Rect> {
+        let mut path = vec![];
+        self.root.query_rect_path(rect, &mut path);
+        path
+    }
+
+    /// Values overlapping `rect`. Unlike [`Quadtree::query_rect`], this also finds values
+    /// that only partially overlap it.
+    pub fn values_intersecting(&self, rect: &Rect) -> Vec<&T> {
+        let mut results = vec![];
+        self.root.values_intersecting(rect, &mut results);
+        results
+    }
+
+    /// Values overlapping `rect` that `filter` accepts. The filter runs during traversal, so
+    /// rejected values are never collected.
+    pub fn values_intersecting_where(&self, rect: &Rect, filter: impl Fn(&T) -> bool) -> Vec<&T> {
+        let mut results = vec![];
+        self.root
+            .values_intersecting_where(rect, &filter, &mut results);
+        results
+    }
+
+    /// Same matches as [`Quadtree::values_intersecting_where`], written into `results` after
+    /// clearing it. Reusing one buffer across queries, e.g. from a
+    /// [`QueryScratch`](super::query_scratch::QueryScratch), skips the per-query allocation.
+    pub fn values_intersecting_into<'a>(
+        &'a self,
+        rect: &Rect,
+        filter: impl Fn(&T) -> bool,
+        results: &mut Vec<&'a T>,
+    ) {
+        results.clear();
+        self.root.values_intersecting_where(rect, &filter, results);
+    }
+
+    /// Number of values overlapping `rect`, same matching as [`Quadtree::values_intersecting`]
+    /// without allocating.
+    pub fn count_in_rect(&self, rect: &Rect) -> usize {
+        self.root.count_in_rect(rect)
+    }
+
+    /// Values whose center is within `radius` of `center`.
+    pub fn query_radius(&self, center: Vec2, radius: f32) -> Vec<&T> {
+        let mut results = vec![];
+        self.root.values_within_radius(center, radius, &mut results);
+        results
+    }
+
+    /// Values within `radius` paired with their distance to `center`, nearest first.
+    pub fn neighbors_sorted(&self, center: Vec2, radius: f32) -> Vec<(&T, f32)> {
+        let mut neighbors: Vec<(&T, f32)> = self
+            .query_radius(center, radius)
+            .into_iter()
+            .map(|value| (value, value.get_center().distance(center)))
+            .collect();
+        neighbors.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        neighbors
+    }
+
+    /// Deepest node containing `point`.
+    pub fn query_point(&self, point: &Vec2) -> Option<&QuadtreeNode<T, S>> {
+        self.root.query_point(point)
+    }
+
+    /// Panics in debug builds if the tree structure is inconsistent, no-op in release.
+    pub fn validate(&self) {
+        if cfg!(debug_assertions) {
+            if let Some(violation) = self.root.find_invariant_violation() {
+                panic!("invalid quadtree: {}", violation);
+            }
+        }
+    }
+
+    /// Mutable version of [`Quadtree::query_rect`].
+    pub fn query_rect_mut(&mut self, rect: &Rect) -> Option<&mut QuadtreeNode<T, S>> {
         self.root.query_rect_mut(rect)
     }
+
+    /// Leaves sharing part of an edge or a corner with `node_rect`, usually the rect of another
+    /// node. Leaves inside or overlapping `node_rect` aren't adjacent and are left out, so a
+    /// node's neighborhood is the node itself plus these.
+    pub fn adjacent_leaves(&self, node_rect: &Rect) -> Vec<&QuadtreeNode<T, S>> {
+        let mut results = vec![];
+        self.root.adjacent_leaves(node_rect, &mut results);
+        results
+    }
+
+    /// Mutable references to the nodes [`Quadtree::query_rect_mut`] would return for each of
+    /// `rects`, all usable at once. Rects landing in the same node yield it once, and a node
+    /// below another requested node is left out because the ancestor already gives access to it.
+    /// Rects outside the root are skipped, and nodes come back in tree order.
+    ///
+    /// Like the other node accessors, values edited through these references aren't reflected
+    /// in [`Quadtree::len`], [`Quadtree::centroid`] or [`Quadtree::bounds`].
+    pub fn query_disjoint_mut(&mut self, rects: &[Rect]) -> Vec<&mut QuadtreeNode<T, S>> {
+        let rects: Vec<&Rect> = rects.iter().collect();
+        let mut results = vec![];
+        self.root.query_disjoint_mut(&rects, &mut results);
+        results
+    }
+
+    // delete without refreshing bounds
+    fn remove(&mut self, value: &T) -> Option<T> {
+        let removed = match self.query_value_mut(value) {
+            Some(node) => node.delete(value),
+            None => None,
+        }?;
+        self.forget(&removed);
+        Some(removed)
+    }
+
+    // running totals after removed has been taken out of its node
+    fn forget(&mut self, removed: &T) {
+        self.len -= 1;
+        if self.len == 0 {
+            self.center_sum = DVec2::ZERO;
+            self.bounds = None;
+            self.bounds_stale = false;
+        } else {
+            self.center_sum -= removed.get_center().as_dvec2();
+            self.mark_bounds(removed.get_rect());
+        }
+    }
+
+    // only a value on the edge of the bounds can shrink them
+    fn mark_bounds(&mut self, rect: &Rect) {
+        if let Some(bounds) = self.bounds {
+            if touches_bounds(&bounds, rect) {
+                self.bounds_stale = true;
+            }
+        }
+    }
+}
+
+/* Internal-only Functions */
+
+fn grow_bounds(bounds: Option<Rect>, rect: &Rect) -> Rect {
+    match bounds {
+        Some(bounds) => rect_union(&bounds, rect),
+        None => *rect,
+    }
+}
+
+fn touches_bounds(bounds: &Rect, rect: &Rect) -> bool {
+    rect.min.x <= bounds.min.x
+        || rect.min.y <= bounds.min.y
+        || rect.max.x >= bounds.max.x
+        || rect.max.y >= bounds.max.y
+}
+
+fn collect_bounds<T: QuadtreeValue, S: NodeValues<T>>(
+    node: &QuadtreeNode<T, S>,
+    bounds: &mut Option<Rect>,
+) where
+    for<'a> &'a S: IntoIterator<Item = &'a T>,
+{
+    for value in &node.values {
+        *bounds = Some(grow_bounds(*bounds, value.get_rect()));
+    }
+    for child in &node.children {
+        collect_bounds(child, bounds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::Vec2, sprite::Rect};
+
+    use super::*;
+    use crate::util::{
+        quadtree::{
+            depth_scaled_threshold, query_scratch::QueryScratch, test_support::TestValue,
+            MAX_DEPTH, MIN_THRESHOLD, THRESHOLD,
+        },
+        rect::rect_contains_point,
+    };
+
+    const WORLD: Rect = Rect {
+        min: Vec2::new(-100., -100.),
+        max: Vec2::new(100., 100.),
+    };
+
+    // count unit values spread over WORLD in a scrambled order, ids 0..count
+    fn scattered_values(count: u32) -> Vec<TestValue> {
+        (0..count)
+            .map(|id| {
+                let cell = id * 7919 % count;
+                let position = Vec2::new((cell % 40) as f32, (cell / 40) as f32) * 4.9 - 99.;
+                TestValue::new(id, position, Vec2::ONE)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn values_intersecting_finds_values_barely_overlapping_the_query() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        // pokes 0.1 into the query rect from the left
+        quadtree.add(TestValue::new(0, Vec2::new(-10.9, 0.), Vec2::ONE));
+        quadtree.add(TestValue::new(1, Vec2::new(-12., 0.), Vec2::ONE));
+        let query = Rect {
+            min: Vec2::new(-10., -10.),
+            max: Vec2::new(10., 10.),
+        };
+        let found = quadtree.values_intersecting(&query);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, 0);
+    }
+
+    #[test]
+    fn bulk_inserted_values_are_all_queryable() {
+        let values = scattered_values(1600);
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(values.clone().into_iter().chain([
+            // outside the tree, skipped like add would
+            TestValue::new(5000, Vec2::new(150., 0.), Vec2::ONE),
+        ]));
+        assert_eq!(quadtree.len(), values.len());
+        assert!(!quadtree.root.is_leaf());
+        for value in &values {
+            assert!(quadtree.query_value(value).is_some(), "lost {}", value.id);
+            assert!(quadtree.values_intersecting(&value.rect).contains(&value));
+        }
+        assert!(quadtree
+            .query_value(&TestValue::new(5000, Vec2::ZERO, Vec2::ONE))
+            .is_none());
+    }
+
+    #[test]
+    fn neighbors_sorted_is_nearest_first_and_within_the_radius() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(1600));
+        let center = Vec2::new(3., -7.);
+        let radius = 25.;
+        let neighbors = quadtree.neighbors_sorted(center, radius);
+        assert_eq!(neighbors.len(), quadtree.query_radius(center, radius).len());
+        assert!(neighbors.len() > 10);
+        for pair in neighbors.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+        for (value, distance) in &neighbors {
+            assert!(*distance <= radius);
+            assert_eq!(*distance, value.get_center().distance(center));
+        }
+    }
+
+    #[test]
+    fn query_point_resolves_to_the_leaf_under_the_point() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(1600));
+        for point in [
+            Vec2::new(0.5, 0.5),
+            Vec2::new(-99.5, -99.5),
+            Vec2::new(99.5, -42.),
+            Vec2::new(-13., 77.),
+        ] {
+            let node = quadtree.query_point(&point).unwrap();
+            assert!(node.is_leaf());
+            assert!(node.depth > 0);
+            assert!(rect_contains_point(&node.rect, &point));
+        }
+        assert!(quadtree.query_point(&Vec2::new(0., 150.)).is_none());
+    }
+
+    #[test]
+    fn validate_accepts_a_built_tree_and_catches_corruption() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(1600));
+        quadtree.validate();
+        assert!(quadtree.root.find_invariant_violation().is_none());
+
+        let mut misplaced = Quadtree::empty(WORLD);
+        misplaced.extend(scattered_values(1600));
+        // a value from the far corner stored in the first child
+        let stray = TestValue::new(5000, Vec2::new(98., 98.), Vec2::ONE);
+        misplaced.root.children[0].values.insert(stray);
+        assert!(misplaced.root.find_invariant_violation().is_some());
+
+        let mut wrong_depth = Quadtree::empty(WORLD);
+        wrong_depth.extend(scattered_values(1600));
+        wrong_depth.root.children[1].depth = 3;
+        assert!(wrong_depth.root.find_invariant_violation().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid quadtree")]
+    fn validate_panics_on_a_corrupted_tree() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(1600));
+        quadtree.root.children.pop();
+        quadtree.validate();
+    }
+
+    #[test]
+    fn centroid_and_bounds_follow_adds_and_deletes() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        assert_eq!(quadtree.centroid(), None);
+        assert!(quadtree.bounds().is_none());
+        let a = TestValue::new(0, Vec2::new(-10., -10.), Vec2::splat(2.));
+        let b = TestValue::new(1, Vec2::new(20., 0.), Vec2::splat(2.));
+        let c = TestValue::new(2, Vec2::new(0., 40.), Vec2::splat(2.));
+        quadtree.add(a.clone());
+        quadtree.add(b.clone());
+        quadtree.add(c.clone());
+        assert_eq!(quadtree.centroid(), Some(Vec2::new(13. / 3., 11.)));
+        let bounds = quadtree.bounds().unwrap();
+        assert_eq!(
+            (bounds.min, bounds.max),
+            (Vec2::new(-10., -10.), Vec2::new(22., 42.))
+        );
+
+        // deleting the value on the edge shrinks the bounds back
+        quadtree.delete(&c);
+        assert_eq!(quadtree.centroid(), Some(Vec2::new(6., -4.)));
+        let bounds = quadtree.bounds().unwrap();
+        assert_eq!(
+            (bounds.min, bounds.max),
+            (Vec2::new(-10., -10.), Vec2::new(22., 2.))
+        );
+
+        quadtree.delete(&a);
+        quadtree.delete(&b);
+        assert_eq!(quadtree.centroid(), None);
+        assert!(quadtree.bounds().is_none());
+    }
+
+    #[test]
+    fn nodes_subdivide_at_their_depth_scaled_capacity() {
+        // one unit value per 10x10 cell, clear of the quadrant edges
+        let grid = |count: usize| {
+            (0..count as u32).map(|id| {
+                let cell = Vec2::new((id % 20) as f32, (id / 20) as f32);
+                TestValue::new(id, cell * 10. - 95.5, Vec2::ONE)
+            })
+        };
+        for depth in 0..MAX_DEPTH {
+            let mut node: QuadtreeNode<TestValue> =
+                QuadtreeNode::with_threshold(WORLD, depth, depth_scaled_threshold);
+            let capacity = (THRESHOLD >> depth).max(MIN_THRESHOLD);
+            assert_eq!(node.capacity(), capacity, "depth {}", depth);
+            for value in grid(capacity) {
+                node.add(value);
+            }
+            assert!(node.is_leaf(), "depth {} split early", depth);
+            node.add(TestValue::new(5000, Vec2::new(40., 50.), Vec2::ONE));
+            assert!(!node.is_leaf(), "depth {} did not split", depth);
+            let stored = node.aggregate_statistic(&|n| n.values.len());
+            assert_eq!(stored, capacity + 1, "depth {}", depth);
+        }
+        // past MAX_DEPTH nodes keep taking values
+        let mut deepest: QuadtreeNode<TestValue> =
+            QuadtreeNode::with_threshold(WORLD, MAX_DEPTH, depth_scaled_threshold);
+        for value in grid(MIN_THRESHOLD * 2) {
+            deepest.add(value);
+        }
+        assert!(deepest.is_leaf());
+    }
+
+    #[test]
+    fn mass_deletion_and_rebuild_release_node_storage() {
+        let capacity = |quadtree: &Quadtree<TestValue>| {
+            quadtree
+                .root
+                .aggregate_statistic(&|node| node.values.capacity())
+        };
+        // nodes with no values that still hold on to storage
+        let idle = |quadtree: &Quadtree<TestValue>| {
+            quadtree.root.aggregate_statistic(&|node| {
+                (node.values.is_empty() && node.values.capacity() > 0) as usize
+            })
+        };
+        let values = scattered_values(1600);
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(values.clone());
+        let full = capacity(&quadtree);
+        assert!(full >= values.len());
+
+        for value in &values[20..] {
+            quadtree.delete(value);
+        }
+        assert_eq!(quadtree.len(), 20);
+        assert_eq!(idle(&quadtree), 0);
+        assert!(
+            capacity(&quadtree) < full / 4,
+            "{} of {}",
+            capacity(&quadtree),
+            full
+        );
+        for value in &values[..20] {
+            quadtree.delete(value);
+        }
+        assert_eq!(capacity(&quadtree), 0);
+
+        // a rebuilt tree holds no more storage than its values need
+        quadtree.rebuild(WORLD, values[..300].iter().cloned());
+        assert_eq!(idle(&quadtree), 0);
+        let oversized = quadtree.root.aggregate_statistic(&|node| {
+            (node.values.capacity() >= 2 * node.values.len() + 4) as usize
+        });
+        assert_eq!(oversized, 0);
+    }
+
+    #[test]
+    fn query_value_locates_a_value_through_a_shared_borrow() {
+        let values = scattered_values(1600);
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(values.clone());
+        let quadtree = &quadtree;
+        for value in values.iter().step_by(37) {
+            let node = quadtree.query_value(value).unwrap();
+            assert!(node.values.contains(value));
+            assert!(node.contains_rect(&value.rect));
+            // the same node the root finds, while the first borrow is still alive
+            let found = quadtree.root.find_value(value).unwrap();
+            assert!(std::ptr::eq(node, found));
+        }
+        let missing = TestValue::new(5000, Vec2::ZERO, Vec2::ONE);
+        assert!(quadtree.query_value(&missing).is_none());
+    }
+
+    #[test]
+    fn query_rect_path_runs_from_the_root_to_the_queried_node() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(1600));
+        let small = |x: f32, y: f32| Rect {
+            min: Vec2::new(x, y),
+            max: Vec2::new(x + 0.5, y + 0.5),
+        };
+        for rect in [small(60.2, 60.2), small(-80.2, 10.2), small(-0.2, 40.2)] {
+            let node = quadtree.query_rect(&rect).unwrap();
+            let path = quadtree.query_rect_path(&rect);
+            assert_eq!(path.len(), node.depth + 1);
+            assert_eq!(path[0].min, WORLD.min);
+            assert_eq!(path[0].max, WORLD.max);
+            let last = path.last().unwrap();
+            assert_eq!((last.min, last.max), (node.rect.min, node.rect.max));
+        }
+        // only a straddling rect stays at the root, the others went down at least a level
+        assert!(quadtree.query_rect_path(&small(60.2, 60.2)).len() > 1);
+        assert_eq!(quadtree.query_rect_path(&small(-0.2, 40.2)).len(), 1);
+        assert!(quadtree.query_rect_path(&small(150., 0.)).is_empty());
+    }
+
+    #[test]
+    fn scratch_query_matches_the_allocating_one() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(1600));
+        let even = |value: &TestValue| value.id.is_multiple_of(2);
+        let mut scratch = QueryScratch::new();
+        let mut results = scratch.take();
+        for (x, y, size) in [
+            (-95., -95., 30.),
+            (-10., -10., 20.),
+            (40., -60., 55.),
+            (0., 0., 1.),
+        ] {
+            let rect = Rect {
+                min: Vec2::new(x, y),
+                max: Vec2::new(x + size, y + size),
+            };
+            // results still holds the previous query's values, they must not leak into these
+            quadtree.values_intersecting_into(&rect, even, &mut results);
+            let expected = quadtree.values_intersecting_where(&rect, even);
+            let ids = |values: &[&TestValue]| values.iter().map(|v| v.id).collect::<Vec<_>>();
+            assert_eq!(ids(&results), ids(&expected));
+        }
+        scratch.restore(results);
+    }
+
+    #[test]
+    fn retain_drops_values_and_keeps_the_totals_in_step() {
+        let values = scattered_values(1600);
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(values.clone());
+        quadtree.retain(|value| value.id % 4 != 0);
+
+        let mut expected = Quadtree::empty(WORLD);
+        expected.extend(values.iter().filter(|v| v.id % 4 != 0).cloned());
+        assert_eq!(quadtree.len(), expected.len());
+        assert!(quadtree
+            .centroid()
+            .unwrap()
+            .abs_diff_eq(expected.centroid().unwrap(), 1e-4));
+        let (bounds, expected_bounds) = (quadtree.bounds().unwrap(), expected.bounds().unwrap());
+        assert_eq!(
+            (bounds.min, bounds.max),
+            (expected_bounds.min, expected_bounds.max)
+        );
+        for value in &values {
+            assert_eq!(quadtree.query_value(value).is_some(), value.id % 4 != 0);
+        }
+        quadtree.validate();
+
+        quadtree.retain(|_| false);
+        assert!(quadtree.is_empty());
+        assert!(quadtree.bounds().is_none() && quadtree.centroid().is_none());
+        assert!(quadtree.root.is_leaf());
+    }
+
+    #[test]
+    fn disjoint_mut_returns_distinct_nodes_and_merges_overlapping_requests() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(scattered_values(400));
+        let corner = |x: f32, y: f32| TestValue::new(0, Vec2::new(x, y), Vec2::ONE).rect;
+        let rects = [
+            corner(-95., -95.),
+            corner(90., -95.),
+            corner(-95., 90.),
+            corner(90., 90.),
+        ];
+        let expected: Vec<Rect> = rects
+            .iter()
+            .map(|rect| quadtree.query_rect(rect).unwrap().rect)
+            .collect();
+        let mut nodes = quadtree.query_disjoint_mut(&rects);
+        assert_eq!(nodes.len(), 4);
+        for (node, expected) in nodes.iter().zip(&expected) {
+            assert_eq!((node.rect.min, node.rect.max), (expected.min, expected.max));
+        }
+        // every node can be written to while the others are still borrowed
+        for (id, node) in (1000..).zip(nodes.iter_mut()) {
+            let min = node.rect.min;
+            assert!(node.add(TestValue::new(id, min, Vec2::splat(0.5))));
+        }
+
+        // the same rect twice comes back once
+        assert_eq!(quadtree.query_disjoint_mut(&[rects[0], rects[0]]).len(), 1);
+        // a rect straddling the center lands in the root, which covers every other request
+        let center = corner(-0.5, -0.5);
+        let nodes = quadtree.query_disjoint_mut(&[rects[0], center, rects[3]]);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].depth, 0);
+        // rects outside the root are skipped
+        assert!(quadtree
+            .query_disjoint_mut(&[corner(200., 200.)])
+            .is_empty());
+    }
+
+    #[test]
+    fn inserting_a_stored_value_again_leaves_the_tree_unchanged() {
+        let values = scattered_values(1600);
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(values.clone());
+        let centroid = quadtree.centroid();
+        // the root has room again since most of these went down, so a plain add of each copy
+        // would land in a different node than the stored value
+        for value in &values {
+            assert!(!quadtree.insert_unique(value.clone()));
+        }
+        assert_eq!(quadtree.len(), values.len());
+        assert_eq!(
+            quadtree.root.aggregate_statistic(&|node| node.values.len()),
+            values.len()
+        );
+        assert_eq!(quadtree.centroid(), centroid);
+        quadtree.validate();
+        assert!(quadtree.insert_unique(TestValue::new(10_000, Vec2::new(5., 5.), Vec2::ONE)));
+        assert_eq!(quadtree.len(), values.len() + 1);
+    }
+
+    #[test]
+    fn relocated_values_move_to_a_node_their_new_rect_fits_in() {
+        let values = scattered_values(1600);
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.extend(values.clone());
+        let centroid = quadtree.centroid();
+        let stored = |quadtree: &Quadtree<TestValue>| {
+            quadtree.root.aggregate_statistic(&|node| node.values.len())
+        };
+        // from the bottom-left corner across to the top-right one
+        let start = values[0].clone();
+        let moved = TestValue::new(start.id, Vec2::splat(90.), Vec2::ONE);
+        // the node holding it can't keep it, so relocate has to take it out and add it again
+        let node = quadtree.query_value(&start).unwrap();
+        assert!(!node.contains_rect(&moved.rect) || node.children_contain_rect(&moved.rect));
+        quadtree.relocate(&start.rect, moved.clone());
+        let node = quadtree.query_value(&moved).unwrap();
+        assert!(node.contains_rect(&moved.rect));
+        assert_eq!(node.values.get(&moved).unwrap().rect.min, moved.rect.min);
+        assert_eq!(quadtree.len(), values.len());
+        assert_eq!(stored(&quadtree), values.len());
+        quadtree.validate();
+        // a stale previous rect misses it, so the whole tree is searched instead
+        quadtree.relocate(&start.rect, start.clone());
+        let node = quadtree.query_value(&start).unwrap();
+        assert_eq!(node.values.get(&start).unwrap().rect.min, start.rect.min);
+        assert_eq!(quadtree.len(), values.len());
+        assert_eq!(stored(&quadtree), values.len());
+        assert_eq!(quadtree.centroid(), centroid);
+        quadtree.validate();
+    }
 }
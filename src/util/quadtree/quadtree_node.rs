@@ -1,34 +1,76 @@
-use std::ops::AddAssign;
+use std::{marker::PhantomData, mem, ops::AddAssign};
 
-use bevy::{sprite::Rect, utils::HashSet};
+use bevy::{prelude::Vec2, sprite::Rect, utils::HashSet};
 
-use crate::util::rect::{partition_rect, rect_contains_rect};
+use crate::util::{
+    rect::{
+        quadrant_index, rect_contains_point, rect_contains_rect, rect_intersects_rect,
+        rect_touches_rect,
+    },
+    region::Partition,
+};
 
-use super::{quadtree_value::QuadtreeValue, MAX_DEPTH, THRESHOLD};
+use super::{
+    constant_threshold, node_values::NodeValues, quadtree_value::QuadtreeValue, ThresholdFn,
+    MAX_DEPTH,
+};
 
-pub struct QuadtreeNode<T> {
+// deleting shrinks a node's storage once its values use no more than 1/SHRINK_RATIO of it
+const SHRINK_RATIO: usize = 4;
+
+/// One node of a [`Quadtree`](super::quadtree::Quadtree): the values it holds itself and
+/// either no children or one per quadrant of its rect. `S` is where the node keeps its values,
+/// see [`NodeValues`].
+pub struct QuadtreeNode<T, S = HashSet<T>> {
+    /// Area this node covers.
     pub rect: Rect,
+    /// Distance from the root, which is at depth 0.
     pub depth: usize,
-    pub children: Vec<QuadtreeNode<T>>,
-    pub values: HashSet<T>,
+    /// Empty for a leaf, otherwise the quadrants in [`partition_rect`] order.
+    ///
+    /// [`partition_rect`]: crate::util::rect::partition_rect
+    pub children: Vec<QuadtreeNode<T, S>>,
+    /// Values stored in this node, not counting its descendants.
+    pub values: S,
+    /// Capacity by depth, shared by every node in the tree.
+    pub threshold: ThresholdFn,
+    // T only appears inside S
+    value_type: PhantomData<T>,
 }
 
-impl<T: QuadtreeValue> QuadtreeNode<T> {
+impl<T: QuadtreeValue, S: NodeValues<T>> QuadtreeNode<T, S>
+where
+    for<'a> &'a S: IntoIterator<Item = &'a T>,
+{
+    /// Creates a leaf with no values and the default [`THRESHOLD`](super::THRESHOLD) capacity.
     pub fn empty(rect: Rect, depth: usize) -> Self {
+        QuadtreeNode::with_threshold(rect, depth, constant_threshold)
+    }
+
+    /// Creates a leaf with no values whose capacity at each depth comes from `threshold`.
+    pub fn with_threshold(rect: Rect, depth: usize, threshold: ThresholdFn) -> Self {
         QuadtreeNode {
             rect,
             depth,
             children: vec![],
-            values: HashSet::new(),
+            values: S::default(),
+            threshold,
+            value_type: PhantomData,
         }
     }
 
+    /// Max values this node holds before pushing new ones down to its children.
+    pub fn capacity(&self) -> usize {
+        (self.threshold)(self.depth)
+    }
+
+    /// Whether the node has no children.
     pub fn is_leaf(&self) -> bool {
-        self.children.len() == 0
+        self.children.is_empty()
     }
 
-    // loop through self and all descendents, run aggregation function and return summed result
-    pub fn aggregate_statistic<AggT: AddAssign<AggT>, AggFn: Fn(&QuadtreeNode<T>) -> AggT>(
+    /// Runs `agg_func` on this node and every descendant and returns the sum.
+    pub fn aggregate_statistic<AggT: AddAssign<AggT>, AggFn: Fn(&QuadtreeNode<T, S>) -> AggT>(
         &self,
         agg_func: &AggFn,
     ) -> AggT {
@@ -36,75 +78,149 @@ impl<T: QuadtreeValue> QuadtreeNode<T> {
         for child in &self.children {
             agg_value += child.aggregate_statistic(agg_func);
         }
-        return agg_value;
+        agg_value
     }
 
-    // add value to self if room, otherwise propagate to children, fall back to self if needed
-    pub fn add(&mut self, value: T) {
+    /// Adds `value` here if there is room, otherwise to the child that fully contains it,
+    /// splitting first if needed; a value no child can hold stays here. Returns false if an
+    /// equal value was already stored in the node it landed in.
+    pub fn add(&mut self, value: T) -> bool {
         if self.is_leaf() {
-            if self.depth >= MAX_DEPTH || self.values.len() < THRESHOLD {
-                self.values.insert(value);
+            if self.depth >= MAX_DEPTH || self.values.len() < self.capacity() {
+                self.values.insert(value)
             } else {
                 self.create_children();
                 self.distribute_values();
-                self.add(value);
+                self.add(value)
             }
         } else {
-            if self.values.len() < THRESHOLD {
-                self.values.insert(value);
+            if self.values.len() < self.capacity() {
+                self.values.insert(value)
             } else if let Some(child) = self.get_child_containing_rect_mut(value.get_rect()) {
-                child.add(value);
+                child.add(value)
             } else {
-                self.values.insert(value);
+                self.values.insert(value)
             }
         }
     }
 
+    /// Whether `rect` lies strictly inside this node's rect.
     pub fn contains_rect(&self, rect: &Rect) -> bool {
         rect_contains_rect(&self.rect, rect)
     }
 
+    /// Whether `value` is stored in this node itself, not counting descendants.
     pub fn contains_value(&self, value: &T) -> bool {
         self.values.contains(value)
     }
 
-    // helper function to determine if one or more children can hold this rect entirely
+    /// Whether one of the children can hold `rect` entirely.
     pub fn children_contain_rect(&self, rect: &Rect) -> bool {
-        if self.is_leaf() {
-            false
-        } else {
-            self.children.iter().any(|child| child.contains_rect(rect))
+        self.child_index_for(rect).is_some()
+    }
+
+    /// Child that `rect` belongs in: the one whose quadrant holds its center (see
+    /// [`quadrant_index`]), and only if that child strictly contains all of it. Anything
+    /// touching a shared edge stays in this node.
+    pub fn get_child_containing_rect_mut(
+        &mut self,
+        rect: &Rect,
+    ) -> Option<&mut QuadtreeNode<T, S>> {
+        let index = self.child_index_for(rect)?;
+        self.children.get_mut(index)
+    }
+
+    /// Node holding `value`, searching this node and every descendant.
+    pub fn find_value(&self, value: &T) -> Option<&QuadtreeNode<T, S>> {
+        if self.contains_value(value) {
+            return Some(self);
         }
+        self.children.iter().find_map(|c| c.find_value(value))
     }
 
-    pub fn get_child_containing_rect_mut(&mut self, rect: &Rect) -> Option<&mut QuadtreeNode<T>> {
+    /// Mutable version of [`QuadtreeNode::find_value`].
+    pub fn find_value_mut(&mut self, value: &T) -> Option<&mut QuadtreeNode<T, S>> {
+        if self.contains_value(value) {
+            return Some(self);
+        }
         self.children
             .iter_mut()
-            .find(|child| child.contains_rect(rect))
+            .find_map(|c| c.find_value_mut(value))
+    }
+
+    /// Like [`QuadtreeNode::find_value`], but only searches the nodes containing `rect`, the
+    /// value's last known rect.
+    pub fn find_value_within(&self, value: &T, rect: &Rect) -> Option<&QuadtreeNode<T, S>> {
+        if !self.contains_rect(rect) {
+            return None;
+        }
+        if self.contains_value(value) {
+            return Some(self);
+        }
+        self.children
+            .iter()
+            .find_map(|c| c.find_value_within(value, rect))
     }
 
-    pub fn find_value_mut(&mut self, value: &T) -> Option<&mut QuadtreeNode<T>> {
+    /// Mutable version of [`QuadtreeNode::find_value_within`].
+    pub fn find_value_within_mut(
+        &mut self,
+        value: &T,
+        rect: &Rect,
+    ) -> Option<&mut QuadtreeNode<T, S>> {
+        if !self.contains_rect(rect) {
+            return None;
+        }
         if self.contains_value(value) {
             return Some(self);
         }
         self.children
             .iter_mut()
-            .find_map(|c| c.find_value_mut(value))
+            .find_map(|c| c.find_value_within_mut(value, rect))
     }
 
+    /// Removes and returns the value equal to `value` from this node itself, collapsing its
+    /// children once they are all empty.
     pub fn delete(&mut self, value: &T) -> Option<T> {
-        // clean up children if needed
-        if !self.is_leaf() {
-            let delete_children = self.children.iter().all(|child| child.values.is_empty());
-            if delete_children {
-                self.children.clear();
-            }
+        let deleted = self.values.take(value);
+        // clean up: collapse children that are all empty, compact only visits those empty leaves
+        // here; shrinking only once storage is mostly unused keeps it amortized O(1) per delete
+        if !self.is_leaf() && self.children_are_empty() {
+            self.compact();
+        } else if self.values.len() * SHRINK_RATIO <= self.values.capacity() {
+            self.values.shrink_to_fit();
         }
-        // delete value
-        self.values.take(value)
+        deleted
     }
 
-    pub fn query_rect(&self, rect: &Rect) -> Option<&QuadtreeNode<T>> {
+    /// Keeps only the values `keep` returns true for, here and in every descendant, cleaning
+    /// up after itself the way [`QuadtreeNode::delete`] does.
+    pub fn retain(&mut self, keep: &mut impl FnMut(&T) -> bool) {
+        for child in &mut self.children {
+            child.retain(keep);
+        }
+        self.values.retain(|value| keep(value));
+        if !self.is_leaf() && self.children_are_empty() {
+            self.compact();
+        } else if self.values.len() * SHRINK_RATIO <= self.values.capacity() {
+            self.values.shrink_to_fit();
+        }
+    }
+
+    /// Drops empty subtrees and releases spare capacity left behind by deletions.
+    pub fn compact(&mut self) {
+        for child in &mut self.children {
+            child.compact();
+        }
+        if self.children_are_empty() {
+            self.children = vec![];
+        }
+        self.children.shrink_to_fit();
+        self.values.shrink_to_fit();
+    }
+
+    /// Deepest node that fully contains `rect`, `None` if this node doesn't.
+    pub fn query_rect(&self, rect: &Rect) -> Option<&QuadtreeNode<T, S>> {
         if !self.contains_rect(rect) {
             return None;
         }
@@ -114,7 +230,34 @@ impl<T: QuadtreeValue> QuadtreeNode<T> {
         }
     }
 
-    pub fn query_rect_mut(&mut self, rect: &Rect) -> Option<&mut QuadtreeNode<T>> {
+    /// Pushes the rects [`QuadtreeNode::query_rect`] descends through onto `path`, returns
+    /// whether this node contains `rect` at all.
+    pub fn query_rect_path(&self, rect: &Rect, path: &mut Vec<Rect>) -> bool {
+        if !self.contains_rect(rect) {
+            return false;
+        }
+        path.push(self.rect);
+        for child in &self.children {
+            if child.query_rect_path(rect, path) {
+                break;
+            }
+        }
+        true
+    }
+
+    /// Deepest node containing `point`.
+    pub fn query_point(&self, point: &Vec2) -> Option<&QuadtreeNode<T, S>> {
+        if !rect_contains_point(&self.rect, point) {
+            return None;
+        }
+        match self.children.iter().find_map(|c| c.query_point(point)) {
+            Some(gc) => Some(gc),
+            None => Some(self),
+        }
+    }
+
+    /// Mutable version of [`QuadtreeNode::query_rect`].
+    pub fn query_rect_mut(&mut self, rect: &Rect) -> Option<&mut QuadtreeNode<T, S>> {
         if !self.contains_rect(rect) {
             return None;
         }
@@ -131,40 +274,225 @@ impl<T: QuadtreeValue> QuadtreeNode<T> {
         None
     }
 
-    pub fn get_all_descendant_nodes(&self) -> Box<dyn Iterator<Item = &QuadtreeNode<T>> + '_> {
+    /// Pushes the leaves touching `rect` along an edge or at a corner onto `results`, skipping
+    /// subtrees that don't reach it.
+    pub fn adjacent_leaves<'a>(&'a self, rect: &Rect, results: &mut Vec<&'a QuadtreeNode<T, S>>) {
+        let touches = rect_touches_rect(&self.rect, rect);
+        if !touches && !rect_intersects_rect(&self.rect, rect) {
+            return;
+        }
+        if self.is_leaf() {
+            if touches {
+                results.push(self);
+            }
+            return;
+        }
+        for child in &self.children {
+            child.adjacent_leaves(rect, results);
+        }
+    }
+
+    /// [`QuadtreeNode::query_rect_mut`] for several rects at once, pushing disjoint borrows of
+    /// the answers onto `results`. A node that is the answer for any rect is pushed whole, so
+    /// rects that would have landed below it collapse into it.
+    pub fn query_disjoint_mut<'a>(
+        &'a mut self,
+        rects: &[&Rect],
+        results: &mut Vec<&'a mut QuadtreeNode<T, S>>,
+    ) {
+        let contained: Vec<&Rect> = rects
+            .iter()
+            .copied()
+            .filter(|rect| self.contains_rect(rect))
+            .collect();
+        if contained.is_empty() {
+            return;
+        }
+        if contained
+            .iter()
+            .any(|rect| !self.children_contain_rect(rect))
+        {
+            results.push(self);
+            return;
+        }
+        for child in self.children.iter_mut() {
+            child.query_disjoint_mut(&contained, results);
+        }
+    }
+
+    /// Pushes the values whose rect overlaps `rect` onto `results`, descending only into
+    /// overlapping nodes.
+    pub fn values_intersecting<'a>(&'a self, rect: &Rect, results: &mut Vec<&'a T>) {
+        self.values_intersecting_where(rect, &|_| true, results);
+    }
+
+    /// [`QuadtreeNode::values_intersecting`], keeping only values accepted by `filter`.
+    pub fn values_intersecting_where<'a, F: Fn(&T) -> bool>(
+        &'a self,
+        rect: &Rect,
+        filter: &F,
+        results: &mut Vec<&'a T>,
+    ) {
+        self.for_each_intersecting_where(rect, filter, &mut |value| results.push(value));
+    }
+
+    /// Calls `visit` on every value [`QuadtreeNode::values_intersecting_where`] would collect,
+    /// in the same order.
+    pub fn for_each_intersecting_where<'a, F: Fn(&T) -> bool, V: FnMut(&'a T)>(
+        &'a self,
+        rect: &Rect,
+        filter: &F,
+        visit: &mut V,
+    ) {
+        if !rect_intersects_rect(&self.rect, rect) {
+            return;
+        }
+        for value in &self.values {
+            if rect_intersects_rect(rect, value.get_rect()) && filter(value) {
+                visit(value);
+            }
+        }
+        for child in &self.children {
+            child.for_each_intersecting_where(rect, filter, visit);
+        }
+    }
+
+    /// Number of values whose rect overlaps `rect`, without collecting them.
+    pub fn count_in_rect(&self, rect: &Rect) -> usize {
+        if !rect_intersects_rect(&self.rect, rect) {
+            return 0;
+        }
+        let own = (&self.values)
+            .into_iter()
+            .filter(|v| rect_intersects_rect(rect, v.get_rect()))
+            .count();
+        own + self
+            .children
+            .iter()
+            .map(|child| child.count_in_rect(rect))
+            .sum::<usize>()
+    }
+
+    /// Pushes the values whose center lies within `radius` of `center` onto `results`,
+    /// skipping nodes out of range.
+    pub fn values_within_radius<'a>(&'a self, center: Vec2, radius: f32, results: &mut Vec<&'a T>) {
+        let bounds = Rect {
+            min: center - Vec2::splat(radius),
+            max: center + Vec2::splat(radius),
+        };
+        if !rect_intersects_rect(&self.rect, &bounds) {
+            return;
+        }
+        let radius_squared = radius * radius;
+        results.extend(
+            (&self.values)
+                .into_iter()
+                .filter(|v| v.get_center().distance_squared(center) <= radius_squared),
+        );
+        for child in &self.children {
+            child.values_within_radius(center, radius, results);
+        }
+    }
+
+    /// First broken structural invariant in this node or a descendant, if any.
+    pub fn find_invariant_violation(&self) -> Option<String> {
+        if self.depth > MAX_DEPTH {
+            return Some(format!("node at depth {} exceeds MAX_DEPTH", self.depth));
+        }
+        if (&self.values)
+            .into_iter()
+            .any(|v| !self.contains_rect(v.get_rect()))
+        {
+            return Some(format!(
+                "node at depth {} holds a value outside its rect",
+                self.depth
+            ));
+        }
+        if self.is_leaf() {
+            return None;
+        }
+        // children must be exactly the partition of our rect, one level deeper
+        let quadrants = self.rect.partition();
+        if self.children.len() != quadrants.len() {
+            return Some(format!(
+                "node at depth {} has {} children",
+                self.depth,
+                self.children.len()
+            ));
+        }
+        for (child, quadrant) in self.children.iter().zip(quadrants.iter()) {
+            if child.rect.min != quadrant.min || child.rect.max != quadrant.max {
+                return Some(format!(
+                    "children of node at depth {} don't partition it",
+                    self.depth
+                ));
+            }
+            if child.depth != self.depth + 1 {
+                return Some(format!(
+                    "child of node at depth {} has depth {}",
+                    self.depth, child.depth
+                ));
+            }
+        }
+        self.children
+            .iter()
+            .find_map(|child| child.find_invariant_violation())
+    }
+
+    /// Every node below this one, depth first, not including this node.
+    pub fn get_all_descendant_nodes(&self) -> Box<dyn Iterator<Item = &QuadtreeNode<T, S>> + '_> {
         Box::new(
             self.children
                 .iter()
-                .filter(|c| !c.is_leaf())
-                .flat_map(|c| c.get_all_descendant_nodes()),
+                .flat_map(|c| std::iter::once(c).chain(c.get_all_descendant_nodes())),
         )
     }
 
+    /// Values stored in this node and every descendant.
     pub fn get_all_descendant_values(&self) -> Box<dyn Iterator<Item = &T> + '_> {
         Box::new(
-            self.values.iter().chain(
-                self.get_all_descendant_nodes()
-                    .flat_map(|c| c.values.iter()),
-            ),
+            (&self.values)
+                .into_iter()
+                .chain(self.get_all_descendant_nodes().flat_map(|c| &c.values)),
         )
     }
 
+    fn child_index_for(&self, rect: &Rect) -> Option<usize> {
+        if self.is_leaf() {
+            return None;
+        }
+        let center = (rect.min + rect.max) / 2.;
+        let index = quadrant_index(&self.rect, center);
+        self.children
+            .get(index)
+            .filter(|child| child.contains_rect(rect))
+            .map(|_| index)
+    }
+
+    // true when every child is a leaf with no values, grandchildren may still hold values otherwise
+    fn children_are_empty(&self) -> bool {
+        self.children
+            .iter()
+            .all(|child| child.is_leaf() && child.values.is_empty())
+    }
+
     fn create_children(&mut self) {
-        if self.children.len() > 0 {
+        if !self.is_leaf() {
             return;
         }
         self.children.extend(
-            partition_rect(&self.rect)
+            self.rect
+                .partition()
                 .iter()
-                .map(|&rect| QuadtreeNode::empty(rect, self.depth + 1)),
+                .map(|&rect| QuadtreeNode::with_threshold(rect, self.depth + 1, self.threshold)),
         );
     }
 
     fn distribute_values(&mut self) {
-        if self.children.len() == 0 {
+        if self.is_leaf() {
             return;
         }
-        let values: Vec<T> = self.values.drain().collect();
+        let values = mem::take(&mut self.values);
         for value in values {
             if let Some(child) = self.get_child_containing_rect_mut(value.get_rect()) {
                 child.add(value);
@@ -174,3 +502,114 @@ impl<T: QuadtreeValue> QuadtreeNode<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{quadtree::test_support::TestValue, rect::partition_rect};
+
+    const WORLD: Rect = Rect {
+        min: Vec2::new(-100., -100.),
+        max: Vec2::new(100., 100.),
+    };
+
+    fn split_node() -> QuadtreeNode<TestValue> {
+        let mut node = QuadtreeNode::empty(WORLD, 0);
+        node.create_children();
+        node
+    }
+
+    #[test]
+    fn rects_go_to_the_child_whose_quadrant_holds_their_center() {
+        let node = split_node();
+        for (index, quarter) in partition_rect(&WORLD).iter().enumerate() {
+            let center = (quarter.min + quarter.max) / 2.;
+            let rect = Rect {
+                min: center - Vec2::ONE,
+                max: center + Vec2::ONE,
+            };
+            assert_eq!(node.child_index_for(&rect), Some(index));
+            assert_eq!(node.children[index].rect.min, quarter.min);
+        }
+    }
+
+    #[test]
+    fn rects_on_a_shared_edge_stay_in_the_parent() {
+        let mut node = split_node();
+        let straddling = [
+            // centered on the midpoint
+            TestValue::new(0, Vec2::splat(-1.), Vec2::splat(2.)),
+            // crossing the vertical split line
+            TestValue::new(1, Vec2::new(-1., 50.), Vec2::splat(2.)),
+            // flush against the horizontal split line from above
+            TestValue::new(2, Vec2::new(-50., 0.), Vec2::splat(2.)),
+            // flush against the parent's outer edge
+            TestValue::new(3, Vec2::new(98., 50.), Vec2::splat(2.)),
+        ];
+        for value in &straddling {
+            assert!(!node.children_contain_rect(&value.rect), "{}", value.id);
+        }
+        // fill the parent so values have to go down if they can
+        for id in 0..node.capacity() as u32 {
+            node.values
+                .insert(TestValue::new(1000 + id, Vec2::ZERO, Vec2::ONE));
+        }
+        for value in straddling {
+            let id = value.id;
+            assert!(node.add(value));
+            assert!(node.values.iter().any(|v| v.id == id), "{}", id);
+        }
+        assert!(node.children.iter().all(|child| child.values.is_empty()));
+    }
+
+    #[test]
+    fn a_central_leaf_is_adjacent_to_the_eight_leaves_around_it() {
+        // a 4x4 grid of 50x50 leaves
+        let mut node = split_node();
+        for child in &mut node.children {
+            child.create_children();
+        }
+        let central = Rect {
+            min: Vec2::new(-50., -50.),
+            max: Vec2::new(0., 0.),
+        };
+        let mut adjacent = vec![];
+        node.adjacent_leaves(&central, &mut adjacent);
+        let mut mins: Vec<(i32, i32)> = adjacent
+            .iter()
+            .map(|leaf| (leaf.rect.min.x as i32, leaf.rect.min.y as i32))
+            .collect();
+        mins.sort_unstable();
+        let mut expected = vec![];
+        for x in [-100, -50, 0] {
+            for y in [-100, -50, 0] {
+                if (x, y) != (-50, -50) {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(mins, expected);
+    }
+
+    #[test]
+    fn descendants_cover_every_node_and_value_below_the_root() {
+        // one child split again: 4 children plus 4 grandchildren
+        let mut node = split_node();
+        node.children[3].create_children();
+        assert_eq!(node.get_all_descendant_nodes().count(), 8);
+        let depths: Vec<usize> = node.get_all_descendant_nodes().map(|n| n.depth).collect();
+        assert_eq!(depths, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+        node.values.insert(TestValue::new(0, Vec2::ZERO, Vec2::ONE));
+        node.children[0]
+            .values
+            .insert(TestValue::new(1, Vec2::splat(-90.), Vec2::ONE));
+        node.children[3].children[2].values.insert(TestValue::new(
+            2,
+            Vec2::new(10., 60.),
+            Vec2::ONE,
+        ));
+        let mut ids: Vec<u32> = node.get_all_descendant_values().map(|v| v.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![0, 1, 2]);
+    }
+}
@@ -1,4 +1,13 @@
-use super::{quadtree::Quadtree, quadtree_node::QuadtreeNode, quadtree_value::QuadtreeValue};
+use std::ops::AddAssign;
+
+use super::{
+    node_values::NodeValues, quadtree::Quadtree, quadtree_node::QuadtreeNode,
+    quadtree_value::QuadtreeValue, MAX_DEPTH, THRESHOLD,
+};
+
+// largest value count of each values_per_node bucket but the last, which holds the nodes over
+// THRESHOLD: 0, 1-15, 16-63, 64-THRESHOLD and more than THRESHOLD
+const VALUE_BUCKET_MAX: [usize; 4] = [0, 15, 63, THRESHOLD];
 
 #[derive(Debug)]
 pub struct QuadtreeStats {
@@ -6,28 +15,186 @@ pub struct QuadtreeStats {
     pub num_values: usize,
     pub average_depth: f32,
     pub average_num_values: f32,
+    // nodes per value count bucket, see VALUE_BUCKET_MAX. Like num_nodes, it doesn't count the
+    // root
+    pub values_per_node: Vec<usize>,
+    // entry i counts the leaves at depth i, from 0 to MAX_DEPTH, so an unsplit root is the one
+    // leaf at depth 0; a balanced tree has them all in one or two buckets
+    pub leaf_depths: Vec<usize>,
 }
 
 impl QuadtreeStats {
     // calcuates common statistics about a quadtree
-    pub fn calculate<T: QuadtreeValue>(quadtree: &Quadtree<T>) -> QuadtreeStats {
+    pub fn calculate<T: QuadtreeValue, S: NodeValues<T>>(quadtree: &Quadtree<T, S>) -> QuadtreeStats
+    where
+        for<'a> &'a S: IntoIterator<Item = &'a T>,
+    {
         // functions
-        let count_children_fn: fn(&QuadtreeNode<T>) -> usize = |node| node.children.len();
-        let count_values_fn: fn(&QuadtreeNode<T>) -> usize = |node| node.values.len();
-        let total_depth_fn: fn(&QuadtreeNode<T>) -> f32 = |node| node.depth as f32;
+        let count_children_fn: fn(&QuadtreeNode<T, S>) -> usize = |node| node.children.len();
+        let count_values_fn: fn(&QuadtreeNode<T, S>) -> usize = |node| node.values.len();
+        let total_depth_fn: fn(&QuadtreeNode<T, S>) -> f32 = |node| node.depth as f32;
+        let values_histogram_fn: fn(&QuadtreeNode<T, S>) -> Histogram = |node| {
+            let mut histogram = Histogram::empty(VALUE_BUCKET_MAX.len() + 1);
+            if node.depth > 0 {
+                histogram.0[value_bucket(node.values.len())] = 1;
+            }
+            histogram
+        };
+        let depth_histogram_fn: fn(&QuadtreeNode<T, S>) -> Histogram = |node| {
+            let mut histogram = Histogram::empty(MAX_DEPTH + 1);
+            if node.is_leaf() {
+                histogram.0[node.depth] = 1;
+            }
+            histogram
+        };
         let num_nodes = quadtree.root.aggregate_statistic(&count_children_fn);
         let num_values = quadtree.root.aggregate_statistic(&count_values_fn);
-        let average_depth = quadtree.root.aggregate_statistic(&total_depth_fn) / (num_nodes as f32).max(1.);
-        let average_num_values = num_values as f32 / (num_nodes as f32).max(1.);
+        // the sums include the root, so average over it too; that also keeps the divisor above
+        // zero when the root has no children
+        let nodes_summed = (num_nodes + 1) as f32;
+        let average_depth = quadtree.root.aggregate_statistic(&total_depth_fn) / nodes_summed;
+        let average_num_values = num_values as f32 / nodes_summed;
         QuadtreeStats {
             num_nodes,
             num_values,
             average_depth,
             average_num_values,
+            values_per_node: quadtree.root.aggregate_statistic(&values_histogram_fn).0,
+            leaf_depths: quadtree.root.aggregate_statistic(&depth_histogram_fn).0,
         }
     }
 
     pub fn print(&self) {
         println!("{:?}", self);
     }
+
+    // one line per bucket, empty buckets included so gaps in the distribution stand out
+    pub fn print_histograms(&self) {
+        println!("values per node:");
+        for (bucket, count) in self.values_per_node.iter().enumerate() {
+            println!("{:>8}: {}", value_bucket_label(bucket), count);
+        }
+        println!("leaf depths:");
+        for (depth, count) in self.leaf_depths.iter().enumerate() {
+            println!("{:>8}: {}", depth, count);
+        }
+    }
+}
+
+/* Internal-only Functions */
+
+// counts per bucket, added bucket by bucket so it can be summed over the tree like any other
+// statistic
+struct Histogram(Vec<usize>);
+
+impl Histogram {
+    fn empty(buckets: usize) -> Self {
+        Histogram(vec![0; buckets])
+    }
+}
+
+impl AddAssign for Histogram {
+    fn add_assign(&mut self, other: Self) {
+        for (count, other_count) in self.0.iter_mut().zip(other.0) {
+            *count += other_count;
+        }
+    }
+}
+
+fn value_bucket(num_values: usize) -> usize {
+    VALUE_BUCKET_MAX
+        .iter()
+        .position(|max| num_values <= *max)
+        .unwrap_or(VALUE_BUCKET_MAX.len())
+}
+
+fn value_bucket_label(bucket: usize) -> String {
+    match bucket {
+        0 => "0".to_string(),
+        _ if bucket < VALUE_BUCKET_MAX.len() => {
+            format!(
+                "{}-{}",
+                VALUE_BUCKET_MAX[bucket - 1] + 1,
+                VALUE_BUCKET_MAX[bucket]
+            )
+        }
+        _ => format!(">{}", THRESHOLD),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::Vec2, sprite::Rect};
+
+    use super::*;
+    use crate::util::quadtree::test_support::TestValue;
+
+    const WORLD: Rect = Rect {
+        min: Vec2::new(-100., -100.),
+        max: Vec2::new(100., 100.),
+    };
+
+    #[test]
+    fn averages_count_the_root_and_are_zero_for_an_empty_tree() {
+        let mut quadtree = Quadtree::empty(WORLD);
+        let stats = QuadtreeStats::calculate(&quadtree);
+        assert_eq!((stats.num_nodes, stats.num_values), (0, 0));
+        assert_eq!((stats.average_depth, stats.average_num_values), (0., 0.));
+
+        // an unsplit root is the only node, so it alone sets the averages
+        let values: Vec<TestValue> = (0..10)
+            .map(|id| TestValue::new(id, Vec2::new(id as f32 * 5., 0.), Vec2::ONE))
+            .collect();
+        quadtree.extend(values.clone());
+        let stats = QuadtreeStats::calculate(&quadtree);
+        assert_eq!(stats.num_nodes, 0);
+        assert_eq!((stats.average_depth, stats.average_num_values), (0., 10.));
+
+        // emptied again, nothing is left to average
+        for value in &values {
+            quadtree.delete(value);
+        }
+        let stats = QuadtreeStats::calculate(&quadtree);
+        assert_eq!(stats.average_num_values, 0.);
+        assert!(stats.average_depth.is_finite());
+    }
+
+    #[test]
+    fn histograms_bucket_a_known_tree() {
+        // a capacity of 0 sends every value to the deepest node that holds it
+        let mut quadtree = Quadtree::with_threshold(WORLD, |_| 0);
+        let mut next_id = 0;
+        let mut add = |quadtree: &mut Quadtree<TestValue>, count: u32, center: Vec2, size: f32| {
+            for _ in 0..count {
+                let min = center - Vec2::splat(size / 2.);
+                quadtree.add(TestValue::new(next_id, min, Vec2::splat(size)));
+                next_id += 1;
+            }
+        };
+        // 300 values in the bottom left corner split the tree down to MAX_DEPTH along it; the
+        // other groups straddle the centers of the nodes at depths 1, 2 and 3 on the way down
+        add(&mut quadtree, 300, Vec2::splat(-99.9), 0.01);
+        add(&mut quadtree, 5, Vec2::splat(-50.), 1.);
+        add(&mut quadtree, 20, Vec2::splat(-75.), 1.);
+        add(&mut quadtree, 100, Vec2::splat(-87.5), 1.);
+        // and these straddle the root's center, which values_per_node doesn't count
+        add(&mut quadtree, 7, Vec2::ZERO, 1.);
+
+        let stats = QuadtreeStats::calculate(&quadtree);
+        // four children at each of the 7 levels below the root
+        assert_eq!(stats.num_nodes, 28);
+        assert_eq!(stats.values_per_node, vec![24, 1, 1, 1, 1]);
+        // three leaves beside the split node at every depth, and all four at the bottom
+        assert_eq!(stats.leaf_depths, vec![0, 3, 3, 3, 3, 3, 3, 4]);
+        assert_eq!(stats.values_per_node.iter().sum::<usize>(), stats.num_nodes);
+
+        // an unsplit root has no nodes to count, but it is a leaf
+        let mut quadtree = Quadtree::empty(WORLD);
+        quadtree.add(TestValue::new(0, Vec2::ZERO, Vec2::ONE));
+        let stats = QuadtreeStats::calculate(&quadtree);
+        assert_eq!(stats.values_per_node, vec![0; 5]);
+        let mut root_only = vec![0; MAX_DEPTH + 1];
+        root_only[0] = 1;
+        assert_eq!(stats.leaf_depths, root_only);
+    }
 }
@@ -1,7 +1,29 @@
-use std::hash::Hash;
+use bevy::{prelude::Vec2, sprite::Rect};
 
-use bevy::sprite::Rect;
-
-pub trait QuadtreeValue: PartialEq + Eq + Hash + Clone {
+/// Anything that can be stored in a [`Quadtree`](super::quadtree::Quadtree). Equality
+/// identifies a value when it is moved or deleted; the default `HashSet` node storage also
+/// needs `Eq + Hash`, [`VecQuadtree`](super::vec_quadtree::VecQuadtree) only `PartialEq`.
+pub trait QuadtreeValue: PartialEq + Clone {
+    /// Bounding rect used to place the value in the tree.
     fn get_rect(&self) -> &Rect;
+
+    /// Midpoint of the value's rect, used by point-based (radius/nearest) queries.
+    fn get_center(&self) -> Vec2 {
+        let rect = self.get_rect();
+        (rect.min + rect.max) / 2.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Vec2;
+
+    use super::QuadtreeValue;
+    use crate::util::quadtree::test_support::TestValue;
+
+    #[test]
+    fn default_center_is_rect_midpoint() {
+        let value = TestValue::new(0, Vec2::new(-3., 1.), Vec2::new(4., 10.));
+        assert_eq!(value.get_center(), Vec2::new(-1., 6.));
+    }
 }
@@ -0,0 +1,59 @@
+use std::mem;
+
+/// Reusable allocation for the results of [`Quadtree::values_intersecting_into`]. Between
+/// queries it holds no values, only the capacity, so it can live in a thread-local while each
+/// query's results borrow the tree for as long as that query needs.
+///
+/// [`QueryScratch::take`] an empty `Vec`, fill it, and [`QueryScratch::restore`] it once the
+/// results are no longer needed; the next `take` starts with the capacity it grew to.
+///
+/// [`Quadtree::values_intersecting_into`]: super::quadtree::Quadtree::values_intersecting_into
+pub struct QueryScratch<T: 'static> {
+    // always empty, 'static only so the scratch doesn't borrow from any one tree
+    spare: Vec<&'static T>,
+}
+
+impl<T: 'static> QueryScratch<T> {
+    pub fn new() -> Self {
+        QueryScratch { spare: vec![] }
+    }
+
+    /// Empty buffer for the next query's results.
+    pub fn take<'a>(&mut self) -> Vec<&'a T> {
+        mem::take(&mut self.spare)
+    }
+
+    /// Hands `results` back, keeping their allocation for the next [`QueryScratch::take`].
+    pub fn restore(&mut self, mut results: Vec<&T>) {
+        results.clear();
+        // an empty Vec can take on any lifetime, and collecting into an element of the same
+        // layout reuses the allocation instead of making a new one
+        self.spare = results.into_iter().map(|_| unreachable!()).collect();
+    }
+}
+
+impl<T: 'static> Default for QueryScratch<T> {
+    fn default() -> Self {
+        QueryScratch::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restored_results_keep_their_allocation() {
+        let values: Vec<u32> = (0..100).collect();
+        let mut scratch = QueryScratch::new();
+        let mut results = scratch.take();
+        results.extend(values.iter());
+        let (pointer, capacity) = (results.as_ptr() as usize, results.capacity());
+        scratch.restore(results);
+
+        let results: Vec<&u32> = scratch.take();
+        assert!(results.is_empty());
+        assert_eq!(results.as_ptr() as usize, pointer);
+        assert_eq!(results.capacity(), capacity);
+    }
+}
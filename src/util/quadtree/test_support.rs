@@ -0,0 +1,44 @@
+use std::hash::{Hash, Hasher};
+
+use bevy::{prelude::Vec2, sprite::Rect};
+
+use super::quadtree_value::QuadtreeValue;
+
+// value identified by id alone, so a moved copy still matches the stored one
+#[derive(Clone, Debug)]
+pub struct TestValue {
+    pub id: u32,
+    pub rect: Rect,
+}
+
+impl TestValue {
+    pub fn new(id: u32, min: Vec2, size: Vec2) -> Self {
+        TestValue {
+            id,
+            rect: Rect {
+                min,
+                max: min + size,
+            },
+        }
+    }
+}
+
+impl PartialEq for TestValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for TestValue {}
+
+impl Hash for TestValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl QuadtreeValue for TestValue {
+    fn get_rect(&self) -> &Rect {
+        &self.rect
+    }
+}
@@ -0,0 +1,85 @@
+use super::{quadtree::Quadtree, quadtree_node::QuadtreeNode};
+
+/// Quadtree whose nodes keep their values in a `Vec` instead of a `HashSet`, for value types
+/// that are only `PartialEq`. It is the same tree with the same placement rules, only finding a
+/// value within a node is a linear scan. Create one with [`Quadtree::with_threshold`], e.g.
+/// with [`constant_threshold`](super::constant_threshold).
+pub type VecQuadtree<T> = Quadtree<T, Vec<T>>;
+
+/// Node of a [`VecQuadtree`].
+pub type VecQuadtreeNode<T> = QuadtreeNode<T, Vec<T>>;
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::Vec2, sprite::Rect};
+
+    use super::*;
+    use crate::util::quadtree::{constant_threshold, quadtree_value::QuadtreeValue, THRESHOLD};
+
+    // f32 fields rule out Eq and Hash
+    #[derive(Clone, Debug)]
+    struct Reading {
+        level: f32,
+        rect: Rect,
+    }
+
+    impl PartialEq for Reading {
+        fn eq(&self, other: &Self) -> bool {
+            self.level == other.level
+                && self.rect.min == other.rect.min
+                && self.rect.max == other.rect.max
+        }
+    }
+
+    impl Reading {
+        fn at(level: f32, min: Vec2) -> Self {
+            Reading {
+                level,
+                rect: Rect {
+                    min,
+                    max: min + Vec2::ONE,
+                },
+            }
+        }
+    }
+
+    impl QuadtreeValue for Reading {
+        fn get_rect(&self) -> &Rect {
+            &self.rect
+        }
+    }
+
+    const WORLD: Rect = Rect {
+        min: Vec2::new(-100., -100.),
+        max: Vec2::new(100., 100.),
+    };
+
+    #[test]
+    fn partial_eq_values_can_be_added_queried_and_deleted() {
+        let mut quadtree = VecQuadtree::with_threshold(WORLD, constant_threshold);
+        let readings: Vec<Reading> = (0..THRESHOLD * 2)
+            .map(|i| {
+                let position = Vec2::new((i % 32) as f32, (i / 32) as f32) * 6. - 95.;
+                Reading::at(i as f32 / 2., position)
+            })
+            .collect();
+        quadtree.extend(readings.clone());
+        assert_eq!(quadtree.len(), readings.len());
+        assert!(!quadtree.root.is_leaf());
+        // stored readings aren't stored or counted twice
+        for reading in &readings {
+            assert!(!quadtree.insert_unique(reading.clone()));
+        }
+        assert_eq!(quadtree.len(), readings.len());
+        quadtree.validate();
+        for reading in &readings {
+            assert!(quadtree.query_value(reading).is_some());
+            assert_eq!(quadtree.values_intersecting(&reading.rect), vec![reading]);
+        }
+        let near_origin = quadtree.query_radius(readings[0].get_center(), 1.);
+        assert_eq!(near_origin, vec![&readings[0]]);
+        assert_eq!(quadtree.delete(&readings[3]), Some(readings[3].clone()));
+        assert!(quadtree.query_value(&readings[3]).is_none());
+        assert_eq!(quadtree.len(), readings.len() - 1);
+    }
+}
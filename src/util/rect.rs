@@ -1,41 +1,60 @@
 use bevy::{prelude::*, sprite::Rect};
 
+/// Splits `rect` into its four quadrants, in the order bottom-left, bottom-right, top-left,
+/// top-right. They share edges at the center, so together they tile `rect` exactly. Both axes
+/// are halved, so children keep the parent's aspect ratio and a 10:1 world stays 10:1 all the
+/// way down to [`MAX_DEPTH`](super::quadtree::MAX_DEPTH).
 pub fn partition_rect(rect: &Rect) -> Vec<Rect> {
-    let start = rect.min;
-    let diag = rect.max - rect.min;
-    let width = diag.project_onto(Vec2::X);
-    let height = diag.project_onto(Vec2::Y);
-    let half_width = width / 2.;
-    let half_height = height / 2.;
-    let half_diag = diag / 2.;
-    let center = start + half_diag;
-    let end = rect.max;
+    let center = (rect.min + rect.max) / 2.;
     vec![
         Rect {
-            min: start.clone(),
-            max: center.clone(),
+            min: rect.min,
+            max: center,
         },
         Rect {
-            min: start + half_width,
-            max: center + half_width,
+            min: Vec2::new(center.x, rect.min.y),
+            max: Vec2::new(rect.max.x, center.y),
         },
         Rect {
-            min: start + half_height,
-            max: center + half_height,
+            min: Vec2::new(rect.min.x, center.y),
+            max: Vec2::new(center.x, rect.max.y),
         },
         Rect {
-            min: center.clone(),
-            max: end.clone(),
+            min: center,
+            max: rect.max,
         },
     ]
 }
 
+/// Index into [`partition_rect`]'s quadrants of the one `point` falls in. Points on a shared
+/// edge go to the right and/or top quadrant.
+pub fn quadrant_index(rect: &Rect, point: Vec2) -> usize {
+    let center = (rect.min + rect.max) / 2.;
+    (point.x >= center.x) as usize + 2 * (point.y >= center.y) as usize
+}
+
+/// Rect with its min corner at the transform's translation and the scale as its size.
 pub fn transform_to_rect(transform: &Transform) -> Rect {
     let min = transform.translation.truncate();
     let max = min + transform.scale.truncate();
     Rect { min, max }
 }
 
+/// Rect centered on the transform's translation with the scale as its size.
+pub fn transform_to_centered_rect(transform: &Transform) -> Rect {
+    centered_rect(transform.translation.truncate(), transform.scale.truncate())
+}
+
+/// Rect of the given `size` centered on `center`.
+pub fn centered_rect(center: Vec2, size: Vec2) -> Rect {
+    let half_size = size / 2.;
+    Rect {
+        min: center - half_size,
+        max: center + half_size,
+    }
+}
+
+/// Scales `rect` by `scale_factor` on each axis, keeping its center in place.
 pub fn magnify_rect(rect: &Rect, scale_factor: Vec2) -> Rect {
     let half_current_scale = (rect.max - rect.min)/2.;
     let mid_point = rect.min + half_current_scale;
@@ -48,10 +67,395 @@ pub fn magnify_rect(rect: &Rect, scale_factor: Vec2) -> Rect {
     Rect { min, max }
 }
 
+/// Like [`magnify_rect`], but `anchor` stays put instead of the center, e.g. a boid's nose for
+/// a detection zone that reaches further ahead than behind. Factors must be positive.
+pub fn scale_rect_about(rect: &Rect, scale_factor: Vec2, anchor: Vec2) -> Rect {
+    Rect {
+        min: anchor + (rect.min - anchor) * scale_factor,
+        max: anchor + (rect.max - anchor) * scale_factor,
+    }
+}
+
+/// Whether `point` lies strictly inside `rect`; points on an edge are outside.
 pub fn rect_contains_point(rect: &Rect, point: &Vec2) -> bool {
     rect.min.x < point.x && point.x < rect.max.x && rect.min.y < point.y && point.y < rect.max.y
 }
 
+/// Whether `other` lies strictly inside `rect`, not touching any of its edges.
 pub fn rect_contains_rect(rect: &Rect, other: &Rect) -> bool {
     rect_contains_point(rect, &other.min) && rect_contains_point(rect, &other.max)
 }
+
+/// Whether the rects overlap. Rects that only share an edge or a corner don't, see
+/// [`rect_touches_rect`].
+pub fn rect_intersects_rect(rect: &Rect, other: &Rect) -> bool {
+    rect.min.x < other.max.x
+        && other.min.x < rect.max.x
+        && rect.min.y < other.max.y
+        && other.min.y < rect.max.y
+}
+
+/// Whether the rects meet along part of an edge or at a corner without overlapping, such as
+/// two neighboring quadtree leaves.
+pub fn rect_touches_rect(rect: &Rect, other: &Rect) -> bool {
+    rect.min.x <= other.max.x
+        && other.min.x <= rect.max.x
+        && rect.min.y <= other.max.y
+        && other.min.y <= rect.max.y
+        && !rect_intersects_rect(rect, other)
+}
+
+/// Whether two circles overlap. Circles touching at a single point don't, matching
+/// [`rect_intersects_rect`].
+pub fn circles_overlap(center: Vec2, radius: f32, other_center: Vec2, other_radius: f32) -> bool {
+    let reach = radius + other_radius;
+    center.distance_squared(other_center) < reach * reach
+}
+
+/// Whether the segment from `start` to `end` touches `rect`, edges included.
+pub fn segment_intersects_rect(start: Vec2, end: Vec2, rect: &Rect) -> bool {
+    // clip the segment against one axis at a time
+    let delta = end - start;
+    let (mut t_enter, mut t_exit) = (0f32, 1f32);
+    for (origin, direction, min, max) in [
+        (start.x, delta.x, rect.min.x, rect.max.x),
+        (start.y, delta.y, rect.min.y, rect.max.y),
+    ] {
+        if direction == 0. {
+            // parallel to this axis' edges, so it has to start between them
+            if origin < min || origin > max {
+                return false;
+            }
+            continue;
+        }
+        let t_min = (min - origin) / direction;
+        let t_max = (max - origin) / direction;
+        t_enter = t_enter.max(t_min.min(t_max));
+        t_exit = t_exit.min(t_min.max(t_max));
+        if t_enter > t_exit {
+            return false;
+        }
+    }
+    true
+}
+
+/// Smallest rect containing both `a` and `b`.
+pub fn rect_union(a: &Rect, b: &Rect) -> Rect {
+    Rect {
+        min: a.min.min(b.min),
+        max: a.max.max(b.max),
+    }
+}
+
+/// Centers of a `cols` by `rows` grid of equal cells tiling `rect`, column by column starting
+/// at `rect.min`, so point `i` is in column `i / rows` and row `i % rows`.
+pub fn grid_points(rect: &Rect, cols: usize, rows: usize) -> impl Iterator<Item = Vec2> {
+    let min = rect.min;
+    let cell = (rect.max - rect.min) / Vec2::new(cols as f32, rows as f32);
+    (0..cols).flat_map(move |col| {
+        (0..rows).map(move |row| min + (Vec2::new(col as f32, row as f32) + 0.5) * cell)
+    })
+}
+
+/// Smallest rect containing every rect, `None` if there are none.
+pub fn rect_union_all<'a>(rects: impl IntoIterator<Item = &'a Rect>) -> Option<Rect> {
+    rects.into_iter().fold(None, |union, rect| match union {
+        Some(union) => Some(rect_union(&union, rect)),
+        None => Some(*rect),
+    })
+}
+
+/// Z-order position of `point` within `bounds`, interleaving 32 bits of each axis with x in the
+/// lower bit of every pair. Sorting by it visits [`partition_rect`]'s quadrants in order at
+/// every level, so nearby points end up close together. Points outside `bounds` are clamped
+/// onto its edge.
+pub fn morton_code(point: Vec2, bounds: Rect) -> u64 {
+    let size = (bounds.max - bounds.min).max(Vec2::splat(f32::EPSILON));
+    let unit = ((point - bounds.min) / size).clamp(Vec2::ZERO, Vec2::ONE);
+    // f64 so the top of the range doesn't round past u32::MAX
+    let cell = |t: f32| (t as f64 * u32::MAX as f64) as u32;
+    spread_bits(cell(unit.x)) | (spread_bits(cell(unit.y)) << 1)
+}
+
+/* Internal-only Functions */
+
+// moves bit i of value to bit 2i
+fn spread_bits(value: u32) -> u64 {
+    let mut x = value as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    x = (x | (x << 1)) & 0x5555_5555_5555_5555;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(min: (f32, f32), max: (f32, f32)) -> Rect {
+        Rect {
+            min: Vec2::new(min.0, min.1),
+            max: Vec2::new(max.0, max.1),
+        }
+    }
+
+    fn corners(rect: Rect) -> (Vec2, Vec2) {
+        (rect.min, rect.max)
+    }
+
+    #[test]
+    fn union_spans_disjoint_rects_and_is_a_no_op_on_itself() {
+        let a = rect((-10., -5.), (-2., 3.));
+        let b = rect((4., 6.), (9., 12.));
+        assert_eq!(
+            corners(rect_union(&a, &b)),
+            corners(rect((-10., -5.), (9., 12.)))
+        );
+        assert_eq!(corners(rect_union(&a, &b)), corners(rect_union(&b, &a)));
+        assert_eq!(corners(rect_union(&a, &a)), corners(a));
+
+        let c = rect((0., -20.), (1., -19.));
+        assert_eq!(
+            corners(rect_union_all([&a, &b, &c]).unwrap()),
+            corners(rect((-10., -20.), (9., 12.)))
+        );
+        assert_eq!(corners(rect_union_all([&b]).unwrap()), corners(b));
+        assert!(rect_union_all([]).is_none());
+    }
+
+    fn area(rect: &Rect) -> f32 {
+        let size = (rect.max - rect.min).max(Vec2::ZERO);
+        size.x * size.y
+    }
+
+    #[test]
+    fn partition_tiles_the_parent_into_equal_quarters() {
+        for parent in [
+            rect((-8., -8.), (8., 8.)),
+            rect((-30., -20.), (-10., -4.)),
+            rect((5., 2.), (35., 12.)),
+        ] {
+            let quarters = partition_rect(&parent);
+            assert_eq!(quarters.len(), 4);
+            for (i, quarter) in quarters.iter().enumerate() {
+                assert_eq!(area(quarter), area(&parent) / 4.);
+                // same aspect ratio as the parent
+                assert_eq!((quarter.max - quarter.min) * 2., parent.max - parent.min);
+                assert!(quarter.min.cmpge(parent.min).all() && quarter.max.cmple(parent.max).all());
+                for other in &quarters[i + 1..] {
+                    let overlap = Rect {
+                        min: quarter.min.max(other.min),
+                        max: quarter.max.min(other.max),
+                    };
+                    assert_eq!(area(&overlap), 0.);
+                }
+            }
+            assert_eq!(corners(rect_union_all(&quarters).unwrap()), corners(parent));
+        }
+    }
+
+    #[test]
+    fn quadrant_index_matches_partition_order_and_breaks_ties_up_and_right() {
+        let parent = rect((-10., -20.), (30., 20.));
+        for (index, quarter) in partition_rect(&parent).iter().enumerate() {
+            let center = (quarter.min + quarter.max) / 2.;
+            assert_eq!(quadrant_index(&parent, center), index);
+        }
+        let mid = Vec2::new(10., 0.);
+        assert_eq!(quadrant_index(&parent, mid), 3);
+        assert_eq!(quadrant_index(&parent, Vec2::new(10., -5.)), 1);
+        assert_eq!(quadrant_index(&parent, Vec2::new(0., 0.)), 2);
+        assert_eq!(quadrant_index(&parent, Vec2::new(9.9, -0.1)), 0);
+    }
+
+    #[test]
+    fn wide_rects_keep_their_aspect_ratio_and_coverage_at_every_depth() {
+        use crate::util::quadtree::MAX_DEPTH;
+
+        let root = rect((-1000., -100.), (1000., 100.));
+        let mut level = vec![root];
+        for _ in 0..MAX_DEPTH {
+            let mut next = vec![];
+            for parent in &level {
+                let quarters = partition_rect(parent);
+                for (index, quarter) in quarters.iter().enumerate() {
+                    let size = quarter.max - quarter.min;
+                    assert_eq!(size.x / size.y, 10.);
+                    let center = (quarter.min + quarter.max) / 2.;
+                    assert_eq!(quadrant_index(parent, center), index);
+                }
+                assert_eq!(
+                    corners(rect_union_all(&quarters).unwrap()),
+                    corners(*parent)
+                );
+                next.extend(quarters);
+            }
+            let covered: f32 = next.iter().map(area).sum();
+            assert!((covered - area(&root)).abs() < 1e-3 * area(&root));
+            level = next;
+        }
+        assert_eq!(level.len(), 4usize.pow(MAX_DEPTH as u32));
+    }
+
+    #[test]
+    fn inscribed_circles_overlap_less_than_their_rects() {
+        // unit circles inscribed in 2x2 boxes, the other box offset by the given amount
+        let both_overlap = |offset: Vec2| {
+            let a = centered_rect(Vec2::ZERO, Vec2::splat(2.));
+            let b = centered_rect(offset, Vec2::splat(2.));
+            (
+                rect_intersects_rect(&a, &b),
+                circles_overlap(Vec2::ZERO, 1., offset, 1.),
+            )
+        };
+        // the box corners overlap, the circles are too far apart along the diagonal
+        assert_eq!(both_overlap(Vec2::splat(1.6)), (true, false));
+        assert_eq!(both_overlap(Vec2::new(1.6, 0.)), (true, true));
+        // touching counts as apart for both
+        assert_eq!(both_overlap(Vec2::new(2., 0.)), (false, false));
+        assert!(!circles_overlap(Vec2::ZERO, 1., Vec2::new(0., 3.), 2.));
+        assert!(circles_overlap(Vec2::ZERO, 1., Vec2::new(0., 2.9), 2.));
+    }
+
+    #[test]
+    fn scaling_about_the_center_matches_magnify_rect() {
+        let parent = rect((-6., 2.), (10., 8.));
+        let center = (parent.min + parent.max) / 2.;
+        for factor in [Vec2::splat(2.), Vec2::new(3., 0.5), Vec2::ONE] {
+            let about = corners(scale_rect_about(&parent, factor, center));
+            let (min, max) = corners(magnify_rect(&parent, factor));
+            assert!(about.0.abs_diff_eq(min, 1e-5) && about.1.abs_diff_eq(max, 1e-5));
+        }
+        // anchored on the right edge, the rect only grows to the left
+        let anchor = Vec2::new(10., 5.);
+        assert_eq!(
+            corners(scale_rect_about(&parent, Vec2::splat(2.), anchor)),
+            corners(rect((-22., -1.), (10., 11.)))
+        );
+    }
+
+    #[test]
+    fn segments_intersect_rects_they_cross_or_touch() {
+        let rect = Rect {
+            min: Vec2::new(0., 0.),
+            max: Vec2::new(2., 2.),
+        };
+        // straight through, diagonally, and ending inside
+        assert!(segment_intersects_rect(
+            Vec2::new(-1., 1.),
+            Vec2::new(3., 1.),
+            &rect
+        ));
+        assert!(segment_intersects_rect(
+            Vec2::new(-1., -1.),
+            Vec2::new(3., 3.),
+            &rect
+        ));
+        assert!(segment_intersects_rect(
+            Vec2::new(1., 5.),
+            Vec2::new(1., 1.),
+            &rect
+        ));
+        // touching an edge counts
+        assert!(segment_intersects_rect(
+            Vec2::new(-1., 2.),
+            Vec2::new(3., 2.),
+            &rect
+        ));
+        // parallel outside, stopping short, and passing a corner
+        assert!(!segment_intersects_rect(
+            Vec2::new(-1., 3.),
+            Vec2::new(3., 3.),
+            &rect
+        ));
+        assert!(!segment_intersects_rect(
+            Vec2::new(-3., 1.),
+            Vec2::new(-1., 1.),
+            &rect
+        ));
+        assert!(!segment_intersects_rect(
+            Vec2::new(-1., 2.),
+            Vec2::new(1., 4.),
+            &rect
+        ));
+    }
+
+    #[test]
+    fn rects_touch_along_an_edge_or_at_a_corner_but_not_when_overlapping() {
+        let rect = Rect {
+            min: Vec2::new(0., 0.),
+            max: Vec2::new(2., 2.),
+        };
+        let at = |x: f32, y: f32| Rect {
+            min: Vec2::new(x, y),
+            max: Vec2::new(x + 2., y + 2.),
+        };
+        assert!(rect_touches_rect(&rect, &at(2., 1.)));
+        assert!(rect_touches_rect(&rect, &at(-2., -2.)));
+        assert!(!rect_touches_rect(&rect, &at(1., 1.)));
+        assert!(!rect_touches_rect(&rect, &at(2.5, 0.)));
+    }
+
+    #[test]
+    fn morton_order_fills_each_quadrant_before_the_next() {
+        let bounds = Rect {
+            min: Vec2::new(-80., -80.),
+            max: Vec2::new(80., 80.),
+        };
+        // centers of an 8x8 grid of cells
+        let mut points: Vec<Vec2> = (0..64)
+            .map(|i| Vec2::new((i % 8) as f32, (i / 8) as f32) * 20. - 70.)
+            .collect();
+        points.sort_unstable_by_key(|point| morton_code(*point, bounds));
+        // 16 points per quadrant, 4 per quadrant of a quadrant, in partition_rect order
+        for (quadrant, rect) in partition_rect(&bounds).iter().enumerate() {
+            let block = &points[quadrant * 16..(quadrant + 1) * 16];
+            assert!(block.iter().all(|point| rect_contains_point(rect, point)));
+            for (sub_quadrant, sub_rect) in partition_rect(rect).iter().enumerate() {
+                let sub_block = &block[sub_quadrant * 4..(sub_quadrant + 1) * 4];
+                assert!(sub_block
+                    .iter()
+                    .all(|point| rect_contains_point(sub_rect, point)));
+            }
+        }
+        // moving right or up along a row or column always raises the code
+        for i in 0..7 {
+            let step = i as f32 * 20. - 70.;
+            let code = |x: f32, y: f32| morton_code(Vec2::new(x, y), bounds);
+            assert!(code(step, 10.) < code(step + 20., 10.));
+            assert!(code(-30., step) < code(-30., step + 20.));
+        }
+        // the corners span the whole range and outside points are clamped
+        assert_eq!(morton_code(bounds.min, bounds), 0);
+        assert_eq!(morton_code(bounds.max, bounds), u64::MAX);
+        assert_eq!(morton_code(Vec2::splat(-500.), bounds), 0);
+    }
+
+    #[test]
+    fn grid_points_are_cell_centers_column_by_column() {
+        let rect = Rect {
+            min: Vec2::new(-10., 0.),
+            max: Vec2::new(10., 40.),
+        };
+        assert_eq!(grid_points(&rect, 0, 3).count(), 0);
+        assert_eq!(grid_points(&rect, 3, 0).count(), 0);
+        assert_eq!(
+            grid_points(&rect, 2, 2).collect::<Vec<_>>(),
+            vec![
+                Vec2::new(-5., 10.),
+                Vec2::new(-5., 30.),
+                Vec2::new(5., 10.),
+                Vec2::new(5., 30.),
+            ]
+        );
+        // point i sits in column i / rows and row i % rows
+        let points: Vec<Vec2> = grid_points(&rect, 4, 5).collect();
+        assert_eq!(points.len(), 20);
+        for (i, point) in points.iter().enumerate() {
+            let expected = Vec2::new(-7.5 + (i / 5) as f32 * 5., 4. + (i % 5) as f32 * 8.);
+            assert_eq!(*point, expected);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use bevy::sprite::Rect;
+
+use super::rect::partition_rect;
+
+// a region a spatial tree can split into CHILD_COUNT equal sub-regions, which fixes the tree's
+// branching factor: Rect gives a quadtree, a 3D box split into octants would give an octree
+pub trait Partition: Sized {
+    const CHILD_COUNT: usize;
+
+    // CHILD_COUNT sub-regions that tile self exactly, always in the same order
+    fn partition(&self) -> Vec<Self>;
+}
+
+impl Partition for Rect {
+    const CHILD_COUNT: usize = 4;
+
+    fn partition(&self) -> Vec<Self> {
+        partition_rect(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Vec2;
+
+    use super::*;
+
+    #[test]
+    fn rects_partition_into_four_tiling_sub_rects() {
+        let parent = Rect {
+            min: Vec2::new(-12., 3.),
+            max: Vec2::new(20., 11.),
+        };
+        let children = parent.partition();
+        assert_eq!(children.len(), Rect::CHILD_COUNT);
+        assert_eq!(Rect::CHILD_COUNT, 4);
+        let child_size = (parent.max - parent.min) / 2.;
+        for (i, child) in children.iter().enumerate() {
+            assert_eq!(child.max - child.min, child_size);
+            // half-size children at the four half-size offsets tile the parent exactly; bit 0
+            // picks the right half, bit 1 the top half
+            let offset = Vec2::new((i & 1) as f32, (i >> 1) as f32) * child_size;
+            assert_eq!(child.min, parent.min + offset);
+        }
+    }
+}